@@ -0,0 +1,306 @@
+//! The library interface: one edit of in-memory content, without prompts, terminal output
+//! or file access. [`run`], [`EditRequest`] and [`EditOutcome`] are also at the crate root.
+
+use crate::azure;
+use crate::console;
+use crate::edit::{self, Endpoint, GenerationOptions, Retries, DEFAULT_RETRY_ON_MALFORMED, DEFAULT_TEMPERATURE, DEFAULT_TOP_P};
+use crate::error::CodersError;
+use crate::http;
+use crate::merge::{combine, match_indentation, Change as LineChange, ChangeType, EditMode};
+use crate::slim;
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+/// The provider an [`EditRequest`] is sent to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Provider {
+    Hyperbolic,
+    OpenRouter,
+    Gemini,
+    Groq,
+    /// Azure OpenAI: `base_url` is the resource endpoint and `model` the deployment name.
+    Azure,
+    Cohere,
+}
+
+impl From<Provider> for crate::Provider {
+    fn from(provider: Provider) -> crate::Provider {
+        match provider {
+            Provider::Hyperbolic => crate::Provider::Hyperbolic,
+            Provider::OpenRouter => crate::Provider::OpenRouter,
+            Provider::Gemini => crate::Provider::Gemini,
+            Provider::Groq => crate::Provider::Groq,
+            Provider::Azure => crate::Provider::Azure,
+            Provider::Cohere => crate::Provider::Cohere,
+        }
+    }
+}
+
+/// Sampling parameters. Fields left at `None` get the CLI's defaults.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Params {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<u64>,
+    pub stop: Vec<String>,
+}
+
+/// How the model's code is combined with [`EditRequest::content`], as the CLI's `--mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Mode {
+    /// Merge the code into the content line by line.
+    #[default]
+    Merge,
+    /// Add the code after the content, leaving every line of it alone.
+    Append,
+    /// Take the code as the whole new content, as `--whole-file-diff` does.
+    Replace,
+}
+
+impl From<Mode> for EditMode {
+    fn from(mode: Mode) -> EditMode {
+        match mode {
+            Mode::Merge => EditMode::Merge,
+            Mode::Append => EditMode::Append,
+            Mode::Replace => EditMode::Replace,
+        }
+    }
+}
+
+/// What to edit and how. Build one with [`EditRequest::new`] and set the optional fields
+/// afterwards.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct EditRequest {
+    /// The file content to edit.
+    pub content: String,
+    /// The language as the CLI names it from a file's extension, e.g. `rust`, `python` or
+    /// `typescript`. It goes into the prompt and picks the code block out of the answer.
+    pub language: String,
+    /// The instruction, e.g. "Add error handling to parse_config".
+    pub prompt: String,
+    pub provider: Provider,
+    pub model: String,
+    pub api_key: String,
+    /// The provider's API base URL; the built-in one when `None`. Required for Azure.
+    pub base_url: Option<String>,
+    pub params: Params,
+    pub mode: Mode,
+    /// Lead-in and sign-off phrases to drop around the code besides the built-in ones, like
+    /// `strip_phrases` in the CLI's config file.
+    pub strip_phrases: Vec<String>,
+}
+
+impl EditRequest {
+    pub fn new(content: impl Into<String>, language: impl Into<String>, prompt: impl Into<String>, provider: Provider, model: impl Into<String>, api_key: impl Into<String>) -> EditRequest {
+        EditRequest {
+            content: content.into(),
+            language: language.into(),
+            prompt: prompt.into(),
+            provider,
+            model: model.into(),
+            api_key: api_key.into(),
+            base_url: None,
+            params: Params::default(),
+            mode: Mode::default(),
+            strip_phrases: Vec::new(),
+        }
+    }
+}
+
+/// The result of [`run`]. Nothing has been written anywhere.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct EditOutcome {
+    /// The content with the model's change merged in, as the CLI would write it.
+    pub content: String,
+    /// The changed lines, as the CLI's diff shows them.
+    pub changes: Vec<Change>,
+    pub usage: Usage,
+    /// From sending the request to having the whole answer, retries included.
+    pub latency: Duration,
+}
+
+/// One changed line of an [`EditOutcome`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// 1-based line number: in the original for deletions, in the new content otherwise.
+    pub line: usize,
+    pub content: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChangeKind {
+    Insert,
+    Delete,
+    Modify,
+}
+
+/// Token counts of the request and its answer, estimated at ~4 characters per token like
+/// the CLI's history, since not every provider reports them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Sends one edit to the provider and merges the answer into `request.content`, the way
+/// the CLI does for a file before showing its diff: the request is built and sent, and the
+/// code taken out of the answer, by the same code as the CLI's, though it isn't added to the
+/// request history. Provider failures keep their
+/// [`Error`](crate::Error) class, which `downcast_ref` recovers. An answer without any
+/// content is an [`Error::ResponseParse`](crate::Error::ResponseParse).
+pub async fn run(request: EditRequest) -> Result<EditOutcome> {
+    let http = http::Transport::new(None, None, Vec::new(), Vec::new());
+    console::silent(edit(request, &http)).await
+}
+
+async fn edit(request: EditRequest, http: &http::Transport) -> Result<EditOutcome> {
+    let provider = crate::Provider::from(request.provider);
+    let base_url = request.base_url.as_deref().unwrap_or(provider.default_base_url()).trim_end_matches('/').to_string();
+    let azure = match provider {
+        crate::Provider::Azure => Some(azure::Deployment::resolve(request.base_url.as_deref(), Some(&request.model), None, None)?),
+        _ => None,
+    };
+    let options = options(&request.params);
+    let endpoint = Endpoint { provider, base_url: &base_url, api_key: &request.api_key, azure: azure.as_ref(), options: &options };
+
+    let context = edit::edit_context(&request.prompt, &request.content);
+    let retries = Retries { empty: 0, malformed: DEFAULT_RETRY_ON_MALFORMED, verbose: false, deadline: None };
+    let recording = edit::Recording::default();
+    let started = Instant::now();
+    let answer = edit::with_retries(provider, retries, || edit::send(http, &endpoint, &request.model, &context, &request.language, &recording)).await?
+        .ok_or_else(|| CodersError::ResponseParse(format!("{} returned no content", provider.name())))?;
+    let latency = started.elapsed();
+
+    let extraction = edit::Extraction { language: &request.language, strip_phrases: &request.strip_phrases, rationale: false, verbatim: false, confidence: false };
+    let code = match_indentation("", &request.content, &edit::extract(&answer, &extraction).code, false);
+    let (content, changes) = combine(request.mode.into(), &request.content, &code);
+    Ok(EditOutcome {
+        content,
+        changes: changes.into_iter().map(Change::from).collect(),
+        usage: Usage {
+            prompt_tokens: slim::estimate_tokens(&context) as u64,
+            completion_tokens: slim::estimate_tokens(&answer) as u64,
+        },
+        latency,
+    })
+}
+
+// The CLI's defaults, without anything from its flags or config file.
+fn options(params: &Params) -> GenerationOptions {
+    GenerationOptions {
+        temperature: params.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+        top_p: params.top_p.unwrap_or(DEFAULT_TOP_P),
+        max_tokens: params.max_tokens,
+        seed: params.seed,
+        stop: params.stop.clone(),
+        frequency_penalty: None,
+        presence_penalty: None,
+        request_confidence: false,
+        request_rationale: false,
+        stream: false,
+        concise: false,
+        tools: false,
+        language_prompts: Default::default(),
+        degraded: false,
+        image: None,
+        messages: None,
+    }
+}
+
+impl From<LineChange> for Change {
+    fn from(change: LineChange) -> Change {
+        let kind = match change.change_type {
+            ChangeType::Insert => ChangeKind::Insert,
+            ChangeType::Delete => ChangeKind::Delete,
+            ChangeType::Modify => ChangeKind::Modify,
+        };
+        Change { kind, line: change.line_number, content: change.content }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(content: &str, prompt: &str) -> EditRequest {
+        EditRequest::new(content, "rust", prompt, Provider::Groq, "llama-3.1-8b-instant", "gsk-test")
+    }
+
+    // Runs `request` as `run` does, against a replay directory that answers whatever it sends
+    // with `status` and `body`. Also returns the request body that was sent.
+    async fn replay(request: EditRequest, status: u16, body: &str) -> (Result<EditOutcome>, serde_json::Value) {
+        let dir = tempfile::tempdir().unwrap();
+        let http = http::Transport::new(None, Some(dir.path().to_path_buf()), Vec::new(), Vec::new());
+        // Without a recording nothing is answered, but what would have been sent is captured
+        let (_, sent) = http::capture(console::silent(edit(request.clone(), &http))).await;
+        let sent = sent.into_iter().next().expect("a request was sent");
+        http::record(dir.path(), &sent.url, &sent.body, status, body);
+        (console::silent(edit(request, &http)).await, sent.body)
+    }
+
+    fn answer(content: &str) -> String {
+        json!({ "choices": [{ "message": { "role": "assistant", "content": content } }] }).to_string()
+    }
+
+    #[tokio::test]
+    async fn run_merges_the_answer_into_the_content() {
+        let content = "fn add(a: i32, b: i32) -> i32 {\n    a - b\n}";
+        let (outcome, sent) = replay(request(content, "Fix add"), 200, &answer("Here's the fix:\n```rust\nfn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n```")).await;
+
+        let outcome = outcome.unwrap();
+        assert_eq!(outcome.content, "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}");
+        assert_eq!(outcome.changes, vec![Change { kind: ChangeKind::Modify, line: 2, content: "    a + b".to_string() }]);
+        assert!(outcome.usage.prompt_tokens > 0 && outcome.usage.completion_tokens > 0);
+        assert_eq!(sent["model"], "llama-3.1-8b-instant");
+        let messages = sent["messages"].as_array().unwrap();
+        assert_eq!(messages.last().unwrap()["content"], format!("The following code is in rust. Fix add\n\n{}", content));
+    }
+
+    #[tokio::test]
+    async fn run_leaves_out_the_reasoning_and_the_strip_phrases() {
+        let mut request = request("let x = 1;", "Rename x to count");
+        request.strip_phrases = vec!["Renamed as asked".to_string()];
+        let response = answer("<think>\nThe user wants x renamed.\n</think>\n```rust\nlet count = 1;\nRenamed as asked.\n```");
+        let (outcome, _) = replay(request, 200, &response).await;
+
+        assert_eq!(outcome.unwrap().content, "let count = 1;");
+    }
+
+    #[tokio::test]
+    async fn run_appends_in_append_mode() {
+        let mut request = request("fn a() {}\n", "Add b");
+        request.mode = Mode::Append;
+        let (outcome, _) = replay(request, 200, &answer("```rust\nfn b() {}\n```")).await;
+
+        let outcome = outcome.unwrap();
+        assert!(outcome.content.starts_with("fn a() {}\n"));
+        assert!(outcome.content.trim_end().ends_with("fn b() {}"));
+        assert!(outcome.changes.iter().all(|change| change.kind == ChangeKind::Insert));
+    }
+
+    #[tokio::test]
+    async fn run_keeps_the_error_class_of_a_provider_failure() {
+        let (outcome, _) = replay(request("x\n", "Edit"), 401, r#"{"error": {"message": "Invalid API Key"}}"#).await;
+
+        let error = outcome.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(crate::Error::ApiKeyInvalid { .. })), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn run_reports_an_empty_answer() {
+        let (outcome, _) = replay(request("x\n", "Edit"), 200, &answer("")).await;
+
+        assert!(matches!(outcome.unwrap_err().downcast_ref(), Some(crate::Error::ResponseParse(_))));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::prompt;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A confirmed edit waiting to be written to disk.
+pub struct PendingWrite {
+    pub path: String,
+    pub content: String,
+    /// The change summary shown before the diff, repeated when the batch is confirmed.
+    pub summary: String,
+    /// Given to the written file instead of the ones it has (`--preserve-permissions`); a new
+    /// file otherwise gets the default ones.
+    pub permissions: Option<fs::Permissions>,
+}
+
+/// The absolute location a relative path will be written to, with symlinks and `..`
+/// resolved as far as the path exists.
+pub fn resolved_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if let Ok(resolved) = fs::canonicalize(path) {
+        return resolved;
+    }
+    // A new file: resolve the directory it will be created in
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    match (fs::canonicalize(parent), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+/// Why `path` can't be written, checked before any request is sent: the file itself is
+/// read-only, or no temporary file can be created next to it. Directories that don't exist
+/// yet are created on apply, so for those the closest existing one is tried instead.
+pub fn check_writable(path: &Path) -> Option<String> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly()) {
+        return Some(format!("{} is read-only", path.display()));
+    }
+    let mut temp = temp_path(path).ok()?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if !parent.is_dir() {
+        let existing = parent.ancestors().find(|dir| dir.is_dir()).unwrap_or(Path::new("."));
+        temp = existing.join(temp.file_name()?);
+    }
+    match fs::OpenOptions::new().write(true).create(true).truncate(true).open(&temp) {
+        Ok(_) => {
+            let _ = fs::remove_file(&temp);
+            None
+        }
+        Err(e) => Some(format!("cannot write to the directory of {}: {}", path.display(), e)),
+    }
+}
+
+/// Writes every pending edit to a synced temporary file next to its target and only renames
+/// them into place once all of them were written. If a rename fails, the files already
+/// renamed are restored from the previous content kept in memory, and the error lists what
+/// happened to each file. Content that could not be written is saved to the system temp
+/// directory so the work isn't lost.
+pub fn apply_all(writes: &[PendingWrite]) -> Result<()> {
+    let mut staged: Vec<(PathBuf, &PendingWrite, Option<Vec<u8>>)> = Vec::new();
+
+    for write in writes {
+        let target = Path::new(&write.path);
+        match previous_content(target).and_then(|backup| Ok((stage(target, write.content.as_bytes(), write.permissions.as_ref())?, backup))) {
+            Ok((temp, backup)) => staged.push((temp, write, backup)),
+            Err(e) => {
+                for (temp, _, _) in &staged {
+                    let _ = fs::remove_file(temp);
+                }
+                let rescued = rescue_all(writes);
+                return Err(e.context(format!("No files were changed because {} could not be written{}", write.path, rescued)));
+            }
+        }
+    }
+
+    for (i, (temp, write, _)) in staged.iter().enumerate() {
+        if let Err(e) = rename_with_retry(temp, Path::new(&write.path)) {
+            for (temp, _, _) in &staged[i..] {
+                let _ = fs::remove_file(temp);
+            }
+            let mut report = Vec::new();
+            for (_, applied, backup) in &staged[..i] {
+                match restore(Path::new(&applied.path), backup.as_deref()) {
+                    Ok(()) => report.push(format!("  {}  rolled back", applied.path)),
+                    Err(e) => report.push(format!("  {}  still has the new content, rollback failed: {:#}", applied.path, e)),
+                }
+            }
+            report.push(format!("  {}  failed: {}", write.path, e));
+            for (_, remaining, _) in &staged[i + 1..] {
+                report.push(format!("  {}  not written", remaining.path));
+            }
+            let rescued = rescue_all(staged.iter().map(|(_, write, _)| *write));
+            return Err(anyhow::Error::new(e).context(format!("Failed to move {} into place:\n{}{}", write.path, report.join("\n"), rescued)));
+        }
+    }
+    for (_, write, _) in &staged {
+        sync_parent(Path::new(&write.path));
+    }
+    Ok(())
+}
+
+// `None` when the file doesn't exist yet, so rolling back means removing it.
+fn previous_content(target: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(target) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to read {}", target.display()))),
+    }
+}
+
+fn restore(target: &Path, previous: Option<&[u8]>) -> Result<()> {
+    match previous {
+        Some(previous) => {
+            let temp = stage(target, previous, None)?;
+            fs::rename(&temp, target).map_err(|e| {
+                let _ = fs::remove_file(&temp);
+                anyhow::Error::new(e)
+            })
+        }
+        None => Ok(fs::remove_file(target)?),
+    }
+}
+
+// Makes the renames themselves durable. Best effort: not every platform can open a directory.
+fn sync_parent(target: &Path) {
+    let parent = target.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(dir) = fs::File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+// Windows refuses to replace a file another process has open (ERROR_SHARING_VIOLATION);
+// that is usually an editor or indexer letting go shortly, so offer to try again.
+fn rename_with_retry(temp: &Path, target: &Path) -> io::Result<()> {
+    loop {
+        match fs::rename(temp, target) {
+            Err(e) if cfg!(windows) && e.raw_os_error() == Some(32) => {
+                let question = format!("{} is in use by another program. Retry? (y/n) ", target.display());
+                if !prompt::confirm(&question).unwrap_or(false) {
+                    return Err(e);
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+// Lists where the unwritten content was saved, formatted to append to an error message.
+fn rescue_all<'a>(writes: impl IntoIterator<Item = &'a PendingWrite>) -> String {
+    let saved: Vec<String> = writes.into_iter()
+        .filter_map(|write| rescue(write).map(|path| format!("{} -> {}", write.path, path.display())))
+        .collect();
+    if saved.is_empty() {
+        String::new()
+    } else {
+        format!("; the proposed content was saved to:\n  {}", saved.join("\n  "))
+    }
+}
+
+// A new file of its own for each write, so files with the same name in a batch don't overwrite
+// each other and nothing planted in the shared temp directory is followed.
+fn rescue(write: &PendingWrite) -> Option<PathBuf> {
+    let name = Path::new(&write.path).file_name()?.to_string_lossy().into_owned();
+    let mut file = tempfile::Builder::new().prefix("coders-").suffix(&format!("-{}", name)).tempfile().ok()?;
+    file.write_all(write.content.as_bytes()).ok()?;
+    let (_, path) = file.keep().ok()?;
+    Some(path)
+}
+
+fn temp_path(target: &Path) -> Result<PathBuf> {
+    let name = target.file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {}", target.display()))?;
+    Ok(target.with_file_name(format!(".{}.coders-tmp", name.to_string_lossy())))
+}
+
+fn stage(target: &Path, content: &[u8], permissions: Option<&fs::Permissions>) -> Result<PathBuf> {
+    // A rename would happily replace a read-only file, so refuse explicitly
+    if let Some(reason) = check_writable(target) {
+        return Err(anyhow::anyhow!(reason));
+    }
+    let temp = temp_path(target)?;
+    if let Some(parent) = target.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    // Synced before the rename, so a crash can't leave a renamed but empty file behind
+    fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+    // The renamed file replaces the original, so carry its permissions over
+    let permissions = permissions.cloned().or_else(|| fs::metadata(target).ok().map(|metadata| metadata.permissions()));
+    if let Some(permissions) = permissions {
+        fs::set_permissions(&temp, permissions)?;
+    }
+    Ok(temp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(path: &Path, content: &str) -> PendingWrite {
+        PendingWrite { path: path.to_string_lossy().into_owned(), content: content.to_string(), summary: String::new(), permissions: None }
+    }
+
+    // The files in `dir`, temporary ones included.
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_read_only_file_leaves_every_file_as_it_was() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let writable = dir.path().join("writable.rs");
+        let locked = dir.path().join("locked.rs");
+        fs::write(&writable, "fn a() {}\n").unwrap();
+        fs::write(&locked, "fn b() {}\n").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o444)).unwrap();
+
+        assert_eq!(check_writable(&locked), Some(format!("{} is read-only", locked.display())));
+        let error = apply_all(&[pending(&writable, "fn a() { 1 }\n"), pending(&locked, "fn b() { 2 }\n")]).unwrap_err();
+
+        let message = format!("{:#}", error);
+        assert!(message.starts_with(&format!("No files were changed because {} could not be written", locked.display())), "{}", message);
+        assert!(message.contains("is read-only"), "{}", message);
+        assert_eq!(fs::read_to_string(&writable).unwrap(), "fn a() {}\n");
+        assert_eq!(fs::read_to_string(&locked).unwrap(), "fn b() {}\n");
+        assert_eq!(fs::metadata(&locked).unwrap().permissions().mode() & 0o777, 0o444);
+        assert_eq!(listing(dir.path()), vec!["locked.rs", "writable.rs"]);
+
+        // The proposed content isn't lost
+        let saved = rescued(&message, &locked);
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "fn b() { 2 }\n");
+        let _ = fs::remove_file(saved);
+        if let Some(saved) = rescued_path(&message, &writable) {
+            let _ = fs::remove_file(saved);
+        }
+    }
+
+    // Where the error `message` says the content for `path` was saved.
+    fn rescued(message: &str, path: &Path) -> PathBuf {
+        rescued_path(message, path).unwrap_or_else(|| panic!("{} was not rescued: {}", path.display(), message))
+    }
+
+    // The cause follows the last path on its line, after the file name.
+    fn rescued_path(message: &str, path: &Path) -> Option<PathBuf> {
+        let prefix = format!("{} -> ", path.display());
+        let suffix = format!("-{}", path.file_name()?.to_string_lossy());
+        let saved = message.lines().find_map(|line| line.trim().strip_prefix(&prefix))?;
+        Some(PathBuf::from(&saved[..saved.find(&suffix)? + suffix.len()]))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_with_the_same_name_are_rescued_separately() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a/mod.rs"), dir.path().join("b/mod.rs"));
+        for path in [&a, &b] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o444)).unwrap();
+        }
+
+        let message = format!("{:#}", apply_all(&[pending(&a, "mod a;\n"), pending(&b, "mod b;\n")]).unwrap_err());
+
+        let (rescued_a, rescued_b) = (rescued(&message, &a), rescued(&message, &b));
+        assert_ne!(rescued_a, rescued_b);
+        assert!(rescued_a.file_name().unwrap().to_string_lossy().starts_with("coders-"));
+        assert!(rescued_a.to_string_lossy().ends_with("-mod.rs"));
+        assert_eq!(fs::read_to_string(&rescued_a).unwrap(), "mod a;\n");
+        assert_eq!(fs::read_to_string(&rescued_b).unwrap(), "mod b;\n");
+        let _ = fs::remove_file(rescued_a);
+        let _ = fs::remove_file(rescued_b);
+    }
+
+    #[test]
+    fn every_write_lands_when_all_are_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("existing.rs");
+        let created = dir.path().join("new/created.rs");
+        fs::write(&existing, "old\n").unwrap();
+
+        apply_all(&[pending(&existing, "new\n"), pending(&created, "created\n")]).unwrap();
+
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(&created).unwrap(), "created\n");
+        assert_eq!(listing(dir.path()), vec!["existing.rs", "new"]);
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_executable_stays_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("run.sh");
+        fs::write(&script, "#!/bin/sh\necho old\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        apply_all(&[pending(&script, "#!/bin/sh\necho new\n")]).unwrap();
+
+        assert_eq!(fs::read_to_string(&script).unwrap(), "#!/bin/sh\necho new\n");
+        assert_eq!(mode(&script), 0o755);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserved_permissions_carry_the_executable_bit_to_the_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("run.sh");
+        fs::write(&input, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&input, fs::Permissions::from_mode(0o750)).unwrap();
+        let (preserved, plain) = (dir.path().join("preserved.sh"), dir.path().join("plain.sh"));
+
+        let write = PendingWrite { permissions: Some(fs::metadata(&input).unwrap().permissions()), ..pending(&preserved, "#!/bin/sh\n") };
+        apply_all(&[write, pending(&plain, "#!/bin/sh\n")]).unwrap();
+
+        assert_eq!(mode(&preserved), 0o750);
+        assert_eq!(mode(&plain) & 0o111, 0);
+    }
+}
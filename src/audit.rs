@@ -0,0 +1,114 @@
+use crate::history;
+use crate::http::Exchange;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever a field is renamed or removed, so tooling reading the artifacts can tell.
+const FORMAT_VERSION: u32 = 1;
+
+/// `--audit-dir`: one JSON artifact per provider request, at `<DIR>/<run>/<NNNN>.json` where
+/// `<run>` is the UTC start time and process id (e.g. `20240901T140322Z-4242`) and `NNNN`
+/// counts the run's requests from `0001`. Each artifact is written as soon as its request
+/// has finished, failed or not, and updated with the hash of the file once a change to it
+/// is written.
+pub struct Audit {
+    dir: PathBuf,
+    run: String,
+    api_key: String,
+    next: Cell<u32>,
+    // The artifacts written so far and the file each was about
+    artifacts: RefCell<Vec<(String, PathBuf)>>,
+}
+
+/// What `Audit::record` needs to know about one request besides the traffic itself.
+pub struct Request<'a> {
+    pub file: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub error: Option<String>,
+}
+
+impl Audit {
+    /// Creates the run's directory under `root`. `api_key` is replaced with `[REDACTED]`
+    /// wherever it would appear in an artifact.
+    pub fn start(root: &Path, api_key: &str) -> Result<Audit> {
+        let started = history::rfc3339(history::now()).replace(['-', ':'], "");
+        let run = format!("{}-{}", started, std::process::id());
+        let dir = root.join(&run);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the audit directory {}", dir.display()))?;
+        Ok(Audit {
+            dir,
+            run,
+            api_key: api_key.to_string(),
+            next: Cell::new(1),
+            artifacts: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Writes an artifact for every POST in `exchanges`, with the hash of `request.file` as it
+    /// is on disk now.
+    pub fn record(&self, request: &Request, exchanges: &[Exchange]) -> Result<()> {
+        let before = fs::read(request.file).ok().map(|content| sha256(&content));
+        for exchange in exchanges {
+            let sequence = self.next.get();
+            self.next.set(sequence + 1);
+            let artifact = json!({
+                "version": FORMAT_VERSION,
+                "run": self.run,
+                "sequence": sequence,
+                "timestamp": history::rfc3339(history::now()),
+                "file": request.file,
+                "file_sha256_before": before,
+                "file_sha256_after": null,
+                "written_to": null,
+                "provider": request.provider,
+                "model": request.model,
+                "host": Url::parse(&exchange.url).ok().and_then(|url| url.host_str().map(str::to_string)),
+                "url": exchange.url,
+                "request": exchange.body,
+                "response": exchange.response.as_ref().map(|(status, body)| json!({"status": status, "body": body})),
+                "hooks": exchange.hooks,
+                "error": request.error,
+            });
+            let path = self.dir.join(format!("{:04}.json", sequence));
+            self.write(&path, &artifact)?;
+            self.artifacts.borrow_mut().push((request.file.to_string(), path));
+        }
+        Ok(())
+    }
+
+    /// Fills in `file_sha256_after` and `written_to` on every artifact about `file`, once
+    /// `content` has been written to `target`.
+    pub fn applied(&self, file: &str, target: &str, content: &str) -> Result<()> {
+        let after = sha256(content.as_bytes());
+        for (_, path) in self.artifacts.borrow().iter().filter(|(artifact_file, _)| artifact_file == file) {
+            let artifact = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the audit artifact {}", path.display()))?;
+            let mut artifact: Value = serde_json::from_str(&artifact)
+                .with_context(|| format!("Invalid audit artifact: {}", path.display()))?;
+            artifact["file_sha256_after"] = json!(after);
+            artifact["written_to"] = json!(target);
+            self.write(path, &artifact)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, path: &Path, artifact: &Value) -> Result<()> {
+        let mut text = serde_json::to_string_pretty(artifact)?;
+        if !self.api_key.is_empty() {
+            text = text.replace(&self.api_key, "[REDACTED]");
+        }
+        fs::write(path, text)
+            .with_context(|| format!("Failed to write the audit artifact {}", path.display()))
+    }
+}
+
+fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
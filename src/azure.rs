@@ -0,0 +1,97 @@
+use crate::config::ProviderConfig;
+use crate::edit::GenerationOptions;
+use crate::error::CodersError;
+use crate::http;
+use crate::openai_compat;
+use crate::prompts::build_messages;
+use crate::Provider;
+use anyhow::Result;
+use serde_json::json;
+
+pub const DEFAULT_API_VERSION: &str = "2024-02-01";
+
+// Newer data-plane API versions dropped the deployments list, so validation pins the last
+// one that has it.
+const DEPLOYMENTS_API_VERSION: &str = "2022-12-01";
+
+/// Azure OpenAI routes requests by resource endpoint and deployment name rather than by
+/// model, and versions its API with a query parameter.
+pub struct Deployment {
+    pub endpoint: String,
+    pub name: String,
+    pub api_version: String,
+}
+
+impl Deployment {
+    /// Command-line values win over the `[providers.azure]` config section.
+    pub fn resolve(endpoint: Option<&str>, name: Option<&str>, api_version: Option<&str>, config: Option<&ProviderConfig>) -> Result<Deployment, CodersError> {
+        let endpoint = endpoint.map(String::from)
+            .or_else(|| config.and_then(|c| c.endpoint.clone()))
+            .ok_or_else(|| CodersError::Config("Azure needs an endpoint: pass --azure-endpoint or set endpoint under [providers.azure]".to_string()))?;
+        let name = name.map(String::from)
+            .or_else(|| config.and_then(|c| c.deployment.clone()))
+            .ok_or_else(|| CodersError::Config("Azure needs a deployment name: pass --azure-deployment or set deployment under [providers.azure]".to_string()))?;
+        let api_version = api_version.map(String::from)
+            .or_else(|| config.and_then(|c| c.api_version.clone()))
+            .unwrap_or_else(|| DEFAULT_API_VERSION.to_string());
+
+        if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+            return Err(CodersError::Config(format!("Azure endpoint '{}' must start with https://", endpoint)));
+        }
+
+        Ok(Deployment { endpoint: endpoint.trim_end_matches('/').to_string(), name, api_version })
+    }
+
+    fn chat_completions_url(&self) -> String {
+        format!("{}/openai/deployments/{}/chat/completions?api-version={}", self.endpoint, self.name, self.api_version)
+    }
+
+    fn deployments_url(&self) -> String {
+        format!("{}/openai/deployments?api-version={}", self.endpoint, DEPLOYMENTS_API_VERSION)
+    }
+}
+
+/// Checks the key against the deployments list. A bad key returns `Ok(false)` so the caller
+/// can prompt again; an unreachable endpoint or a missing deployment is a config error.
+pub async fn validate_api_key(http: &http::Transport, deployment: &Deployment, api_key: &str) -> Result<bool> {
+    let response = http.get(&deployment.deployments_url(), &[("api-key", api_key.to_string())]).await
+        .map_err(|e| CodersError::Config(format!("Could not reach the Azure endpoint {} ({:#}); check --azure-endpoint", deployment.endpoint, e)))?;
+
+    match response.status.as_u16() {
+        401 | 403 => return Ok(false),
+        404 => {
+            return Err(CodersError::Config(format!("{} does not look like an Azure OpenAI endpoint (no deployments list); check --azure-endpoint", deployment.endpoint)).into());
+        }
+        _ if !response.status.is_success() => {
+            return Err(CodersError::from_response("Azure", response.status.as_u16(), &response.body).into());
+        }
+        _ => {}
+    }
+
+    let json_response: serde_json::Value = serde_json::from_str(&response.body)
+        .map_err(|e| CodersError::ResponseParse(e.to_string()))?;
+    let names: Vec<&str> = json_response["data"].as_array()
+        .map(|deployments| deployments.iter().filter_map(|d| d["id"].as_str()).collect())
+        .unwrap_or_default();
+    if !names.contains(&deployment.name.as_str()) {
+        let available = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+        return Err(CodersError::Config(format!("Deployment '{}' was not found at {} (available: {}); check --azure-deployment", deployment.name, deployment.endpoint, available)).into());
+    }
+
+    Ok(true)
+}
+
+pub async fn send_request_to_azure(http: &http::Transport, api_key: &str, deployment: &Deployment, context: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    // No "model" field: the deployment in the URL decides which model answers.
+    let mut request_body = json!({
+        "messages": build_messages(language, context, options, options.capabilities(Provider::Azure, &deployment.name)),
+        "max_tokens": 2048,
+    });
+    options.apply_to(&mut request_body);
+
+    let response = openai_compat::post_timed(http, "Azure", &deployment.chat_completions_url(), &[("api-key", api_key.to_string())], &request_body).await?;
+    if response.status.as_u16() == 404 {
+        return Err(CodersError::Config(format!("Deployment '{}' was not found at {}; check --azure-deployment", deployment.name, deployment.endpoint)).into());
+    }
+    openai_compat::chat_completion_content("Azure", response, options.tools)
+}
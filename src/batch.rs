@@ -0,0 +1,257 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// One line of a `--batch` file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub file: String,
+    pub prompt: String,
+    /// Model name or alias; the run's model when missing.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Reads a `--batch` file: one JSON object per line, blank lines skipped. A malformed line
+/// or a missing file is an error before anything is sent.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(line)
+            .map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+        if entry.prompt.trim().is_empty() {
+            return Err(anyhow::anyhow!("{}:{}: the prompt is empty", path.display(), number + 1));
+        }
+        if !Path::new(&entry.file).is_file() {
+            return Err(anyhow::anyhow!("{}:{}: {} is not a file", path.display(), number + 1, entry.file));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// How one entry ended: the summary of the accepted change, `None` when nothing was
+/// accepted (a dry run, no change, or a declined proposal), or the error.
+pub struct Outcome {
+    pub file: String,
+    pub model: String,
+    pub prompt: String,
+    pub result: Result<Option<String>, String>,
+    pub usage: Usage,
+    pub stats: Stats,
+}
+
+/// The lines the last proposal for a file inserted, deleted and modified, and the time its
+/// requests took, for `--quiet`.
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub inserted: usize,
+    pub deleted: usize,
+    pub modified: usize,
+    pub latency: Duration,
+    /// The `--fallback-model`, when it answered instead of the file's model.
+    pub fallback: Option<String>,
+}
+
+/// The estimated tokens spent on one file, per provider and model, since a verifier can use
+/// another one than the edit.
+#[derive(Clone, Default)]
+pub struct Usage(BTreeMap<(String, String), (usize, usize)>);
+
+impl Usage {
+    pub fn add(&mut self, provider: &str, model: &str, prompt_tokens: usize, completion_tokens: usize) {
+        let tokens = self.0.entry((provider.to_string(), model.to_string())).or_default();
+        tokens.0 += prompt_tokens;
+        tokens.1 += completion_tokens;
+    }
+
+    pub fn merge(&mut self, other: &Usage) {
+        for ((provider, model), (prompt_tokens, completion_tokens)) in &other.0 {
+            self.add(provider, model, *prompt_tokens, *completion_tokens);
+        }
+    }
+
+    /// Prompt and completion tokens.
+    pub fn tokens(&self) -> (usize, usize) {
+        self.0.values().fold((0, 0), |(prompt, completion), tokens| (prompt + tokens.0, completion + tokens.1))
+    }
+
+    /// USD at `prices` (per million input and output tokens, by provider and model); `None`
+    /// when one of the models has no price.
+    pub fn cost(&self, prices: &Prices) -> Option<f64> {
+        self.0.iter()
+            .map(|(key, (prompt, completion))| {
+                let (input, output) = prices.get(key)?;
+                Some((*prompt as f64 * input + *completion as f64 * output) / 1_000_000.0)
+            })
+            .sum()
+    }
+}
+
+/// USD per million input and output tokens, by provider id and model.
+pub type Prices = HashMap<(String, String), (f64, f64)>;
+
+/// Prints the run's outcomes in batch order, each with its tokens and cost, followed by the
+/// totals. With `json`, the same goes out as a single JSON object. `written` says whether
+/// the accepted changes were written.
+pub fn print_summary(outcomes: &[Outcome], written: bool, prices: &Prices, json: bool) {
+    let mut total = Usage::default();
+    for outcome in outcomes {
+        total.merge(&outcome.usage);
+    }
+    if json {
+        let (prompt_tokens, completion_tokens) = total.tokens();
+        let files: Vec<_> = outcomes.iter()
+            .map(|outcome| {
+                let (status, detail) = status(outcome, written);
+                let (prompt_tokens, completion_tokens) = outcome.usage.tokens();
+                json!({
+                    "file": outcome.file,
+                    "model": outcome.model,
+                    "prompt": outcome.prompt,
+                    "status": status,
+                    "detail": detail,
+                    "prompt_tokens": prompt_tokens,
+                    "completion_tokens": completion_tokens,
+                    "cost": outcome.usage.cost(prices),
+                })
+            })
+            .collect();
+        println!("{}", json!({
+            "files": files,
+            "written": written,
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "cost": total.cost(prices),
+        }));
+        return;
+    }
+
+    let count = |wanted: &str| outcomes.iter().filter(|outcome| status(outcome, written).0 == wanted).count();
+    println!(
+        "\nBatch summary: {} changed, {} accepted but not written, {} not applied, {} failed",
+        count("changed"), count("accepted"), count("not applied"), count("failed")
+    );
+    let file_width = outcomes.iter().map(|outcome| outcome.file.len()).max().unwrap_or(0).max("file".len());
+    let model_width = outcomes.iter().map(|outcome| outcome.model.len()).max().unwrap_or(0).max("model".len());
+    println!("  {:<12} {:<file_width$} {:<model_width$} {:>16} {:>9}", "status", "file", "model", "tokens in/out", "cost");
+    for outcome in outcomes {
+        let (status, detail) = status(outcome, written);
+        let (prompt_tokens, completion_tokens) = outcome.usage.tokens();
+        println!(
+            "  {:<12} {:<file_width$} {:<model_width$} {:>16} {:>9}{}",
+            status, outcome.file, outcome.model,
+            format!("{}/{}", prompt_tokens, completion_tokens),
+            format_cost(outcome.usage.cost(prices)),
+            detail.map(|detail| format!("  {}", detail)).unwrap_or_default()
+        );
+    }
+    println!("Total: {}", describe_total(&total, prices));
+}
+
+/// `--quiet`: one line per outcome, `path<TAB>status<TAB>+a/-d/~m<TAB>model<TAB>latency_ms`,
+/// with status `applied`, `accepted` (but not written), `discarded`, `unchanged` or `failed`.
+/// The format stays as it is, since scripts parse it.
+pub fn print_quiet(outcomes: &[Outcome], written: bool) {
+    print!("{}", quiet_lines(outcomes, written));
+}
+
+fn quiet_lines(outcomes: &[Outcome], written: bool) -> String {
+    let mut lines = String::new();
+    for outcome in outcomes {
+        let stats = &outcome.stats;
+        let status = match &outcome.result {
+            Err(_) => "failed",
+            Ok(Some(_)) if written => "applied",
+            Ok(Some(_)) => "accepted",
+            Ok(None) if stats.inserted + stats.deleted + stats.modified > 0 => "discarded",
+            Ok(None) => "unchanged",
+        };
+        lines.push_str(&format!(
+            "{}\t{}\t+{}/-{}/~{}\t{}\t{}\n",
+            outcome.file, status, stats.inserted, stats.deleted, stats.modified, stats.fallback.as_ref().unwrap_or(&outcome.model), stats.latency.as_millis()
+        ));
+    }
+    lines
+}
+
+/// e.g. "~1834 tokens (1500 in, 334 out), estimated cost $0.0012".
+pub fn describe_total(usage: &Usage, prices: &Prices) -> String {
+    let (prompt_tokens, completion_tokens) = usage.tokens();
+    let cost = match usage.cost(prices) {
+        Some(cost) => format!("estimated cost {}", format_cost(Some(cost))),
+        None => "cost unknown (only OpenRouter lists prices)".to_string(),
+    };
+    format!("~{} tokens ({} in, {} out), {}", prompt_tokens + completion_tokens, prompt_tokens, completion_tokens, cost)
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        None => "-".to_string(),
+        Some(cost) if cost > 0.0 && cost < 0.0001 => "<$0.0001".to_string(),
+        Some(cost) => format!("${:.4}", cost),
+    }
+}
+
+fn status(outcome: &Outcome, written: bool) -> (&'static str, Option<&str>) {
+    match &outcome.result {
+        Ok(Some(summary)) if written => ("changed", Some(summary.as_str())),
+        Ok(Some(summary)) => ("accepted", Some(summary.as_str())),
+        Ok(None) => ("not applied", None),
+        Err(e) => ("failed", Some(e.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(file: &str, result: Result<Option<String>, String>, stats: Stats) -> Outcome {
+        Outcome { file: file.to_string(), model: "llama-3.1-8b-instant".to_string(), prompt: "Fix it".to_string(), result, usage: Usage::default(), stats }
+    }
+
+    fn stats(inserted: usize, deleted: usize, modified: usize, latency_ms: u64) -> Stats {
+        Stats { inserted, deleted, modified, latency: Duration::from_millis(latency_ms), fallback: None }
+    }
+
+    #[test]
+    fn each_outcome_is_one_tab_separated_line() {
+        let outcomes = [
+            outcome("src/a.rs", Ok(Some("1 modified".to_string())), stats(2, 1, 3, 1250)),
+            outcome("src/b.rs", Ok(None), stats(0, 4, 0, 980)),
+            outcome("src/c.rs", Ok(None), stats(0, 0, 0, 40)),
+            outcome("src/d.rs", Err("Groq API error (503)".to_string()), stats(0, 0, 0, 7)),
+            outcome("src/e.rs", Ok(Some("1 inserted".to_string())), Stats { fallback: Some("llama-3.3-70b-versatile".to_string()), ..stats(1, 0, 0, 2001) }),
+        ];
+
+        assert_eq!(quiet_lines(&outcomes, true), concat!(
+            "src/a.rs\tapplied\t+2/-1/~3\tllama-3.1-8b-instant\t1250\n",
+            "src/b.rs\tdiscarded\t+0/-4/~0\tllama-3.1-8b-instant\t980\n",
+            "src/c.rs\tunchanged\t+0/-0/~0\tllama-3.1-8b-instant\t40\n",
+            "src/d.rs\tfailed\t+0/-0/~0\tllama-3.1-8b-instant\t7\n",
+            "src/e.rs\tapplied\t+1/-0/~0\tllama-3.3-70b-versatile\t2001\n",
+        ));
+    }
+
+    #[test]
+    fn an_accepted_change_that_was_not_written_says_so() {
+        let outcomes = [outcome("src/a.rs", Ok(Some("1 modified".to_string())), stats(0, 0, 1, 3))];
+
+        assert_eq!(quiet_lines(&outcomes, false), "src/a.rs\taccepted\t+0/-0/~1\tllama-3.1-8b-instant\t3\n");
+    }
+
+    #[test]
+    fn no_outcomes_print_nothing() {
+        assert_eq!(quiet_lines(&[], true), "");
+    }
+}
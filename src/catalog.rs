@@ -0,0 +1,279 @@
+use crate::cli::{fuzzy_match_models, model_alias, models_endpoint, Cli};
+use crate::config;
+use crate::error::CodersError;
+use crate::history;
+use crate::http;
+use crate::keys;
+use crate::paths;
+use crate::{Capabilities, Provider};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+// How long a cached listing is used before `load` fetches it again; `--refresh` doesn't wait.
+const MAX_AGE_SECS: u64 = 24 * 60 * 60;
+// Suggestions for a name no listing has.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// One model from a provider's live `/models` listing, with whatever it reports about it.
+pub struct Model {
+    pub id: String,
+    pub context_length: Option<u64>,
+    pub max_completion_tokens: Option<u64>,
+    /// USD per million input and output tokens; only OpenRouter reports it.
+    pub pricing: Option<(f64, f64)>,
+    /// e.g. "text+image->text", as OpenRouter reports it.
+    pub modality: Option<String>,
+    pub description: Option<String>,
+}
+
+// A listing as the provider sent it, in `models/<provider>.json` in the cache directory.
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    fetched: u64,
+    base_url: String,
+    listing: Value,
+}
+
+/// The provider's model list, sorted by id: the copy cached within the last day from the
+/// same `base_url`, unless `refresh` is set, or else the live listing, which is cached.
+pub async fn load(http: &http::Transport, provider: Provider, base_url: &str, api_key: &str, refresh: bool) -> Result<Vec<Model>> {
+    if let Some(cached) = read_cache(provider).filter(|cached| !refresh && cached.base_url == base_url && is_fresh(cached)) {
+        return Ok(parse(&cached.listing));
+    }
+    let listing = fetch(http, provider, base_url, api_key).await?;
+    let models = parse(&listing);
+    write_cache(provider, &Cached { fetched: history::now(), base_url: base_url.to_string(), listing });
+    Ok(models)
+}
+
+/// The last listing fetched for `provider`, however old, without making a request.
+pub fn cached(provider: Provider) -> Option<Vec<Model>> {
+    read_cache(provider).map(|cached| parse(&cached.listing))
+}
+
+async fn fetch(http: &http::Transport, provider: Provider, base_url: &str, api_key: &str) -> Result<Value> {
+    let (url, auth) = models_endpoint(provider.name(), base_url, api_key)
+        .ok_or_else(|| anyhow::anyhow!("{} has no model list", provider.name()))?;
+    let response = http.get(&url, &[auth]).await?;
+    if !response.status.is_success() {
+        return Err(CodersError::from_response(provider.name(), response.status.as_u16(), &response.body).into());
+    }
+    Ok(serde_json::from_str(&response.body).map_err(|e| CodersError::ResponseParse(e.to_string()))?)
+}
+
+// The OpenAI-style providers answer with `data[].id`; Gemini and Cohere with `models[].name`.
+fn parse(listing: &Value) -> Vec<Model> {
+    let mut models: Vec<Model> = listing["data"].as_array()
+        .map(|models| models.iter().filter_map(|m| Some((m["id"].as_str()?.to_string(), m))).collect::<Vec<_>>())
+        .or_else(|| listing["models"].as_array()
+            .map(|models| models.iter()
+                .filter_map(|m| Some((m["name"].as_str()?.trim_start_matches("models/").to_string(), m)))
+                .collect()))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, m)| Model {
+            id,
+            context_length: context_length(m),
+            max_completion_tokens: max_completion_tokens(m),
+            pricing: pricing(m),
+            modality: m["architecture"]["modality"].as_str().map(String::from),
+            description: m["description"].as_str().map(str::trim).filter(|text| !text.is_empty()).map(String::from),
+        })
+        .collect();
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    models
+}
+
+fn cache_file(provider: Provider) -> Option<PathBuf> {
+    paths::cache_dir().ok().map(|dir| dir.join("models").join(format!("{}.json", provider.id())))
+}
+
+fn read_cache(provider: Provider) -> Option<Cached> {
+    let contents = fs::read_to_string(cache_file(provider)?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn is_fresh(cached: &Cached) -> bool {
+    history::now().saturating_sub(cached.fetched) < MAX_AGE_SECS
+}
+
+// The cache is only an optimisation, so failing to write it is not worth reporting.
+fn write_cache(provider: Provider, cached: &Cached) {
+    if let Some(path) = cache_file(provider) {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(contents) = serde_json::to_string(cached) {
+            let _ = fs::write(path, contents);
+        }
+    }
+}
+
+// OpenRouter, Hyperbolic and Cohere say `context_length`, Groq `context_window` and Gemini
+// `inputTokenLimit`.
+fn context_length(model: &Value) -> Option<u64> {
+    ["context_length", "context_window", "inputTokenLimit"].iter()
+        .find_map(|field| model[*field].as_u64())
+}
+
+// OpenRouter reports it for the provider serving the model, Groq and Gemini directly.
+fn max_completion_tokens(model: &Value) -> Option<u64> {
+    model["top_provider"]["max_completion_tokens"].as_u64()
+        .or_else(|| ["max_completion_tokens", "outputTokenLimit"].iter().find_map(|field| model[*field].as_u64()))
+}
+
+// OpenRouter prices are strings in USD per token; a negative price means it varies.
+fn pricing(model: &Value) -> Option<(f64, f64)> {
+    let per_million = |field: &str| {
+        let price = &model["pricing"][field];
+        let price = price.as_str().and_then(|price| price.parse::<f64>().ok()).or_else(|| price.as_f64())?;
+        (price >= 0.0).then_some(price * 1_000_000.0)
+    };
+    Some((per_million("prompt")?, per_million("completion")?))
+}
+
+fn describe_price(pricing: (f64, f64)) -> String {
+    match pricing {
+        (input, output) if input == 0.0 && output == 0.0 => "free".to_string(),
+        (input, output) => format!("${:.2} in / ${:.2} out per 1M tokens", input, output),
+    }
+}
+
+/// e.g. "131k context, $0.50 in / $1.50 out per 1M tokens", or an empty string when the
+/// listing said nothing about the model.
+pub fn details(model: &Model) -> String {
+    let context = model.context_length.map(|tokens| format!("{}k context", (tokens + 500) / 1000));
+    let price = model.pricing.map(describe_price);
+    context.into_iter().chain(price).collect::<Vec<_>>().join(", ")
+}
+
+/// `coders models info <NAME>`: what the listings of the providers with a key (or a cached
+/// listing) say about the model. `provider:model` or an `[aliases]` entry with a provider
+/// looks at that provider only; a name several providers list is shown for each of them.
+pub async fn run_info(cli: &Cli, config: &config::Config, name: &str) -> Result<()> {
+    let (only, model) = match model_alias(config, name) {
+        Some((provider, model)) => (provider, model),
+        None => match name.split_once(':').map(|(prefix, model)| (Provider::from_str(prefix, true), model)) {
+            Some((Ok(provider), model)) => (Some(provider), model),
+            _ => (None, name),
+        },
+    };
+
+    let http = http::Transport::new(None, None, cli.headers.clone(), config.allowed_hosts.clone());
+    let mut searched = Vec::new();
+    let mut found = Vec::new();
+    let mut known_ids = Vec::new();
+    // Azure lists deployments, not models
+    for provider in Provider::value_variants().iter().copied().filter(|provider| *provider != Provider::Azure && only.is_none_or(|only| only == *provider)) {
+        let models = match keys::stored_key(provider) {
+            Some((_, api_key)) => match load(&http, provider, &keys::base_url(cli, config, provider), &api_key, cli.refresh).await {
+                Ok(models) => models,
+                Err(e) => {
+                    eprintln!("Warning: could not fetch the {} model list: {:#}", provider.name(), e);
+                    match cached(provider) {
+                        Some(models) => models,
+                        None => continue,
+                    }
+                }
+            },
+            None => match cached(provider) {
+                Some(models) => models,
+                None => continue,
+            },
+        };
+        searched.push(provider.id());
+        for listed in models {
+            if listed.id.eq_ignore_ascii_case(model) {
+                found.push((provider, listed));
+            } else {
+                known_ids.push(format!("{}:{}", provider.id(), listed.id));
+            }
+        }
+    }
+
+    if searched.is_empty() {
+        return Err(CodersError::Config(match only {
+            Some(provider) => format!("No {} model list to look in; set {}_API_KEY or run coders once with it", provider.name(), provider.name().to_uppercase()),
+            None => "No model list to look in; set a provider's API key (e.g. OPENROUTER_API_KEY) or run coders once with it".to_string(),
+        }).into());
+    }
+    if found.is_empty() {
+        let ids: Vec<&str> = known_ids.iter().map(String::as_str).collect();
+        let suggestions = suggestions(model, &ids);
+        let hint = if suggestions.is_empty() {
+            String::new()
+        } else {
+            format!(". Did you mean: {}?", suggestions.join(", "))
+        };
+        return Err(CodersError::Config(format!("No model named '{}' in the {} model list(s){}", model, searched.join(", "), hint)).into());
+    }
+
+    for (i, (provider, listed)) in found.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_info(*provider, listed);
+    }
+    if found.len() > 1 {
+        println!("\n{} providers list '{}'; pick one with a provider prefix, e.g. --model-name {}:{}.", found.len(), model, found[0].0.id(), found[0].1.id);
+    }
+    Ok(())
+}
+
+fn print_info(provider: Provider, model: &Model) {
+    let tokens = |count: Option<u64>| count.map_or("not listed".to_string(), |count| format!("{} tokens", count));
+    println!("{}:{}", provider.id(), model.id);
+    println!("  context:         {}", tokens(model.context_length));
+    println!("  max completion:  {}", tokens(model.max_completion_tokens));
+    println!("  price:           {}", model.pricing.map_or("not listed".to_string(), describe_price));
+    if let Some(modality) = &model.modality {
+        println!("  modality:        {}", modality);
+    }
+    let system = match Capabilities::known(provider, &model.id) {
+        Some(capabilities) if capabilities.system_role => "yes",
+        Some(_) => "no, the instructions lead the first user message",
+        None => "not known; sent, and folded into the first user message if rejected",
+    };
+    println!("  system messages: {}", system);
+    if let Some(description) = &model.description {
+        println!("  description:     {}", description);
+    }
+}
+
+// Ids containing every part of the name, else the ones within a few typos of it.
+fn suggestions<'a>(name: &str, ids: &[&'a str]) -> Vec<&'a str> {
+    let mut close = fuzzy_match_models(name, ids);
+    if close.is_empty() {
+        let name = name.to_lowercase();
+        let mut scored: Vec<(usize, &str)> = ids.iter()
+            .map(|id| {
+                let model = id.split_once(':').map_or(*id, |(_, model)| model).to_lowercase();
+                let short = model.rsplit('/').next().unwrap_or(&model).to_string();
+                (edit_distance(&name, &model).min(edit_distance(&name, &short)), *id)
+            })
+            .filter(|(distance, _)| *distance <= (name.chars().count() / 4).max(2))
+            .collect();
+        scored.sort();
+        close = scored.into_iter().map(|(_, id)| id).collect();
+    }
+    close.truncate(MAX_SUGGESTIONS);
+    close
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Only the tail of a long compiler log is sent back to the model.
+const MAX_OUTPUT_CHARS: usize = 4000;
+
+pub struct CheckResult {
+    pub passed: bool,
+    pub output: String,
+}
+
+/// Runs the `--check` command against `content` as it would be written to `file`. The
+/// content goes to a temporary sibling with the same extension, so the file on disk is
+/// never touched; `{file}` in the command is replaced by that path, or the path is appended.
+pub fn run_check(command: &str, file: &str, content: &str) -> Result<CheckResult> {
+    let temp = temp_path(Path::new(file))?;
+    fs::write(&temp, content)
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+
+    let quoted = quote(&temp.to_string_lossy());
+    let command = if command.contains("{file}") {
+        command.replace("{file}", &quoted)
+    } else {
+        format!("{} {}", command, quoted)
+    };
+    let output = shell(&command).output();
+    let _ = fs::remove_file(&temp);
+    let output = output.with_context(|| format!("Failed to run check command: {}", command))?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let mut text = text.replace(temp.to_string_lossy().as_ref(), file);
+    if text.trim().is_empty() {
+        text = format!("(no output, {})", output.status);
+    }
+    Ok(CheckResult { passed: output.status.success(), output: tail(&text, MAX_OUTPUT_CHARS) })
+}
+
+fn temp_path(file: &Path) -> Result<PathBuf> {
+    let stem = file.file_stem()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {}", file.display()))?
+        .to_string_lossy();
+    let name = match file.extension() {
+        Some(extension) => format!(".{}.coders-check.{}", stem, extension.to_string_lossy()),
+        None => format!(".{}.coders-check", stem),
+    };
+    Ok(file.with_file_name(name))
+}
+
+#[cfg(unix)]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+#[cfg(unix)]
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+#[cfg(not(unix))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(unix))]
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path)
+}
+
+fn tail(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.trim_end().to_string();
+    }
+    let tail: String = text.chars().skip(count - max_chars).collect();
+    format!("...\n{}", tail.trim_end())
+}
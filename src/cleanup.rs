@@ -0,0 +1,58 @@
+/// Lead-ins and sign-offs models wrap around code. A line at the start or end of the
+/// extracted code that begins with one of these (ignoring case) is dropped; the config's
+/// `strip_phrases` adds to the list.
+pub const DEFAULT_PHRASES: &[&str] = &[
+    "here's the updated code",
+    "here is the updated code",
+    "here's the modified code",
+    "here is the modified code",
+    "here's the corrected code",
+    "here is the corrected code",
+    "here's the code",
+    "here is the code",
+    "sure, here",
+    "certainly! here",
+    "certainly, here",
+    "let me know if",
+    "i hope this helps",
+    "hope this helps",
+    "feel free to ask",
+    "feel free to let me know",
+];
+
+/// Removes boilerplate lines from the start and end of extracted code, and a closing fence
+/// glued to the last line (models copying the few-shot examples write `}```).
+pub fn strip_artifacts(code: &str, extra_phrases: &[String]) -> String {
+    let is_artifact = |line: &str| {
+        let line = line.trim().to_lowercase();
+        DEFAULT_PHRASES.iter().any(|phrase| line.starts_with(phrase))
+            || extra_phrases.iter().any(|phrase| !phrase.is_empty() && line.starts_with(&phrase.to_lowercase()))
+    };
+
+    let mut lines: Vec<&str> = code.lines().collect();
+    // Blank lines are only dropped together with the artifact they separate from the code
+    while let Some(first) = lines.iter().position(|line| !line.trim().is_empty()) {
+        if !is_artifact(lines[first]) {
+            break;
+        }
+        lines.drain(..=first);
+        while lines.first().is_some_and(|line| line.trim().is_empty()) {
+            lines.remove(0);
+        }
+    }
+    while let Some(last) = lines.iter().rposition(|line| !line.trim().is_empty()) {
+        if !is_artifact(lines[last]) {
+            break;
+        }
+        lines.truncate(last);
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+    }
+    if let Some(last) = lines.last_mut() {
+        if let Some(stripped) = last.strip_suffix("```") {
+            *last = stripped;
+        }
+    }
+    lines.join("\n")
+}
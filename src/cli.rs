@@ -0,0 +1,1077 @@
+use crate::audit;
+use crate::azure;
+use crate::batch;
+use crate::catalog;
+use crate::config;
+use crate::console;
+use crate::edit::{GenerationOptions, DEFAULT_RETRY_ON_MALFORMED};
+use crate::error::{self, CodersError};
+use crate::export;
+use crate::git;
+use crate::history;
+use crate::hooks;
+use crate::http;
+use crate::image;
+use crate::init;
+use crate::keys;
+use crate::memory;
+use crate::merge::EditMode;
+use crate::messages;
+use crate::paths;
+use crate::preview;
+use crate::privacy;
+use crate::profile;
+use crate::prompt;
+use crate::render::DiffFormat;
+use crate::routing;
+use crate::scope;
+use crate::secrets;
+use crate::taskfile;
+use crate::tasks::{check_gate, process_files, read_examples, resolve_verifier, run_manifest, Session, Task};
+use crate::undo;
+use crate::{Capabilities, OpenRouterModel, Provider};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 256 * 1024;
+
+// OpenRouter's published limits for `:free` model variants.
+const FREE_TIER_PER_MINUTE: u32 = 20;
+const FREE_TIER_PER_DAY: u32 = 200;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[arg(long, value_name = "DIR", global = true, help = "Directory for the config file and API keys (also CODERS_CONFIG_DIR); state and cache go in subdirectories")]
+    pub config_dir: Option<PathBuf>,
+    #[arg(short, long, required_unless_present_any = ["since", "files_from", "batch"])]
+    pub file: Option<String>,
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["since", "files_from", "batch"], help = "Write the accepted changes to PATH instead of back to the input file")]
+    pub output: Option<String>,
+    #[arg(long, help = "Give the file written to --output the input file's permissions, such as the executable bit")]
+    pub preserve_permissions: bool,
+    #[arg(long, help = "Expect FILE to be new (or empty) and have the model write all of it; an error if it already has content. A missing file is created without it too")]
+    pub create: bool,
+    #[arg(short, long, help = "Don't ask to confirm where changes will be written; with --quiet, apply the proposed changes without asking too")]
+    pub yes: bool,
+    #[arg(long, value_name = "GIT_REF", help = "Process every file changed since the given git ref")]
+    pub since: Option<String>,
+    #[arg(long, value_name = "PATH", help = "Process every file listed in PATH, one per line (blank lines and # comments are skipped)")]
+    pub files_from: Option<PathBuf>,
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "since", "files_from"], help = "Run every {\"file\", \"prompt\", \"model\"} line of a JSONL file instead of asking for a prompt, then print a summary")]
+    pub batch: Option<PathBuf>,
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_FILE_SIZE, help = "Refuse to send files larger than this")]
+    pub max_file_size: u64,
+    #[arg(long, help = "Stream the response as it is generated (OpenRouter only)")]
+    pub stream: bool,
+    #[arg(long, value_enum, help = "How proposed changes are printed [default: color]")]
+    pub format: Option<DiffFormat>,
+    #[arg(long, help = "Words instead of +/-/~ markers, no colors and a plain spinner, for screen readers and terminals without Unicode (automatic with TERM=dumb or a non-UTF-8 locale)")]
+    pub ascii: bool,
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = http::parse_header, help = "Extra HTTP header sent with every provider request (repeatable)")]
+    pub headers: Vec<(String, String)>,
+    #[arg(long, help = "Fetch the providers' model lists again instead of using the copies cached for a day")]
+    pub refresh: bool,
+    #[arg(long, visible_alias = "no-validate-key", help = "Skip the API key check against the provider's models endpoint; a rejected key is only reported when the request itself fails")]
+    pub offline_validate: bool,
+    #[arg(long, help = "Use a typed API key for this run only instead of saving it for the next one")]
+    pub no_save_key: bool,
+    #[arg(long, value_name = "DIR", conflicts_with = "replay", help = "Save every provider request/response pair to DIR")]
+    pub record: Option<PathBuf>,
+    #[arg(long, value_name = "DIR", help = "Serve provider responses from recordings in DIR without touching the network")]
+    pub replay: Option<PathBuf>,
+    #[arg(long, value_name = "DIR", help = "Write a JSON artifact per provider request to DIR: destination, model, payload, response and file hashes")]
+    pub audit_dir: Option<PathBuf>,
+    #[arg(long, value_name = "N", help = "Retry up to N times when the provider answers successfully but with an empty completion [default: 0]")]
+    pub retry_on_empty: Option<u32>,
+    #[arg(long, value_name = "N", help = "Retry up to N times when a successful response body isn't valid JSON, e.g. because it was cut off [default: 2]")]
+    pub retry_on_malformed: Option<u32>,
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), help = "Give up on each attempt of a request (every retry gets its own) without a complete answer after SECS seconds")]
+    pub timeout: Option<u64>,
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), help = "Stop a request after SECS seconds in all, retries and their waits included; timed-out attempts are retried until then")]
+    pub deadline: Option<u64>,
+    #[arg(long, value_name = "MODEL", requires = "deadline", help = "When the model hasn't started answering by half the --deadline, cancel its request and ask MODEL (a faster one) for the rest of the time [config: fallback_model]")]
+    pub fallback_model: Option<String>,
+    #[arg(short, long, help = "Print extra diagnostic output")]
+    pub verbose: bool,
+    #[arg(short, long, conflicts_with_all = ["verbose", "show_context"], help = "Print only one path<TAB>status<TAB>+a/-d/~m<TAB>model<TAB>latency_ms line per file, errors on stderr, and never ask anything (see --on-no-tty)")]
+    pub quiet: bool,
+    #[arg(long, help = "Print how long key validation, prompt assembly, network, parsing and diffing took at the end of the run")]
+    pub profile: bool,
+    #[arg(long, help = "Print every message of each request with its token count and ask before sending it")]
+    pub show_context: bool,
+    #[arg(long, help = "Show which [[routing]] rule picked the provider and model, and why")]
+    pub explain_routing: bool,
+    #[arg(long, value_name = "CMD", help = "Command that must succeed on the proposed file before it can be applied; {file} is replaced by its path")]
+    pub check: Option<String>,
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "check", help = "How many times a --check failure is sent back to the model for a fix")]
+    pub max_repair_attempts: u32,
+    #[arg(long, visible_alias = "max-concurrent-requests", value_name = "N", default_value_t = 4, global = true, value_parser = clap::value_parser!(u32).range(1..), help = "In batch runs and `coders run`, send at most N requests at once")]
+    pub jobs: u32,
+    #[arg(long, help = "In batch runs, answer apply/skip/abort for each file instead of y/n")]
+    pub confirm_each_file: bool,
+    #[arg(long, help = "Don't parse the merged result to warn about syntax errors before applying")]
+    pub no_syntax_check: bool,
+    #[arg(long, help = "List the lines of blocks the model moved, instead of one \"moved (lines a-b -> c-d)\" line per block")]
+    pub expand_moves: bool,
+    #[arg(long, help = "For JSON, YAML and TOML files, show the changed keys instead of a line diff and rebuild the file from the original")]
+    pub structured_merge: bool,
+    #[arg(long, value_name = "A-B", value_parser = scope::parse_lines, conflicts_with = "slim_context", help = "Ask the model to change only lines A-B of the file (1-based, inclusive)")]
+    pub lines: Option<scope::LineRange>,
+    #[arg(long, conflicts_with = "structured_merge", help = "Leave out changes outside --lines, or outside the functions and types the prompt names, unless a second confirmation accepts them")]
+    pub strict_scope: bool,
+    #[arg(long, value_enum, default_value = "merge", help = "How the model's code is combined with the file")]
+    pub mode: EditMode,
+    #[arg(long, conflicts_with_all = ["mode", "structured_merge"], help = "Write the model's code as the whole file and diff it line by line against the original, without the merge heuristic")]
+    pub whole_file_diff: bool,
+    #[arg(long, value_enum, default_value = "error", help = "What to do with a proposal when stdin has ended or there is no terminal to confirm on")]
+    pub on_no_tty: prompt::NoTty,
+    #[arg(long, help = "Allow writing a JSON, TOML or YAML file even when the merged result no longer parses")]
+    pub force: bool,
+    #[arg(long, value_name = "PATH", help = "Also write the proposed changes of every file to PATH, in the --format chosen")]
+    pub diff_out: Option<PathBuf>,
+    #[arg(long, help = "Show the proposed changes without asking to apply them")]
+    pub no_apply_prompt: bool,
+    #[arg(long, value_name = "PROVIDER:MODEL", help = "Have a second model (or alias) review the diff of every proposal, and show its correction if it sends one")]
+    pub verify_model: Option<String>,
+    #[arg(long, conflicts_with = "fail_on_no_change", help = "Show the proposed changes without asking, and exit with code 3 if there are any (CI gate)")]
+    pub fail_on_change: bool,
+    #[arg(long, help = "Show the proposed changes without asking, and exit with code 3 if no file has any (CI gate)")]
+    pub fail_on_no_change: bool,
+    #[arg(long, value_name = "PATH", help = "Also write the prompt, model and a side-by-side diff of every file to a standalone HTML page")]
+    pub export_html: Option<PathBuf>,
+    #[arg(long, help = "Send no few-shot examples and a shorter system prompt, saving about 200 tokens per request")]
+    pub concise: bool,
+    #[arg(long, conflicts_with_all = ["explain", "min_confidence"], help = "Have the model return the code through an apply_edit tool call instead of a fenced block (OpenRouter, Groq, Hyperbolic chat models, Azure)")]
+    pub tools: bool,
+    #[arg(long, conflicts_with = "tools", help = "Apply the answer as it came, without taking the code out of its fenced block, e.g. for Markdown files that contain fences themselves")]
+    pub keep_fences: bool,
+    #[arg(long, value_name = "PATH", help = "Send an image (PNG, JPEG, GIF or WebP), e.g. a screenshot of the bug, with the request; needs a vision model on an OpenAI-compatible provider")]
+    pub image: Option<PathBuf>,
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["concise", "explain", "min_confidence"], help = "Send the {\"role\", \"content\"} messages of a JSON array instead of the built-in system prompt and examples; the file and prompt still follow as the last message")]
+    pub messages_file: Option<PathBuf>,
+    #[arg(long, help = "Acknowledge the privacy notice shown before the first request to a provider without asking, e.g. in CI")]
+    pub accept_terms: bool,
+    #[arg(long, help = "After a rejected proposal, end instead of asking for feedback to send to the model")]
+    pub no_feedback_loop: bool,
+    #[arg(long, value_name = "TEXT", help = "Put TEXT in front of the prompt you type, e.g. \"Refactor for readability:\" (overrides prompt_prefix in the config; \"\" turns it off)")]
+    pub prompt_prefix: Option<String>,
+    #[arg(long, help = "Ask the model for a short rationale and show it alongside the diff")]
+    pub explain: bool,
+    #[arg(long, help = "Include a map of top-level symbols from the rest of the project as context")]
+    pub repo_map: bool,
+    #[arg(long, help = "When a file is over --max-file-size, send a copy without comments and repeated blank lines if that fits")]
+    pub trim_context: bool,
+    #[arg(long, help = "Strip comments, blank-line runs and long string literals from what the model sees (the file on disk keeps them)")]
+    pub slim_context: bool,
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "0", help = "Include uncommitted changes (or the last N commits) of the file's repository as context")]
+    pub git_context: Option<usize>,
+    #[arg(long = "example", value_name = "PATH", help = "Include PATH as a read-only example of how the project does things, for the model to follow but not edit (repeatable)")]
+    pub examples: Vec<PathBuf>,
+    #[arg(long, help = "Remember the proposals for each file and whether they were applied, and include a short summary of them in later requests for that file")]
+    pub with_memory: bool,
+    #[arg(long, help = "Send possible secrets (API keys, private keys, tokens, passwords) as <REDACTED-n> placeholders instead of asking first, and put them back where the answer repeats the placeholders")]
+    pub scrub_secrets: bool,
+    #[arg(short, long)]
+    pub model: bool,
+    #[arg(long, value_name = "NAME", conflicts_with = "model", help = "Model to use; partial names like hermes-405b are matched against the known models")]
+    pub model_name: Option<String>,
+    #[arg(long, value_enum, help = "Provider to send the request to [default: hyperbolic]")]
+    pub provider: Option<Provider>,
+    #[arg(long, value_name = "URL", value_parser = parse_base_url, help = "Base URL of the provider's API, overriding [endpoints] in the config and the built-in URL")]
+    pub base_url: Option<String>,
+    #[arg(long, value_name = "URL", help = "Azure OpenAI resource endpoint, e.g. https://my-resource.openai.azure.com")]
+    pub azure_endpoint: Option<String>,
+    #[arg(long, value_name = "NAME", help = "Azure OpenAI deployment to send requests to")]
+    pub azure_deployment: Option<String>,
+    #[arg(long, value_name = "VERSION", help = "Azure OpenAI api-version query parameter [default: 2024-02-01]")]
+    pub azure_api_version: Option<String>,
+    #[arg(short, long, conflicts_with = "provider", help = "Deprecated: use --provider openrouter")]
+    pub openrouter: bool,
+    #[arg(short, long, help = "Reset API key")]
+    pub reset: bool,
+    #[arg(long, value_parser = parse_confidence, help = "Only apply changes when the model's self-rated confidence (0-1) is at least this value")]
+    pub min_confidence: Option<f32>,
+    #[arg(long, help = "Use temperature 0, top_p 1 and a fixed seed for reproducible output")]
+    pub deterministic: bool,
+    #[arg(long, help = "Seed passed to the provider for reproducible sampling")]
+    pub seed: Option<u64>,
+    #[arg(long, value_parser = parse_temperature, help = "Sampling temperature (0 to 2)")]
+    pub temperature: Option<f32>,
+    #[arg(long, value_parser = parse_top_p, help = "Nucleus sampling top_p (0 to 1)")]
+    pub top_p: Option<f32>,
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help = "Maximum number of tokens to generate")]
+    pub max_tokens: Option<u32>,
+    #[arg(long = "stop", value_name = "STR", help = "Stop sequence (repeatable); \\n and \\t are unescaped")]
+    pub stop: Vec<String>,
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_penalty, help = "Frequency penalty (-2 to 2)")]
+    pub frequency_penalty: Option<f32>,
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_penalty, help = "Presence penalty (-2 to 2)")]
+    pub presence_penalty: Option<f32>,
+}
+
+pub fn parse_penalty(value: &str) -> Result<f32, String> {
+    let penalty: f32 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    config::check_range("penalty", penalty, -2.0, 2.0).map_err(|e| e.to_string())?;
+    Ok(penalty)
+}
+
+pub fn parse_base_url(value: &str) -> Result<String, String> {
+    if value.starts_with("https://") || value.starts_with("http://") {
+        Ok(value.to_string())
+    } else {
+        Err(format!("'{}' must start with https:// or http://", value))
+    }
+}
+
+pub fn unescape_stop_sequence(value: &str) -> String {
+    value.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+impl Cli {
+    // An alias with a provider prefix picks the provider unless one was given explicitly.
+    pub fn provider(&self, config: &config::Config) -> Provider {
+        let aliased = self.model_name.as_deref()
+            .and_then(|name| model_alias(config, name))
+            .and_then(|(provider, _)| provider);
+        match self.provider {
+            Some(provider) => provider,
+            None if self.openrouter => Provider::OpenRouter,
+            None => aliased
+                .or_else(|| config.provider.as_deref().and_then(|name| Provider::from_str(name, true).ok()))
+                .unwrap_or(Provider::Hyperbolic),
+        }
+    }
+
+    // JSON stays JSON; otherwise --ascii wins over --format, and a terminal that looks like
+    // it can't show Unicode wins over the config file.
+    pub fn format(&self, config: &config::Config) -> DiffFormat {
+        match (self.format, config.color) {
+            (Some(DiffFormat::Json), _) => DiffFormat::Json,
+            _ if self.ascii => DiffFormat::Ascii,
+            (Some(format), _) => format,
+            (None, _) if console::ascii_terminal() => DiffFormat::Ascii,
+            (None, Some(false)) => DiffFormat::Plain,
+            (None, _) => DiffFormat::Color,
+        }
+    }
+
+    pub fn edit_mode(&self) -> EditMode {
+        if self.whole_file_diff { EditMode::Replace } else { self.mode }
+    }
+
+    // --base-url, then the provider's `[endpoints]` entry, then the built-in URL.
+    pub fn base_url(&self, config: &config::Config, provider: Provider) -> String {
+        self.base_url.as_deref()
+            .or(config.endpoints.get(provider.id()).map(String::as_str))
+            .unwrap_or(provider.default_base_url())
+            .trim_end_matches('/')
+            .to_string()
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Show the effective configuration for the selected provider
+    Config {
+        #[command(subcommand)]
+        list: Option<ConfigList>,
+    },
+    /// Set up a default provider, API key and model interactively
+    Init,
+    /// Check the saved or environment API key of every provider, without sending a completion
+    #[command(visible_alias = "check")]
+    Keys,
+    /// Show latency, failure rate and token totals per provider and model
+    Stats {
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..), help = "Only include requests from the last N days")]
+        days: u64,
+    },
+    /// Run the [[task]] entries of a TOML manifest one after another, recording each result
+    Run {
+        /// Manifest whose [[task]] entries each give a file or glob, a prompt or preset, and optionally a model and auto_apply
+        manifest: PathBuf,
+        #[arg(long, value_name = "PATH", help = "Write one JSON result per task to PATH [default: the manifest's path with .report.jsonl]")]
+        report: Option<PathBuf>,
+        #[arg(long, help = "Skip the tasks an earlier run of this manifest completed")]
+        resume: bool,
+        #[arg(long, help = "Stop at the first task that fails")]
+        fail_fast: bool,
+    },
+    /// Restore the content a file had before the last change coders applied to it
+    Undo {
+        /// File to restore [default: the most recently changed file]
+        file: Option<String>,
+    },
+    /// Show or clear what --with-memory remembers of a file
+    Memory {
+        #[command(subcommand)]
+        action: MemoryAction,
+    },
+    /// Look up models in the providers' live model lists
+    Models {
+        #[command(subcommand)]
+        action: ModelsAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigList {
+    /// List the model aliases defined in the config file
+    Aliases,
+}
+
+#[derive(Subcommand)]
+pub enum MemoryAction {
+    /// List the proposals remembered for a file
+    Show {
+        file: String,
+    },
+    /// Forget the proposals remembered for a file
+    Clear {
+        #[arg(required_unless_present = "all")]
+        file: Option<String>,
+        #[arg(long, conflicts_with = "file", help = "Forget the proposals remembered for every file")]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ModelsAction {
+    /// Show a model's context window, completion limit, price, modality, system message support and description
+    Info {
+        /// Model id, `provider:model` or alias
+        name: String,
+    },
+}
+
+pub fn parse_temperature(value: &str) -> Result<f32, String> {
+    let temperature: f32 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    config::check_range("temperature", temperature, 0.0, 2.0).map_err(|e| e.to_string())?;
+    Ok(temperature)
+}
+
+pub fn parse_top_p(value: &str) -> Result<f32, String> {
+    let top_p: f32 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    config::check_range("top_p", top_p, 0.0, 1.0).map_err(|e| e.to_string())?;
+    Ok(top_p)
+}
+
+pub fn parse_confidence(value: &str) -> Result<f32, String> {
+    let confidence: f32 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    if (0.0..=1.0).contains(&confidence) {
+        Ok(confidence)
+    } else {
+        Err("confidence must be between 0 and 1".to_string())
+    }
+}
+
+// The menu lists the known models; the live listing only adds their context window and
+// price, so a failure to fetch it just leaves them out.
+pub async fn select_model(http: &http::Transport, provider: Provider, base_url: &str, api_key: &str, refresh: bool) -> Result<String> {
+    if provider.models().is_empty() {
+        return Err(CodersError::Config(format!("{} has no model list to choose from", provider.name())).into());
+    }
+    let live = if http.is_replay() {
+        Vec::new()
+    } else {
+        catalog::load(http, provider, base_url, api_key, refresh).await.unwrap_or_else(|_| {
+            println!("Could not fetch the live model list; showing the known models without details.");
+            Vec::new()
+        })
+    };
+    choose_model(&provider.models(), &live)
+}
+
+// `live` supplies the details shown next to each name, when the listing has the model.
+pub fn choose_model(models: &[&str], live: &[catalog::Model]) -> Result<String> {
+    println!("Select a model:");
+    let width = models.iter().map(|model| model.len()).max().unwrap_or(0);
+    for (i, model) in models.iter().enumerate() {
+        let details = live.iter().find(|live| live.id == *model).map(catalog::details).unwrap_or_default();
+        if details.is_empty() {
+            println!("{}. {}", i + 1, model);
+        } else {
+            println!("{}. {:<width$}  {}", i + 1, model, details, width = width);
+        }
+    }
+
+    loop {
+        let input = prompt::ask("Enter the number of your choice: ")?;
+
+        if let Ok(choice) = input.parse::<usize>() {
+            if choice > 0 && choice <= models.len() {
+                return Ok(models[choice - 1].to_string());
+            }
+        }
+
+        println!("Invalid choice. Please try again.");
+    }
+}
+
+// Models whose id contains every `-`, `/`, `.` or `:` separated part of `query`, so that
+// `hermes-405b` finds `nousresearch/hermes-3-llama-3.1-405b`.
+pub fn fuzzy_match_models<'a>(query: &str, models: &[&'a str]) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let parts: Vec<&str> = query.split(['-', '/', '.', ':', ' '])
+        .filter(|part| !part.is_empty())
+        .collect();
+    models.iter()
+        .filter(|model| {
+            let model = model.to_lowercase();
+            !parts.is_empty() && parts.iter().all(|part| model.contains(part))
+        })
+        .copied()
+        .collect()
+}
+
+// `[aliases]` values are `model` or `provider:model`. Model ids can contain ':' themselves
+// (e.g. `:extended` on OpenRouter), so only a known provider id counts as a prefix.
+pub fn model_alias<'c>(config: &'c config::Config, name: &str) -> Option<(Option<Provider>, &'c str)> {
+    let target = config.aliases.get(name)?;
+    match target.split_once(':') {
+        Some((prefix, model)) => match Provider::from_str(prefix, true) {
+            Ok(provider) => Some((Some(provider), model)),
+            Err(_) => Some((None, target)),
+        },
+        None => Some((None, target)),
+    }
+}
+
+// A name from --model-name or `default_model`: an alias, or else a known or fuzzy-matched model.
+pub fn resolve_model(provider: Provider, config: &config::Config, name: &str) -> Result<String> {
+    let Some((_, model)) = model_alias(config, name) else {
+        return resolve_model_name(provider, name);
+    };
+    let shadowed = Provider::value_variants().iter()
+        .any(|provider| provider.models().iter().any(|model| model.eq_ignore_ascii_case(name)));
+    if shadowed {
+        eprintln!("Warning: alias '{}' has the same name as a model id; using the alias.", name);
+    }
+    say!("Using model: {} (alias '{}')", model, name);
+    Ok(model.to_string())
+}
+
+pub fn resolve_model_name(provider: Provider, name: &str) -> Result<String> {
+    let models = provider.models();
+    if let Some(model) = models.iter().find(|model| model.eq_ignore_ascii_case(name)) {
+        return Ok(model.to_string());
+    }
+
+    let matches = fuzzy_match_models(name, &models);
+    match matches.as_slice() {
+        [] => {
+            say!("'{}' is not a known {} model; sending it as-is.", name, provider.name());
+            Ok(name.to_string())
+        }
+        [model] => {
+            say!("Using model: {}", model);
+            Ok(model.to_string())
+        }
+        _ => {
+            println!("'{}' matches several models.", name);
+            choose_model(&matches, &[])
+        }
+    }
+}
+
+/// The `coders` command line, for the binary; not part of the library's API.
+#[doc(hidden)]
+pub async fn cli_main() -> ExitCode {
+    // The diff colors are raw ANSI escapes, which older Windows consoles only honor once
+    // virtual terminal processing is switched on
+    #[cfg(windows)]
+    let _ = colored::control::set_virtual_terminal(true);
+
+    match run_cli().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
+
+pub async fn run_cli() -> Result<()> {
+    if std::env::args_os().len() == 1 && !paths::config_file()?.exists() {
+        println!("No configuration found. Run `coders init` to pick a provider, enter an API key and choose a default model.");
+        println!("Run `coders --help` to see all options.");
+        return Ok(());
+    }
+
+    let cli = Cli::parse();
+    if let Some(dir) = &cli.config_dir {
+        paths::set_config_dir_override(dir.clone());
+    }
+    if cli.quiet {
+        console::set_quiet();
+    }
+    let config = config::load_config()?;
+    if cli.format(&config) == DiffFormat::Ascii {
+        console::set_ascii();
+    }
+    let secrets = secrets::Scanner::new(&config.secret_patterns).map_err(CodersError::Config)?;
+    let examples = read_examples(&cli.examples)?;
+
+    let provider = cli.provider(&config);
+    if cli.openrouter {
+        eprintln!("Warning: -o/--openrouter is deprecated and will be removed in a future release; use --provider openrouter instead.");
+    }
+    for name in config.providers.keys() {
+        if Provider::from_str(name, true).is_err() {
+            eprintln!("Warning: ignoring config section [providers.{}]: unknown provider", name);
+        }
+    }
+
+    for name in config.endpoints.keys() {
+        if Provider::from_str(name, true).is_err() {
+            eprintln!("Warning: ignoring [endpoints] entry '{}': unknown provider", name);
+        }
+    }
+
+    if let Some((Some(aliased), _)) = cli.model_name.as_deref().and_then(|name| model_alias(&config, name)) {
+        if aliased != provider {
+            return Err(CodersError::Config(format!(
+                "'{}' is an alias for a {} model, but the provider was set to {}",
+                cli.model_name.as_deref().unwrap_or_default(), aliased.name(), provider.name()
+            )).into());
+        }
+    }
+
+    routing::validate(&config)?;
+    if let Some(name) = &config.provider {
+        if Provider::from_str(name, true).is_err() && !name.eq_ignore_ascii_case("auto") {
+            eprintln!("Warning: ignoring unknown provider '{}' in the config file", name);
+        }
+    }
+
+    match &cli.command {
+        Some(Command::Config { list: None }) => return show_config(&cli, &config),
+        Some(Command::Config { list: Some(ConfigList::Aliases) }) => return show_aliases(&config),
+        Some(Command::Init) => return init::run_init(&cli, &config).await,
+        Some(Command::Keys) => return keys::run_check(&cli, &config).await,
+        Some(Command::Stats { days }) => return history::print_stats(*days),
+        Some(Command::Undo { file }) => return undo::run_undo(file.as_deref()),
+        Some(Command::Memory { action: MemoryAction::Show { file } }) => return memory::show(file),
+        Some(Command::Memory { action: MemoryAction::Clear { file, .. } }) => return memory::clear(file.as_deref()),
+        Some(Command::Models { action: ModelsAction::Info { name } }) => return catalog::run_info(&cli, &config, name).await,
+        Some(Command::Run { .. }) if cli.file.is_some() || cli.since.is_some() || cli.files_from.is_some() || cli.batch.is_some() || cli.output.is_some() => {
+            return Err(CodersError::Config("coders run takes its files from the manifest; drop --file, --since, --files-from, --batch and --output".to_string()).into());
+        }
+        Some(Command::Run { .. }) => {}
+        None => {}
+    }
+
+    if cli.mode == EditMode::Append && cli.structured_merge {
+        return Err(CodersError::Config("--structured-merge rebuilds the whole document, so it can't be combined with --mode append".to_string()).into());
+    }
+    if cli.mode == EditMode::Append && (cli.strict_scope || cli.lines.is_some()) {
+        return Err(CodersError::Config("--mode append only adds code at the end, so it can't be combined with --lines or --strict-scope".to_string()).into());
+    }
+
+    if cli.reset {
+        reset_api_key(provider.name())?;
+        return Ok(());
+    }
+
+    // Read before the prompt is asked for, so a bad path doesn't waste it
+    let image = cli.image.as_deref().map(image::data_url).transpose()?;
+    let preset = cli.messages_file.as_deref().map(messages::read).transpose()?;
+    let batch = cli.batch.as_deref().map(batch::read).transpose()?;
+    let manifest = match &cli.command {
+        Some(Command::Run { manifest, .. }) => Some(taskfile::read(manifest, &config.presets)?),
+        _ => None,
+    };
+    let files = match (&batch, &manifest) {
+        (Some(entries), _) => entries.iter().map(|entry| entry.file.clone()).collect(),
+        (_, Some(entries)) => entries.iter().flat_map(|entry| entry.files.iter().cloned()).collect(),
+        _ => target_files(&cli)?,
+    };
+    if files.is_empty() {
+        say!("No files to process.");
+        return Ok(());
+    }
+
+    let routed = routing::route(&cli, &config, &files, cli.explain_routing)?;
+    let provider = routed.as_ref().map_or(provider, |(provider, _)| *provider);
+
+    let azure = match provider {
+        Provider::Azure => Some(azure::Deployment::resolve(
+            cli.azure_endpoint.as_deref(),
+            cli.azure_deployment.as_deref(),
+            cli.azure_api_version.as_deref(),
+            config.provider(provider.id()),
+        )?),
+        _ => None,
+    };
+    if azure.is_some() && (cli.base_url.is_some() || config.endpoints.contains_key(provider.id())) {
+        eprintln!("Warning: Azure ignores --base-url and [endpoints]; set the resource with --azure-endpoint or [providers.azure] endpoint.");
+    }
+    let base_url = cli.base_url(&config, provider);
+
+    let mut http = http::Transport::new(cli.record.clone(), cli.replay.clone(), cli.headers.clone(), config.allowed_hosts.clone());
+    http.set_timeout(cli.timeout.map(Duration::from_secs));
+    if cli.show_context {
+        let json = cli.format(&config) == DiffFormat::Json;
+        http.set_review(move |url, body| preview::confirm(url, body, json));
+    }
+    http.set_hooks(hooks::Hooks::new(config.pre_send_hook.clone(), config.post_receive_hook.clone(), config.hook_timeout, cli.verbose));
+    let profile = profile::Profile::new(cli.profile);
+
+    if !http.is_replay() {
+        let url = azure.as_ref().map_or(base_url.as_str(), |deployment| deployment.endpoint.as_str());
+        privacy::acknowledge(&config, provider, url, cli.accept_terms)?;
+    }
+
+    // Replayed sessions never reach a provider, so no key is needed
+    let api_key = if http.is_replay() {
+        String::new()
+    } else {
+        let started = Instant::now();
+        let api_key = get_or_prompt_for_api_key(&http, provider.name(), &base_url, azure.as_ref(), !cli.offline_validate, !cli.no_save_key).await?;
+        profile.add("key validation", started.elapsed());
+        api_key
+    };
+
+    let verifier = match &cli.verify_model {
+        Some(name) => Some(resolve_verifier(&cli, &config, &http, name, provider, &base_url, &api_key).await?),
+        None => None,
+    };
+    if let Some(verifier) = verifier.as_ref().filter(|verifier| verifier.provider != provider && !http.is_replay()) {
+        privacy::acknowledge(&config, verifier.provider, &verifier.base_url, cli.accept_terms)?;
+    }
+
+    // Batch and manifest entries bring their own prompts
+    let prompt = if batch.is_some() || manifest.is_some() {
+        String::new()
+    } else {
+        with_prompt_prefix(cli.prompt_prefix.as_deref().or(config.prompt_prefix.as_deref()), prompt_for_user_input()?)
+    };
+
+    let model = if let Some(deployment) = &azure {
+        if cli.model || cli.model_name.is_some() {
+            say!("Azure routes requests by deployment; ignoring the model selection.");
+        }
+        deployment.name.clone()
+    } else if let Some((_, model)) = routed {
+        model
+    } else if cli.model {
+        select_model(&http, provider, &base_url, &api_key, cli.refresh).await?
+    } else if let Some(name) = &cli.model_name {
+        resolve_model(provider, &config, name)?
+    } else {
+        default_model(provider, &config)
+    };
+
+    // Only a --deadline gives the fallback a reason to step in
+    let fallback_model = match cli.fallback_model.as_ref().or(config.fallback_model.as_ref()).filter(|_| cli.deadline.is_some()) {
+        Some(_) if azure.is_some() => {
+            return Err(CodersError::Config("--fallback-model doesn't work with Azure, which routes requests by deployment".to_string()).into());
+        }
+        Some(name) => Some(resolve_model(provider, &config, name)?),
+        None => None,
+    };
+
+    let mut options = GenerationOptions::from_cli(&cli, &config, provider);
+    options.messages = preset;
+    if cli.stream && provider != Provider::OpenRouter {
+        say!("Streaming is only supported for OpenRouter; waiting for the full response instead.");
+    }
+    if options.tools {
+        let completion_model = !Capabilities::of(provider, &model).chat;
+        if matches!(provider, Provider::Gemini | Provider::Cohere) || completion_model {
+            return Err(CodersError::Config(format!("--tools needs an OpenAI-compatible chat model; {} is not supported", if completion_model { &model } else { provider.name() })).into());
+        }
+        if options.stream {
+            say!("Streaming is not used with --tools; waiting for the full response instead.");
+            options.stream = false;
+        }
+    }
+    if options.stream && files.len() > 1 && cli.jobs > 1 && !cli.show_context {
+        say!("Streaming is not used with several requests in flight; waiting for the full responses instead.");
+        options.stream = false;
+    }
+    if options.stream && !http.is_live() {
+        say!("Streaming is disabled while recording or replaying; waiting for the full response instead.");
+        options.stream = false;
+    }
+    let audit_dir = cli.audit_dir.clone().or_else(|| config.audit_dir.clone());
+    if options.stream && audit_dir.is_some() {
+        say!("Streaming is disabled while auditing; waiting for the full response instead.");
+        options.stream = false;
+    }
+    if options.stream && (config.pre_send_hook.is_some() || config.post_receive_hook.is_some()) {
+        say!("Streaming is disabled with a pre_send_hook or post_receive_hook; waiting for the full response instead.");
+        options.stream = false;
+    }
+    if let Some(seed) = options.seed {
+        say!("Using seed: {}", seed);
+    }
+
+    // Every file of the run is appended to --diff-out, so start from an empty file
+    if let Some(path) = &cli.diff_out {
+        fs::write(path, "").with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    let report_prompt = match (&cli.batch, &cli.command) {
+        (Some(path), _) | (_, Some(Command::Run { manifest: path, .. })) => format!("(per entry, from {})", path.display()),
+        _ => prompt.clone(),
+    };
+    let report = cli.export_html.clone().map(|path| RefCell::new(export::HtmlReport::new(path, &report_prompt, &model)));
+
+    // An entry's own model, checked against the provider like --model-name
+    let entry_model = |name: Option<&str>, source: &str| -> Result<String> {
+        match name {
+            Some(name) if azure.is_none() => {
+                if let Some((Some(aliased), _)) = model_alias(&config, name).filter(|(aliased, _)| *aliased != Some(provider)) {
+                    return Err(CodersError::Config(format!(
+                        "{}: '{}' is an alias for a {} model, but the provider is {}",
+                        source, name, aliased.name(), provider.name()
+                    )).into());
+                }
+                Ok(resolve_model(provider, &config, name)?)
+            }
+            _ => Ok(model.clone()),
+        }
+    };
+    let runs = manifest.map(|entries| entries.into_iter()
+        .map(|entry| {
+            let model = entry_model(entry.model.as_deref(), &format!("task {}", entry.number))?;
+            let tasks = entry.files.iter()
+                .map(|file| Task { file: file.clone(), prompt: entry.prompt.clone(), model: model.clone(), auto_apply: entry.auto_apply })
+                .collect();
+            Ok((entry, tasks))
+        })
+        .collect::<Result<Vec<(taskfile::Entry, Vec<Task>)>>>())
+        .transpose()?;
+    let tasks = match (batch, &runs) {
+        (Some(entries), _) => entries.into_iter()
+            .map(|entry| {
+                let model = entry_model(entry.model.as_deref(), &entry.file)?;
+                Ok(Task { file: entry.file, prompt: entry.prompt, model, auto_apply: false })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        (_, Some(runs)) => runs.iter().flat_map(|(_, tasks)| tasks.iter().cloned()).collect(),
+        _ => files.into_iter()
+            .map(|file| Task { file, prompt: prompt.clone(), model: model.clone(), auto_apply: false })
+            .collect(),
+    };
+
+    // Batch and manifest entries can each pick a model, so every distinct one is checked
+    let free: HashSet<&str> = tasks.iter()
+        .filter_map(|task| OpenRouterModel::from_id(&task.model))
+        .filter(|model| provider == Provider::OpenRouter && model.is_free())
+        .map(|model| model.as_str())
+        .collect();
+    for model in free {
+        say!("Note: {} is a free variant; OpenRouter limits free models to {} requests a minute and {} a day, so larger batches will be throttled.", model, FREE_TIER_PER_MINUTE, FREE_TIER_PER_DAY);
+    }
+    if image.is_some() {
+        if matches!(provider, Provider::Gemini | Provider::Cohere) {
+            return Err(CodersError::Config(format!("--image needs an OpenAI-compatible provider; {} is not supported", provider.name())).into());
+        }
+        let models: HashSet<&str> = tasks.iter().map(|task| task.model.as_str()).collect();
+        let text_only = |model: &&str| {
+            let capabilities = Capabilities::of(provider, model);
+            !capabilities.chat || !capabilities.vision
+        };
+        if let Some(model) = models.into_iter().find(text_only) {
+            return Err(CodersError::Config(format!("--image needs a vision model, and {} doesn't accept images; pick one with --model-name", model)).into());
+        }
+        options.image = image;
+    }
+    let audit = audit_dir.map(|dir| audit::Audit::start(&dir, &api_key)).transpose()?;
+    let session = Session {
+        cli: &cli,
+        provider,
+        http,
+        base_url,
+        api_key,
+        options,
+        retry_on_empty: cli.retry_on_empty.or(config.retry_on_empty).unwrap_or(0),
+        retry_on_malformed: cli.retry_on_malformed.or(config.retry_on_malformed).unwrap_or(DEFAULT_RETRY_ON_MALFORMED),
+        azure,
+        format: cli.format(&config),
+        strip_phrases: config.strip_phrases.clone(),
+        report,
+        feedback_loop: !cli.no_feedback_loop && config.feedback_loop.unwrap_or(true),
+        profile,
+        audit,
+        proposals: RefCell::new(Vec::new()),
+        usage: RefCell::new(HashMap::new()),
+        stats: RefCell::new(HashMap::new()),
+        secrets,
+        redactions: RefCell::new(HashMap::new()),
+        fallback_model,
+        examples,
+        verifier,
+    };
+
+    let result = match (&cli.command, runs) {
+        (Some(Command::Run { manifest, report, resume, fail_fast }), Some(runs)) => {
+            let report = report.clone().unwrap_or_else(|| manifest.with_extension("report.jsonl"));
+            run_manifest(&session, manifest, &runs, &report, *resume, *fail_fast).await
+        }
+        _ => process_files(&session, &tasks).await.and_then(|()| check_gate(&session, tasks.len())),
+    };
+    session.profile.print();
+    result
+}
+
+// `default_model` may be an alias, as long as it doesn't belong to another provider.
+pub fn default_model(provider: Provider, config: &config::Config) -> String {
+    let Some(name) = config.provider(provider.id()).and_then(|p| p.default_model.as_deref()) else {
+        return provider.default_model().to_string();
+    };
+    match model_alias(config, name) {
+        Some((aliased, model)) if aliased.is_none_or(|aliased| aliased == provider) => model.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+pub fn show_config(cli: &Cli, config: &config::Config) -> Result<()> {
+    let provider = cli.provider(config);
+    let options = GenerationOptions::from_cli(cli, config, provider);
+    let model = match &cli.model_name {
+        Some(name) => model_alias(config, name).map_or(name.as_str(), |(_, model)| model).to_string(),
+        None => default_model(provider, config),
+    };
+
+    println!("config file:       {}", paths::config_file()?.display());
+    if let Some(project) = config::project_config_file() {
+        println!("project config:    {}", project.display());
+    }
+    println!("provider:          {}", provider.id());
+    if provider == Provider::Azure {
+        let section = config.provider(provider.id());
+        let setting = |cli_value: &Option<String>, config_value: Option<&String>| {
+            cli_value.as_ref().or(config_value).cloned().unwrap_or_else(|| "not set".to_string())
+        };
+        println!("endpoint:          {}", setting(&cli.azure_endpoint, section.and_then(|c| c.endpoint.as_ref())));
+        println!("deployment:        {}", setting(&cli.azure_deployment, section.and_then(|c| c.deployment.as_ref())));
+        println!("api_version:       {}", cli.azure_api_version.as_deref()
+            .or(section.and_then(|c| c.api_version.as_deref()))
+            .unwrap_or(azure::DEFAULT_API_VERSION));
+    } else {
+        println!("base_url:          {}", cli.base_url(config, provider));
+        println!("model:             {}", model);
+    }
+    println!("temperature:       {}", options.temperature);
+    println!("top_p:             {}", options.top_p);
+    println!("max_tokens:        {}", options.max_tokens.map_or("provider default".to_string(), |t| t.to_string()));
+    println!("seed:              {}", options.seed.map_or("none".to_string(), |s| s.to_string()));
+    println!("stop:              {:?}", options.stop);
+    println!("frequency_penalty: {}", options.frequency_penalty.map_or("none".to_string(), |p| p.to_string()));
+    println!("presence_penalty:  {}", options.presence_penalty.map_or("none".to_string(), |p| p.to_string()));
+    println!("format:            {}", match cli.format(config) { DiffFormat::Color => "color", DiffFormat::Plain => "plain", DiffFormat::Json => "json", DiffFormat::Ascii => "ascii" });
+    println!("retry_on_empty:    {}", cli.retry_on_empty.or(config.retry_on_empty).unwrap_or(0));
+    println!("retry_on_malformed: {}", cli.retry_on_malformed.or(config.retry_on_malformed).unwrap_or(DEFAULT_RETRY_ON_MALFORMED));
+    println!("feedback_loop:     {}", !cli.no_feedback_loop && config.feedback_loop.unwrap_or(true));
+    if let Some(model) = cli.fallback_model.as_ref().or(config.fallback_model.as_ref()) {
+        println!("fallback_model:    {}{}", model, if cli.deadline.is_some() { "" } else { " (only used with --deadline)" });
+    }
+    if let Some(prefix) = cli.prompt_prefix.as_deref().or(config.prompt_prefix.as_deref()).filter(|prefix| !prefix.is_empty()) {
+        println!("prompt_prefix:     {:?}", prefix);
+    }
+    if let Some(dir) = cli.audit_dir.as_ref().or(config.audit_dir.as_ref()) {
+        println!("audit_dir:         {}", dir.display());
+    }
+    if let Some(hook) = &config.pre_send_hook {
+        println!("pre_send_hook:     {}", hook);
+    }
+    if let Some(hook) = &config.post_receive_hook {
+        println!("post_receive_hook: {}", hook);
+    }
+    if !config.allowed_hosts.is_empty() {
+        println!("allowed_hosts:     {}", config.allowed_hosts.join(", "));
+    }
+    if !config.routing.is_empty() {
+        let auto = config.provider.as_deref().is_some_and(|provider| provider.eq_ignore_ascii_case("auto"));
+        println!("routing:           {} rule(s){}", config.routing.len(), if auto { "" } else { ", off (provider is not \"auto\")" });
+    }
+    Ok(())
+}
+
+pub fn show_aliases(config: &config::Config) -> Result<()> {
+    if config.aliases.is_empty() {
+        println!("No aliases defined. Add an [aliases] table to {}.", paths::config_file()?.display());
+        return Ok(());
+    }
+    let mut aliases: Vec<(&String, &String)> = config.aliases.iter().collect();
+    aliases.sort();
+    let width = aliases.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, target) in aliases {
+        println!("{:<width$}  {}", name, target, width = width);
+    }
+    Ok(())
+}
+
+pub fn target_files(cli: &Cli) -> Result<Vec<String>> {
+    let mut files: Vec<String> = cli.file.iter().cloned().collect();
+    if let Some(since) = &cli.since {
+        for file in git::changed_files_since(since)? {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if let Some(manifest) = &cli.files_from {
+        for file in read_file_list(manifest)? {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Paths in a --files-from manifest are relative to the current directory. Missing files are
+// an error before anything is sent; files matched by git's ignore rules are skipped.
+pub fn read_file_list(manifest: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read {}", manifest.display()))?;
+    let cwd = std::env::current_dir()?;
+
+    let mut files = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let path = line.trim();
+        if path.is_empty() || path.starts_with('#') {
+            continue;
+        }
+        if !Path::new(path).is_file() {
+            return Err(anyhow::anyhow!("{}:{}: {} is not a file", manifest.display(), number + 1, path));
+        }
+        if git::is_ignored(&cwd, path).unwrap_or(false) {
+            say!("Skipping {}: ignored by git", path);
+            continue;
+        }
+        files.push(path.to_string());
+    }
+    Ok(files)
+}
+
+pub fn reset_api_key(provider: &str) -> Result<()> {
+    let config_file = paths::api_key_file(provider)?;
+
+    if config_file.exists() {
+        std::fs::remove_file(&config_file)?;
+        println!("{} API key has been reset. You will be prompted for a new key on the next run.", provider);
+    } else {
+        println!("No existing {} API key found. You will be prompted for a key on the next run.", provider);
+    }
+    
+    Ok(())
+}
+
+// With `validate` unset the key is trusted as-is, so no request is made before the first
+// completion. A typed key is saved when `save` is set and the config directory is writable;
+// otherwise it lasts for this run.
+pub async fn get_or_prompt_for_api_key(http: &http::Transport, api_name: &str, base_url: &str, azure: Option<&azure::Deployment>, validate: bool, save: bool) -> Result<String> {
+    // An environment variable such as GROQ_API_KEY takes precedence over the saved key
+    let env_var = format!("{}_API_KEY", api_name.to_uppercase());
+    if let Ok(api_key) = std::env::var(&env_var) {
+        if !api_key.trim().is_empty() {
+            if !validate || validate_api_key(http, api_name, base_url, api_key.trim(), azure).await? {
+                return Ok(api_key.trim().to_string());
+            }
+            return Err(CodersError::ApiKeyInvalid { provider: api_name.to_string() })
+                .with_context(|| format!("The key in {} was rejected", env_var));
+        }
+    }
+
+    let config_file = paths::api_key_file(api_name)?;
+    let saved = fs::read_to_string(&config_file).ok()
+        .map(|api_key| api_key.trim().to_string())
+        .filter(|api_key| !api_key.is_empty());
+
+    // A typed key is only written once it is known to work (or validation is off), so
+    // aborting a prompt never leaves a bad key file behind
+    let mut api_key = match &saved {
+        Some(api_key) => api_key.clone(),
+        None => prompt_for_api_key(api_name)?,
+    };
+    if validate {
+        while !validate_api_key(http, api_name, base_url, &api_key, azure).await? {
+            println!("Invalid {} API key. Please enter a valid key (q to quit).", api_name);
+            api_key = prompt_for_api_key(api_name)?;
+        }
+    }
+    if saved.as_ref() != Some(&api_key) {
+        let validated = if validate { "validated and " } else { "" };
+        if !save {
+            say!("{} API key {}used for this run only (--no-save-key)", api_name, validated);
+        } else if let Err(e) = save_api_key(&config_file, &api_key) {
+            eprintln!(
+                "Warning: could not save the {} API key to {}: {:#}. Using it for this run only; pass --no-save-key to skip saving, or set {} instead of typing the key.",
+                api_name, config_file.display(), e, env_var
+            );
+        } else {
+            println!("{} API key {}saved successfully", api_name, validated);
+        }
+    }
+    Ok(api_key)
+}
+
+pub async fn validate_api_key(http: &http::Transport, api_name: &str, base_url: &str, api_key: &str, azure: Option<&azure::Deployment>) -> Result<bool> {
+    if let ("Azure", Some(deployment)) = (api_name, azure) {
+        return azure::validate_api_key(http, deployment, api_key).await;
+    }
+
+    let (url, auth) = models_endpoint(api_name, base_url, api_key)
+        .ok_or_else(|| anyhow::anyhow!("Unknown API provider"))?;
+
+    let response = http.get(&url, &[auth]).await?;
+
+    Ok(response.status.is_success())
+}
+
+// The provider's model list, which doubles as the cheapest authenticated request for
+// checking a key. Azure lists deployments per resource instead, see `azure::validate_api_key`.
+pub fn models_endpoint(api_name: &str, base_url: &str, api_key: &str) -> Option<(String, (&'static str, String))> {
+    let auth_header = match api_name {
+        "Hyperbolic" | "OpenRouter" | "Groq" | "Cohere" => "Authorization",
+        "Gemini" => "x-goog-api-key",
+        _ => return None,
+    };
+    let url = format!("{}/models", base_url);
+    let auth_value = if auth_header == "Authorization" {
+        format!("Bearer {}", api_key)
+    } else {
+        api_key.to_string()
+    };
+    Some((url, (auth_header, auth_value)))
+}
+
+pub fn save_api_key(config_file: &Path, api_key: &str) -> Result<()> {
+    if let Some(dir) = config_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(config_file, api_key)?;
+    Ok(())
+}
+
+pub fn prompt_for_api_key(api_name: &str) -> Result<String> {
+    prompt::ask(&format!("Enter your {} API key: ", api_name))
+}
+
+pub fn prompt_for_user_input() -> Result<String> {
+    prompt::ask_with_history("Enter your prompt: ", &paths::state_dir()?.join("prompt_history"))
+}
+
+// `--prompt-prefix`: the prefix and the typed prompt, with a space between them unless the
+// prefix ends in whitespace already. The history keeps what was typed.
+pub fn with_prompt_prefix(prefix: Option<&str>, prompt: String) -> String {
+    match prefix {
+        Some(prefix) if !prefix.is_empty() && !prefix.ends_with(char::is_whitespace) => format!("{} {}", prefix, prompt),
+        Some(prefix) => format!("{}{}", prefix, prompt),
+        None => prompt,
+    }
+}
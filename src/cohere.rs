@@ -0,0 +1,90 @@
+use crate::edit::GenerationOptions;
+use crate::error::CodersError;
+use crate::http;
+use crate::messages::{self, Message, Role};
+use crate::openai_compat;
+use crate::prompts::{system_prompt, user_message, DEFAULT_EXAMPLES, DEFAULT_SYSTEM_PROMPT};
+use crate::render::display_waiting_message;
+use anyhow::Result;
+use serde_json::json;
+
+pub async fn send_request_to_cohere(http: &http::Transport, base_url: &str, api_key: &str, context: &str, model: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    let user_message = user_message(language, context);
+    let system = system_prompt(DEFAULT_SYSTEM_PROMPT, language, options);
+
+    let (preamble, history) = match &options.messages {
+        Some(preset) => (messages::system(preset), preset_history(preset)),
+        None => (Some(system), chat_history(options.examples(DEFAULT_EXAMPLES))),
+    };
+
+    let mut request_body = json!({
+        "model": model,
+        "chat_history": history,
+        "message": user_message,
+        "max_tokens": 2048,
+    });
+    if let Some(preamble) = preamble {
+        request_body["preamble"] = json!(preamble);
+    }
+    apply_options(options, &mut request_body);
+
+    let response = openai_compat::post_timed(http, "Cohere", &format!("{}/chat", base_url), &[("Authorization", format!("Bearer {}", api_key))], &request_body).await?;
+    if !response.status.is_success() {
+        return Err(CodersError::from_response("Cohere", response.status.as_u16(), &response.body).into());
+    }
+
+    let spinner = display_waiting_message("Processing response...");
+    say!("Response body: {}", response.body);
+    if response.body.is_empty() {
+        spinner.finish_and_clear();
+        return Ok(None);
+    }
+    let json_response = openai_compat::parse_body("Cohere", &response.body)?;
+    spinner.finish_and_clear();
+    Ok(json_response["text"].as_str().map(String::from))
+}
+
+// Cohere takes the system prompt as `preamble`, the new message on its own, and earlier
+// turns as `chat_history` with USER/CHATBOT roles.
+fn chat_history(examples: &[(&str, &str)]) -> Vec<serde_json::Value> {
+    examples.iter()
+        .flat_map(|(user, assistant)| [
+            json!({"role": "USER", "message": user}),
+            json!({"role": "CHATBOT", "message": assistant}),
+        ])
+        .collect()
+}
+
+// The non-system messages of a --messages-file as `chat_history`; the system ones go in the
+// preamble.
+fn preset_history(preset: &[Message]) -> Vec<serde_json::Value> {
+    preset.iter()
+        .filter_map(|message| match message.role {
+            Role::System => None,
+            Role::User => Some(json!({"role": "USER", "message": message.content})),
+            Role::Assistant => Some(json!({"role": "CHATBOT", "message": message.content})),
+        })
+        .collect()
+}
+
+// Same settings as `GenerationOptions::apply_to`, under Cohere's parameter names.
+fn apply_options(options: &GenerationOptions, request_body: &mut serde_json::Value) {
+    request_body["temperature"] = json!(options.temperature);
+    // Cohere rejects p above 0.99, which --deterministic would otherwise send
+    request_body["p"] = json!(options.top_p.min(0.99));
+    if let Some(max_tokens) = options.max_tokens {
+        request_body["max_tokens"] = json!(max_tokens);
+    }
+    if let Some(seed) = options.seed {
+        request_body["seed"] = json!(seed);
+    }
+    if !options.stop.is_empty() {
+        request_body["stop_sequences"] = json!(options.stop);
+    }
+    if let Some(frequency_penalty) = options.frequency_penalty {
+        request_body["frequency_penalty"] = json!(frequency_penalty);
+    }
+    if let Some(presence_penalty) = options.presence_penalty {
+        request_body["presence_penalty"] = json!(presence_penalty);
+    }
+}
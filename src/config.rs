@@ -0,0 +1,337 @@
+use crate::error::CodersError;
+use crate::paths;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Provider used when `--provider` is not given; `auto` picks one with `routing`.
+    pub provider: Option<String>,
+    /// `false` makes plain diff output the default.
+    pub color: Option<bool>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub seed: Option<u64>,
+    pub deterministic: bool,
+    pub stop: Vec<String>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// When non-empty, requests to any other host are refused before they are sent.
+    pub allowed_hosts: Vec<String>,
+    /// Where `--audit-dir` artifacts go when the flag isn't given; auditing is off when unset.
+    pub audit_dir: Option<std::path::PathBuf>,
+    /// Extra lead-in/sign-off phrases stripped from the start and end of extracted code.
+    pub strip_phrases: Vec<String>,
+    /// How many times to retry a successful response with empty content.
+    pub retry_on_empty: Option<u32>,
+    /// How many times to resend a request whose response body isn't valid JSON.
+    pub retry_on_malformed: Option<u32>,
+    /// `false` ends the session after a rejected proposal instead of asking for feedback.
+    pub feedback_loop: Option<bool>,
+    /// Put in front of every prompt typed at `Enter your prompt:`, e.g. "Refactor for readability:".
+    pub prompt_prefix: Option<String>,
+    /// Model (or alias) asked instead when the first one hasn't answered by half the `--deadline`.
+    pub fallback_model: Option<String>,
+    /// Command run on each completion request body (JSON on stdin) before it is sent; what it
+    /// prints is sent instead. Failing stops the request.
+    pub pre_send_hook: Option<String>,
+    /// Command run on each successful response body before the answer is read from it; what
+    /// it prints is used instead. Failing leaves the response as it came.
+    pub post_receive_hook: Option<String>,
+    /// Seconds either hook may run; 30 when unset.
+    pub hook_timeout: Option<u64>,
+    /// Added to the privacy notice shown before the first request to each provider, e.g. a
+    /// company policy on what may be sent.
+    pub privacy_notice: Option<String>,
+    /// Extra system-prompt instructions keyed by detected language (e.g. `rust`, `python`).
+    pub language_prompts: HashMap<String, String>,
+    /// More secrets to look for before a request is sent, as regular expressions keyed by the
+    /// name a warning calls them. A `value` group marks the secret within the match.
+    pub secret_patterns: HashMap<String, String>,
+    /// Base URL per provider id, e.g. `openrouter = "https://my-mirror/api/v1"`.
+    pub endpoints: HashMap<String, String>,
+    /// Short names for models, e.g. `fast = "hyperbolic:meta-llama/Meta-Llama-3.1-8B-Instruct"`;
+    /// the provider prefix is optional.
+    pub aliases: HashMap<String, String>,
+    /// Named prompts that `coders run` tasks can use with `preset = "<name>"`.
+    pub presets: HashMap<String, String>,
+    /// Overrides applied only while the named provider is active, e.g. `[providers.openrouter]`.
+    pub providers: HashMap<String, ProviderConfig>,
+    /// `[[routing]]` tiers for `provider = "auto"`, smallest first.
+    pub routing: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingRule {
+    /// The rule matches files estimated below this many tokens; the last rule may leave it
+    /// out to catch everything else.
+    pub below_tokens: Option<usize>,
+    /// `provider:model`, or an alias with a provider prefix.
+    pub model: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    pub default_model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Azure only: resource endpoint, deployment name and `api-version`.
+    pub endpoint: Option<String>,
+    pub deployment: Option<String>,
+    pub api_version: Option<String>,
+    /// What the privacy notice says about how long the provider keeps requests.
+    pub retention_note: Option<String>,
+    /// Counts the privacy notice as acknowledged, so it is never shown.
+    pub terms_accepted: bool,
+}
+
+/// The user's config file merged with the project's (see `project_config_file`). Tables
+/// are merged key by key and the project's values win; anything else, arrays included, is
+/// replaced whole. Command-line flags still override both.
+pub fn load_config() -> Result<Config, CodersError> {
+    let user_path = paths::config_file()?;
+    let mut table = read(&user_path)?.unwrap_or_default();
+    let Some(project_path) = project_config_file() else {
+        return into_config(table, &user_path);
+    };
+    let Some(mut project) = read(&project_path)? else {
+        return into_config(table, &user_path);
+    };
+    restrict_project(&mut project, &project_path)?;
+    merge(&mut table, project);
+    into_config(table, &project_path)
+}
+
+/// `.coders/config.toml` in the current directory or the closest parent that has one, the way
+/// git finds `.git`, so a team can commit shared settings with the code. A `--config-dir` that
+/// happens to be such a directory is still only the user config.
+pub fn project_config_file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let user = paths::config_file().ok().and_then(|path| fs::canonicalize(path).ok());
+    cwd.ancestors()
+        .map(|dir| dir.join(".coders").join("config.toml"))
+        .find(|path| path.is_file() && fs::canonicalize(path).ok() != user)
+}
+
+// Each file is checked on its own first, so a mistake is reported against the file it's in.
+fn read(path: &Path) -> Result<Option<toml::Table>, CodersError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)?;
+    let table: toml::Table = toml::from_str(&contents)
+        .map_err(|e| CodersError::Config(format!("Failed to parse config file {}: {}", path.display(), e)))?;
+    into_config(table.clone(), path)?;
+    Ok(Some(table))
+}
+
+fn into_config(table: toml::Table, path: &Path) -> Result<Config, CodersError> {
+    let config: Config = toml::Value::Table(table).try_into()
+        .map_err(|e| CodersError::Config(format!("Failed to parse config file {}: {}", path.display(), e)))?;
+    config.validate()
+        .map_err(|e| CodersError::Config(format!("Invalid config file {}: {}", path.display(), e)))?;
+    Ok(config)
+}
+
+// A project config comes with the code, so it can't redirect requests, and the API key in
+// them, to another host, nor run commands on them, nor change the user's `allowed_hosts`.
+// Its `audit_dir` must be inside the project, and is relative to it rather than to wherever
+// the tool runs.
+fn restrict_project(project: &mut toml::Table, path: &Path) -> Result<(), CodersError> {
+    let redirects = project.contains_key("endpoints") || project.get("providers")
+        .and_then(toml::Value::as_table)
+        .is_some_and(|providers| providers.values().any(|provider| provider.get("endpoint").is_some()));
+    if redirects {
+        return Err(CodersError::Config(format!(
+            "{} sets an endpoint, which only the user config file or --base-url may do, since requests carry your API key",
+            path.display()
+        )));
+    }
+    let accepts = project.get("providers")
+        .and_then(toml::Value::as_table)
+        .is_some_and(|providers| providers.values().any(|provider| provider.get("terms_accepted").is_some()));
+    if accepts {
+        return Err(CodersError::Config(format!(
+            "{} sets terms_accepted, which only the user config file may do, since it acknowledges a privacy notice for you",
+            path.display()
+        )));
+    }
+    if let Some(hook) = ["pre_send_hook", "post_receive_hook"].into_iter().find(|hook| project.contains_key(*hook)) {
+        return Err(CodersError::Config(format!(
+            "{} sets {}, which only the user config file may do, since it runs a command on every request",
+            path.display(), hook
+        )));
+    }
+    // An empty list lifts the user's allow-list, and any other one replaces it
+    if project.contains_key("allowed_hosts") {
+        return Err(CodersError::Config(format!(
+            "{} sets allowed_hosts, which only the user config file may do, since it would replace your own list",
+            path.display()
+        )));
+    }
+    let root = path.parent().and_then(Path::parent).unwrap_or(Path::new("."));
+    if let Some(toml::Value::String(dir)) = project.get_mut("audit_dir") {
+        // Artifacts hold your code, so they stay inside the project
+        let relative = Path::new(dir.as_str());
+        if !relative.is_relative() || relative.components().any(|component| component == std::path::Component::ParentDir) {
+            return Err(CodersError::Config(format!(
+                "{} sets audit_dir to '{}', which is outside the project; a project config may only name a directory inside it",
+                path.display(), dir
+            )));
+        }
+        *dir = root.join(dir.as_str()).to_string_lossy().into_owned();
+    }
+    Ok(())
+}
+
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), CodersError> {
+        if let Some(temperature) = self.temperature {
+            check_range("temperature", temperature, 0.0, 2.0)?;
+        }
+        if let Some(top_p) = self.top_p {
+            check_range("top_p", top_p, 0.0, 1.0)?;
+        }
+        if let Some(frequency_penalty) = self.frequency_penalty {
+            check_range("frequency_penalty", frequency_penalty, -2.0, 2.0)?;
+        }
+        if let Some(presence_penalty) = self.presence_penalty {
+            check_range("presence_penalty", presence_penalty, -2.0, 2.0)?;
+        }
+        for (name, url) in &self.endpoints {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(CodersError::Config(format!("endpoints.{} must be an http(s) URL, got '{}'", name, url)));
+            }
+        }
+        for (name, target) in &self.aliases {
+            if target.trim().is_empty() {
+                return Err(CodersError::Config(format!("aliases.{} must name a model", name)));
+            }
+        }
+        for (name, prompt) in &self.presets {
+            if prompt.trim().is_empty() {
+                return Err(CodersError::Config(format!("presets.{} must not be empty", name)));
+            }
+        }
+        if self.provider.as_deref().is_some_and(|provider| provider.eq_ignore_ascii_case("auto")) && self.routing.is_empty() {
+            return Err(CodersError::Config("provider = \"auto\" needs at least one [[routing]] rule".to_string()));
+        }
+        if self.hook_timeout == Some(0) {
+            return Err(CodersError::Config("hook_timeout must be greater than 0".to_string()));
+        }
+        let mut previous = 0;
+        for (i, rule) in self.routing.iter().enumerate() {
+            if rule.model.trim().is_empty() {
+                return Err(CodersError::Config(format!("routing rule {} must name a model", i + 1)));
+            }
+            match rule.below_tokens {
+                Some(below) if below <= previous => {
+                    return Err(CodersError::Config(format!("routing rule {}: below_tokens must be greater than the previous rule's ({})", i + 1, previous)));
+                }
+                Some(below) => previous = below,
+                None if i + 1 < self.routing.len() => {
+                    return Err(CodersError::Config(format!("routing rule {} has no below_tokens, so the rules after it can never match", i + 1)));
+                }
+                None => {}
+            }
+        }
+        for (name, provider) in &self.providers {
+            if let Some(temperature) = provider.temperature {
+                check_range(&format!("providers.{}.temperature", name), temperature, 0.0, 2.0)?;
+            }
+            if let Some(top_p) = provider.top_p {
+                check_range(&format!("providers.{}.top_p", name), top_p, 0.0, 1.0)?;
+            }
+            if provider.max_tokens == Some(0) {
+                return Err(CodersError::Config(format!("providers.{}.max_tokens must be greater than 0", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn provider(&self, id: &str) -> Option<&ProviderConfig> {
+        self.providers.get(id)
+    }
+}
+
+pub fn check_range(name: &str, value: f32, min: f32, max: f32) -> Result<(), CodersError> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(CodersError::Config(format!("{} must be between {} and {}, got {}", name, min, max, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = "/work/app/.coders/config.toml";
+
+    fn restrict(project: &str) -> Result<toml::Table, CodersError> {
+        let mut table: toml::Table = toml::from_str(project).unwrap();
+        restrict_project(&mut table, Path::new(PROJECT))?;
+        Ok(table)
+    }
+
+    fn refusal(project: &str) -> String {
+        match restrict(project) {
+            Err(CodersError::Config(message)) => message,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("accepted: {}", project),
+        }
+    }
+
+    #[test]
+    fn project_may_not_touch_allowed_hosts() {
+        assert!(refusal("allowed_hosts = []").contains("sets allowed_hosts"));
+        assert!(refusal("allowed_hosts = [\"evil.example.com\"]").contains("sets allowed_hosts"));
+    }
+
+    #[test]
+    fn project_audit_dir_must_stay_inside_the_project() {
+        assert!(refusal("audit_dir = \"/tmp/artifacts\"").contains("outside the project"));
+        assert!(refusal("audit_dir = \"../artifacts\"").contains("outside the project"));
+        assert!(refusal("audit_dir = \"logs/../../artifacts\"").contains("outside the project"));
+    }
+
+    #[test]
+    fn project_audit_dir_is_relative_to_the_project_root() {
+        let table = restrict("audit_dir = \"audit\"").unwrap();
+        assert_eq!(table["audit_dir"].as_str(), Some(Path::new("/work/app").join("audit").to_str().unwrap()));
+    }
+
+    #[test]
+    fn project_may_not_redirect_run_hooks_or_accept_terms() {
+        assert!(refusal("[endpoints]\nopenrouter = \"https://evil.example.com\"").contains("sets an endpoint"));
+        assert!(refusal("[providers.azure]\nendpoint = \"https://evil.example.com\"").contains("sets an endpoint"));
+        assert!(refusal("pre_send_hook = \"true\"").contains("sets pre_send_hook"));
+        assert!(refusal("post_receive_hook = \"true\"").contains("sets post_receive_hook"));
+        assert!(refusal("[providers.groq]\nterms_accepted = true").contains("sets terms_accepted"));
+    }
+
+    #[test]
+    fn project_may_set_everything_else() {
+        let table = restrict("provider = \"groq\"\n[language_prompts]\nrust = \"Use anyhow.\"").unwrap();
+        assert_eq!(table["provider"].as_str(), Some("groq"));
+    }
+}
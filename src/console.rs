@@ -0,0 +1,66 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+tokio::task_local! {
+    static SILENT: ();
+}
+
+/// Runs `future` with the progress output of the request path turned off: the `say!` lines,
+/// spinners and retry notices. `coders::run` is called from other programs, which have
+/// their own output.
+pub async fn silent<F: Future>(future: F) -> F::Output {
+    SILENT.scope((), future).await
+}
+
+/// Whether progress output is shown, i.e. not inside `silent` and not under `--quiet`.
+pub fn enabled() -> bool {
+    SILENT.try_with(|_| ()).is_err() && !quiet()
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `--quiet`: no progress output for the whole run, and no questions either; the run prints
+/// only its one line per file.
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// `--ascii`: progress output without animation characters or colors, for screen readers and
+/// terminals without Unicode.
+pub fn set_ascii() {
+    ASCII.store(true, Ordering::Relaxed);
+    colored::control::set_override(false);
+}
+
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// Whether the terminal probably can't show Unicode or colors: `TERM=dumb`, or a locale that
+/// is set but isn't UTF-8. An unset locale says nothing, since many systems leave it so.
+pub fn ascii_terminal() -> bool {
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        return true;
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()));
+    locale.is_some_and(|locale| {
+        let locale = locale.to_lowercase();
+        !locale.contains("utf-8") && !locale.contains("utf8")
+    })
+}
+
+/// `println!` for progress output, which `silent` turns off.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::console::enabled() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,1020 @@
+//! One edit request, from building and sending it to pulling the code out of the answer:
+//! what the CLI and [`crate::api::run`] share.
+
+use crate::audit;
+use crate::azure;
+use crate::catalog;
+use crate::cleanup;
+use crate::cli::{unescape_stop_sequence, Cli};
+use crate::cohere;
+use crate::config;
+use crate::error::CodersError;
+use crate::gemini;
+use crate::history;
+use crate::hooks;
+use crate::http;
+use crate::messages;
+use crate::openai_compat;
+use crate::prompts::{append_sentence, build_messages, completion_prompt, system_prompt, user_message, whole_file_tokens, DEFAULT_EXAMPLES, DEFAULT_SYSTEM_PROMPT};
+use crate::render::display_waiting_message;
+use crate::secrets;
+use crate::slim;
+use crate::stream;
+use crate::{Capabilities, OpenRouterModel, Provider};
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+pub const DEFAULT_TOP_P: f32 = 0.9;
+pub const DEFAULT_SEED: u64 = 42;
+pub const DEFAULT_RETRY_ON_MALFORMED: u32 = 2;
+
+#[derive(Clone)]
+pub struct GenerationOptions {
+    pub temperature: f32,
+    pub top_p: f32,
+    pub max_tokens: Option<u32>,
+    pub seed: Option<u64>,
+    pub stop: Vec<String>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub request_confidence: bool,
+    pub request_rationale: bool,
+    pub stream: bool,
+    pub concise: bool,
+    pub tools: bool,
+    pub language_prompts: HashMap<String, String>,
+    // Set for the retry after the provider rejected the system role or example turns
+    pub degraded: bool,
+    // --image as a `data:` URL, sent with the user message
+    pub image: Option<String>,
+    // --messages-file, sent as they are before the user message
+    pub messages: Option<Vec<messages::Message>>,
+}
+
+impl GenerationOptions {
+    // Precedence: CLI flags, then the active provider's config section, then the top-level
+    // config values, then built-in defaults.
+    pub fn from_cli(cli: &Cli, config: &config::Config, provider: Provider) -> GenerationOptions {
+        let deterministic = cli.deterministic || config.deterministic;
+        let seed = cli.seed.or(config.seed);
+        let stop = if cli.stop.is_empty() { &config.stop } else { &cli.stop };
+        let provider_config = config.provider(provider.id());
+
+        let mut options = GenerationOptions {
+            temperature: cli.temperature
+                .or(provider_config.and_then(|p| p.temperature))
+                .or(config.temperature)
+                .unwrap_or(DEFAULT_TEMPERATURE),
+            top_p: cli.top_p
+                .or(provider_config.and_then(|p| p.top_p))
+                .or(config.top_p)
+                .unwrap_or(DEFAULT_TOP_P),
+            max_tokens: cli.max_tokens.or(provider_config.and_then(|p| p.max_tokens)),
+            seed,
+            stop: stop.iter().map(|s| unescape_stop_sequence(s)).collect(),
+            frequency_penalty: cli.frequency_penalty.or(config.frequency_penalty),
+            presence_penalty: cli.presence_penalty.or(config.presence_penalty),
+            request_confidence: cli.min_confidence.is_some(),
+            request_rationale: cli.explain,
+            stream: cli.stream && provider == Provider::OpenRouter,
+            concise: cli.concise,
+            tools: cli.tools,
+            language_prompts: config.language_prompts.clone(),
+            degraded: false,
+            image: None,
+            messages: None,
+        };
+        if deterministic {
+            options.temperature = 0.0;
+            options.top_p = 1.0;
+            options.seed = Some(seed.unwrap_or(DEFAULT_SEED));
+        }
+        options
+    }
+
+    // What the message builder may use for `model`, less after a rejected layout.
+    pub fn capabilities(&self, provider: Provider, model: &str) -> Capabilities {
+        let capabilities = Capabilities::of(provider, model);
+        if self.degraded { capabilities.degraded() } else { capabilities }
+    }
+
+    // The few-shot examples to send, none under --concise.
+    pub fn examples<'a>(&self, examples: &'a [(&'a str, &'a str)]) -> &'a [(&'a str, &'a str)] {
+        if self.concise { &[] } else { examples }
+    }
+
+    // Adds the sampling parameters to a request body. Optional fields are left out
+    // entirely when unset, since some providers reject unknown or null fields.
+    pub fn apply_to(&self, request_body: &mut serde_json::Value) {
+        request_body["temperature"] = json!(self.temperature);
+        request_body["top_p"] = json!(self.top_p);
+        if let Some(max_tokens) = self.max_tokens {
+            request_body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(seed) = self.seed {
+            request_body["seed"] = json!(seed);
+        }
+        if !self.stop.is_empty() {
+            request_body["stop"] = json!(self.stop);
+        }
+        if let Some(frequency_penalty) = self.frequency_penalty {
+            request_body["frequency_penalty"] = json!(frequency_penalty);
+        }
+        if let Some(presence_penalty) = self.presence_penalty {
+            request_body["presence_penalty"] = json!(presence_penalty);
+        }
+        if self.tools {
+            openai_compat::add_edit_tool(request_body);
+        }
+    }
+}
+
+// Where a request goes and how it is generated: the run's provider, or the --verify-model one.
+#[derive(Clone, Copy)]
+pub struct Endpoint<'a> {
+    pub provider: Provider,
+    pub base_url: &'a str,
+    pub api_key: &'a str,
+    pub azure: Option<&'a azure::Deployment>,
+    pub options: &'a GenerationOptions,
+}
+
+// The user message of an edit, before the CLI adds the optional git and repository context.
+pub fn edit_context(prompt: &str, content: &str) -> String {
+    format!("{}\n\n{}", prompt, content)
+}
+
+// How often a request is sent again: after an empty answer, and after a body that isn't
+// valid JSON. A `deadline` caps all the attempts together and retries a timed-out attempt
+// while there is time left.
+#[derive(Clone, Copy)]
+pub struct Retries {
+    pub empty: u32,
+    pub malformed: u32,
+    // Announce the empty-answer retries too
+    pub verbose: bool,
+    pub deadline: Option<Duration>,
+}
+
+// The retries of every request, from the CLI and `coders::run` alike. An empty completion
+// with a successful status is usually transient, and a body that isn't valid JSON is
+// usually a response cut off in transit; both are retried separately from (and in
+// addition to) HTTP errors. An empty answer comes back as `None`.
+pub async fn with_retries<F, Fut>(provider: Provider, retries: Retries, mut send: F) -> Result<Option<String>>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<Option<String>>>,
+{
+    let (mut empty, mut malformed) = (0, 0);
+    let started = Instant::now();
+    loop {
+        let result = match retries.deadline {
+            // Dropping the attempt cancels its request, and any rate-limit wait with it
+            Some(deadline) => match tokio::time::timeout(deadline.saturating_sub(started.elapsed()), send()).await {
+                Ok(result) => result,
+                Err(_) => return Err(CodersError::Timeout(format!("{} had no complete answer within the {}s --deadline", provider.name(), deadline.as_secs())).into()),
+            },
+            None => send().await,
+        };
+        match result {
+            Err(e) if retries.deadline.is_some() && matches!(e.downcast_ref(), Some(CodersError::Timeout(_))) => {
+                say!("{}; retrying within the --deadline", e);
+            }
+            Err(e) if malformed < retries.malformed && matches!(e.downcast_ref(), Some(CodersError::MalformedResponse { .. })) => {
+                malformed += 1;
+                say!("{} sent a response that isn't valid JSON; retrying ({}/{})", provider.name(), malformed, retries.malformed);
+            }
+            Ok(response) => match response.filter(|content| !content.trim().is_empty()) {
+                None if empty < retries.empty => {
+                    empty += 1;
+                    if retries.verbose {
+                        say!("Empty completion from {}; retrying ({}/{})", provider.name(), empty, retries.empty);
+                    }
+                }
+                response => return Ok(response),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// What goes with a request besides the request itself. The CLI fills it in from its flags
+// and config file; `coders::run` sends with the defaults.
+#[derive(Default)]
+pub struct Recording<'a> {
+    // The file the request is about, for the hooks and the audit artifacts
+    pub file: &'a str,
+    pub audit: Option<&'a audit::Audit>,
+    // --scrub-secrets: placeholders go out in place of the file's secrets and come back as them
+    pub redactions: Option<&'a secrets::Redactions>,
+    // Show the model's reasoning rather than only how long it was
+    pub verbose: bool,
+    // Add the request to the history `coders stats` reads; the library writes no files
+    pub history: bool,
+}
+
+// One attempt of a request, from the CLI and `coders::run` alike. With `history` every
+// request is recorded, failures included; replayed responses say nothing about the provider
+// and are left out. The answer comes back without the model's reasoning.
+pub async fn send(http: &http::Transport, endpoint: &Endpoint<'_>, model: &str, context: &str, language: &str, recording: &Recording<'_>) -> Result<Option<String>> {
+    // Feedback, repairs and verification repeat code the secrets were put back into
+    let scrubbed = recording.redactions.map(|redactions| redactions.scrub(context));
+    let context = scrubbed.as_deref().unwrap_or(context);
+    http.take_headers_latency();
+    let started = Instant::now();
+    let metadata = hooks::Metadata { file: recording.file.to_string(), provider: endpoint.provider.id().to_string(), model: model.to_string() };
+    let dispatched = hooks::scope(metadata, dispatch_request(http, endpoint, model, context, language));
+    let result = match recording.audit {
+        Some(audit) => {
+            let (result, exchanges) = http::capture(dispatched).await;
+            let request = audit::Request {
+                file: recording.file,
+                provider: endpoint.provider.id(),
+                model,
+                error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            };
+            audit.record(&request, &exchanges)?;
+            result
+        }
+        None => dispatched.await,
+    };
+    let prompt_tokens = slim::estimate_tokens(context);
+    let completion_tokens = result.as_ref().ok().and_then(Option::as_deref).map_or(0, slim::estimate_tokens);
+    let result = result.map(|content| content.map(|content| {
+        let content = match recording.redactions {
+            Some(redactions) => redactions.restore(&content),
+            None => content,
+        };
+        let (answer, thinking) = split_thinking(&content);
+        match thinking {
+            Some(thinking) if recording.verbose => say!("Model reasoning (left out of the answer):\n{}", thinking),
+            Some(thinking) => say!("Left out {} line(s) of model reasoning; --verbose shows them.", thinking.lines().count()),
+            None => {}
+        }
+        answer
+    }));
+    if !recording.history || http.is_replay() {
+        return result;
+    }
+
+    let request = history::Request {
+        timestamp: history::now(),
+        provider: endpoint.provider.id().to_string(),
+        model: model.to_string(),
+        headers_ms: http.take_headers_latency().map(|latency| latency.as_millis() as u64),
+        total_ms: started.elapsed().as_millis() as u64,
+        ok: result.is_ok(),
+        prompt_tokens,
+        completion_tokens,
+    };
+    if let Err(e) = history::record(&request) {
+        eprintln!("Warning: could not update the request history: {:#}", e);
+    }
+    result
+}
+
+// A 400 that complains about the system role or the message layout is sent once more with
+// the instructions folded into the first user message and no example turns.
+pub async fn dispatch_request(http: &http::Transport, endpoint: &Endpoint<'_>, model: &str, context: &str, language: &str) -> Result<Option<String>> {
+    match dispatch_once(http, endpoint, model, context, language).await {
+        // Messages from --messages-file are sent as written, so there is nothing to fall back to
+        Err(e) if !endpoint.options.degraded && endpoint.options.messages.is_none() && rejects_message_layout(&e) => {
+            say!("{}; retrying with the instructions in the user message and no examples.", e);
+            let degraded = GenerationOptions { degraded: true, ..endpoint.options.clone() };
+            dispatch_once(http, &Endpoint { options: &degraded, ..*endpoint }, model, context, language).await
+        }
+        result => result,
+    }
+}
+
+pub fn rejects_message_layout(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<CodersError>() {
+        Some(CodersError::Provider { status: 400, message, .. }) => {
+            let message = message.to_lowercase();
+            message.contains("system") || message.contains("role")
+        }
+        _ => false,
+    }
+}
+
+pub async fn dispatch_once(http: &http::Transport, endpoint: &Endpoint<'_>, model: &str, context: &str, language: &str) -> Result<Option<String>> {
+    let Endpoint { provider, base_url, api_key, azure, options } = *endpoint;
+    match provider {
+        Provider::OpenRouter => send_request_to_openrouter(http, base_url, api_key, context, model, language, options).await,
+        Provider::Hyperbolic => send_request_to_hyperbolic(http, base_url, api_key, context, model, language, options).await,
+        Provider::Gemini => gemini::send_request_to_gemini(http, base_url, api_key, context, model, language, options).await,
+        Provider::Groq => send_request_to_groq(http, base_url, api_key, context, model, language, options).await,
+        Provider::Cohere => cohere::send_request_to_cohere(http, base_url, api_key, context, model, language, options).await,
+        Provider::Azure => {
+            let deployment = azure.context("Azure deployment was not resolved")?;
+            azure::send_request_to_azure(http, api_key, deployment, context, language, options).await
+        }
+    }
+}
+
+// Output budget of the base completions path when --max-tokens isn't set. The ceiling is
+// the most the 405B base model will generate in one response.
+pub const BASE_MODEL_MIN_TOKENS: u32 = 512;
+pub const BASE_MODEL_MAX_TOKENS: u32 = 8192;
+
+pub fn extract_completion_content(choice: &serde_json::Value, is_completion_model: bool, tools: bool) -> Result<Option<String>> {
+    if is_completion_model {
+        Ok(choice["text"].as_str().map(String::from))
+    } else {
+        openai_compat::message_content(&choice["message"], tools)
+    }
+}
+
+pub async fn send_request_to_hyperbolic(http: &http::Transport, base_url: &str, api_key: &str, context: &str, model: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    let capabilities = options.capabilities(Provider::Hyperbolic, model);
+    let is_completion_model = !capabilities.chat;
+    let url = if is_completion_model {
+        format!("{}/completions", base_url)
+    } else {
+        format!("{}/chat/completions", base_url)
+    };
+    say!("Sending request to Hyperbolic API: {}", url);
+
+    let mut request_body = if is_completion_model {
+        // A base model has only the raw prompt to go on, so spell out the fence the extractor looks for
+        let mut system = system_prompt(DEFAULT_SYSTEM_PROMPT, language, options);
+        append_sentence(&mut system, &format!("Put the code in a single ```{} fenced code block.", language));
+        let user_message = user_message(language, context);
+        json!({
+            "model": model,
+            "prompt": match &options.messages {
+                Some(preset) => messages::completion_prompt(preset, &user_message),
+                None => completion_prompt(&system, options.examples(DEFAULT_EXAMPLES), &user_message),
+            },
+            "max_tokens": whole_file_tokens(context).clamp(BASE_MODEL_MIN_TOKENS, BASE_MODEL_MAX_TOKENS),
+            "stream": false
+        })
+    } else {
+        json!({
+            "model": model,
+            "messages": build_messages(language, context, options, capabilities),
+            "max_tokens": 2048,
+            "stream": false
+        })
+    };
+    options.apply_to(&mut request_body);
+    if is_completion_model {
+        let budget = request_body["max_tokens"].as_u64().unwrap_or(0);
+        let needed = whole_file_tokens(context) as u64;
+        if budget < needed {
+            say!("Warning: the output budget of {} tokens is likely too small for a whole-file answer (~{} tokens); the response may be cut off. Try a smaller file, a higher --max-tokens or a chat model.", budget, needed);
+        }
+    }
+    say!("Request body: {}", serde_json::to_string_pretty(&request_body)?);
+    http.review(&url, &request_body)?;
+
+    let spinner = display_waiting_message("Sending request...");
+
+    let response = http.post_json(&url, &[("Authorization", format!("Bearer {}", api_key))], &request_body).await?;
+
+    spinner.finish_and_clear();
+    say!("Response status: {}", response.status);
+
+    if response.status.is_success() {
+        let spinner = display_waiting_message("Processing response...");
+        let body = response.body;
+        say!("Response body: {}", body);
+        if body.is_empty() {
+            spinner.finish_and_clear();
+            say!("Received empty response from Hyperbolic API");
+            return Ok(None);
+        }
+        let json_response = openai_compat::parse_body("Hyperbolic", &body)?;
+        spinner.finish_and_clear();
+        
+        // Completion models answer in `text`, chat models in `message.content`
+        extract_completion_content(openai_compat::first_choice("Hyperbolic", &json_response)?, is_completion_model, options.tools)
+    } else {
+        Err(CodersError::from_response("Hyperbolic", response.status.as_u16(), &response.body).into())
+    }
+}
+
+pub async fn send_request_to_openrouter(http: &http::Transport, base_url: &str, api_key: &str, context: &str, model: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    let url = format!("{}/chat/completions", base_url);
+
+    let known = OpenRouterModel::from_id(model);
+    let mut request_body = json!({
+        "model": model,
+        "messages": build_messages(language, context, options, options.capabilities(Provider::OpenRouter, model)),
+        "max_tokens": known.as_ref().map_or(2048, OpenRouterModel::default_max_tokens),
+    });
+    options.apply_to(&mut request_body);
+    // The cached model list knows more models, and newer limits, than the built-in table
+    let context_length = catalog::cached(Provider::OpenRouter)
+        .and_then(|models| models.into_iter().find(|listed| listed.id == model)?.context_length)
+        .or(known.as_ref().map(|known| known.context_length() as u64));
+    if let Some(context_length) = context_length {
+        let prompt_tokens = slim::estimate_tokens(&request_body["messages"].to_string()) as u64;
+        let budget = request_body["max_tokens"].as_u64().unwrap_or(0);
+        if prompt_tokens + budget > context_length {
+            say!("Warning: ~{} prompt tokens plus a {}-token answer exceed the {}-token context of {}; the answer may be cut off. Try the :extended variant or --slim-context.", prompt_tokens, budget, context_length, model);
+        }
+    }
+
+    if options.stream {
+        return stream::stream_chat_completion(http, &url, api_key, &request_body).await;
+    }
+
+    openai_compat::send_chat_completion(http, "OpenRouter", &url, api_key, &request_body).await
+}
+
+pub async fn send_request_to_groq(http: &http::Transport, base_url: &str, api_key: &str, context: &str, model: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    let url = format!("{}/chat/completions", base_url);
+
+    let mut request_body = json!({
+        "model": model,
+        "messages": build_messages(language, context, options, options.capabilities(Provider::Groq, model)),
+        "max_tokens": 2048,
+    });
+    options.apply_to(&mut request_body);
+
+    openai_compat::send_chat_completion(http, "Groq", &url, api_key, &request_body).await
+}
+
+// How an answer is turned into the code to merge.
+pub struct Extraction<'a> {
+    pub language: &'a str,
+    pub strip_phrases: &'a [String],
+    // --explain: the prose after the code block is the rationale, not code
+    pub rationale: bool,
+    // A tool call carries the code alone, with no fence or prose to strip, and --keep-fences
+    // takes the answer as it came
+    pub verbatim: bool,
+    // --min-confidence: a `{"confidence": ...}` line after the code block is the score, not code
+    pub confidence: bool,
+}
+
+pub struct Extracted {
+    pub code: String,
+    pub confidence: Option<f32>,
+    pub rationale: Option<String>,
+}
+
+// The code in an answer `send` returned, with the confidence trailer and rationale taken off.
+pub fn extract(answer: &str, extraction: &Extraction<'_>) -> Extracted {
+    let (answer, confidence) = if extraction.confidence {
+        extract_confidence_trailer(answer)
+    } else {
+        (answer.to_string(), None)
+    };
+    let (answer, rationale) = if extraction.rationale {
+        split_rationale(&answer)
+    } else {
+        (answer, None)
+    };
+    let code = if extraction.verbatim {
+        answer
+    } else {
+        extract_code_from_response(&answer, extraction.strip_phrases, extraction.language)
+    };
+    Extracted { code, confidence, rationale }
+}
+
+// Removes the `{"confidence": ...}` trailer requested by --min-confidence so it never
+// reaches the extracted code, returning the remaining response and the parsed score. Only
+// the lines after the last closing fence are looked at, so JSON code keeps its lines; an
+// answer without a fence can only have it as its last line.
+fn extract_confidence_trailer(response: &str) -> (String, Option<f32>) {
+    let lines: Vec<&str> = response.lines().collect();
+    let after = match lines.iter().rposition(|line| line.trim_end().ends_with("```")) {
+        Some(close) => close + 1,
+        None => lines.iter().rposition(|line| !line.trim().is_empty()).unwrap_or(0),
+    };
+    let trailer = lines.iter().enumerate().skip(after).rev().find_map(|(i, line)| {
+        let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+        let confidence = value.get("confidence")?.as_f64()?;
+        Some((i, confidence as f32))
+    });
+
+    match trailer {
+        Some((index, confidence)) => {
+            let remaining: Vec<&str> = lines.iter()
+                .enumerate()
+                .filter(|(i, _)| *i != index)
+                .map(|(_, line)| *line)
+                .collect();
+            (remaining.join("\n"), Some(confidence.clamp(0.0, 1.0)))
+        }
+        None => (response.to_string(), None),
+    }
+}
+
+// Reasoning models (DeepSeek-R1, QwQ, ...) think in a `<think>...</think>` block before they
+// answer; a template that opens the block itself leaves only the closing tag, and a response
+// cut off mid-thought has only the opening one. Tags after the code starts are code.
+fn split_thinking(response: &str) -> (String, Option<String>) {
+    let (thinking, answer) = match response.trim_start().strip_prefix("<think>") {
+        Some(rest) => rest.split_once("</think>").unwrap_or((rest, "")),
+        None => match response.split_once("</think>") {
+            Some((thinking, answer)) if !thinking.contains("```") => (thinking, answer),
+            _ => return (response.to_string(), None),
+        },
+    };
+    let thinking = thinking.trim();
+    (answer.trim_start().to_string(), (!thinking.is_empty()).then(|| thinking.to_string()))
+}
+
+// Splits the prose that --explain asks for off the end of the response: everything after
+// the first fenced block's closing fence is the rationale.
+fn split_rationale(response: &str) -> (String, Option<String>) {
+    let lines: Vec<&str> = response.lines().collect();
+    let Some(open) = lines.iter().position(|line| line.starts_with("```")) else {
+        return (response.to_string(), None);
+    };
+    let Some(close) = lines.iter()
+        .skip(open + 1)
+        .position(|line| line.trim_end().ends_with("```"))
+        .map(|i| i + open + 1)
+    else {
+        return (response.to_string(), None);
+    };
+
+    let rationale = lines[close + 1..].join("\n").trim().to_string();
+    let code = lines[..=close].join("\n");
+    if rationale.is_empty() {
+        (code, None)
+    } else {
+        (code, Some(rationale))
+    }
+}
+
+// Without a fence the whole response is taken as code, relying on `cleanup` to drop the
+// prose models put around it. With several blocks, the first one tagged with the file's
+// language wins, then simply the first one.
+fn extract_code_from_response(response: &str, strip_phrases: &[String], language: &str) -> String {
+    let blocks = fenced_blocks(response);
+    let code = match blocks.iter().find(|(info, _)| fence_language(info) == Some(language)).or(blocks.first()) {
+        Some((info, code)) => {
+            match fence_language(info) {
+                Some(tagged) if tagged != language && language != "plaintext" => {
+                    say!("Warning: the code block is marked `{}`, but the file is {}.", info, language);
+                }
+                _ => {}
+            }
+            code.clone()
+        }
+        None => response.to_string(),
+    };
+    cleanup::strip_artifacts(&code, strip_phrases)
+}
+
+// (info string, code) for every fenced block. A closing fence glued to the last line, as in
+// the few-shot examples, ends the block too.
+pub fn fenced_blocks(response: &str) -> Vec<(&str, String)> {
+    let mut blocks = Vec::new();
+    let mut lines = response.lines();
+    while let Some(line) = lines.by_ref().find(|line| line.starts_with("```")) {
+        let info = line.trim_start_matches('`').trim();
+        let mut code: Vec<&str> = Vec::new();
+        for line in lines.by_ref() {
+            if line.starts_with("```") {
+                break;
+            }
+            if let Some(last) = line.strip_suffix("```") {
+                code.push(last);
+                break;
+            }
+            code.push(line);
+        }
+        blocks.push((info, code.join("\n")));
+    }
+    blocks
+}
+
+// The `get_file_language` name for a fence's info string, which is either a language name
+// (`python`), an extension (`py`) or a common alias. `None` when it's missing.
+pub fn fence_language(info: &str) -> Option<&'static str> {
+    let tag = info.split_whitespace().next()?.to_lowercase();
+    let language = match tag.as_str() {
+        "bash" | "zsh" | "console" => "shell",
+        "golang" => "go",
+        "csharp" => "c#",
+        "py3" | "python3" => "python",
+        "jsx" => "javascript",
+        "tsx" => "typescript",
+        _ => match get_file_language(&format!("file.{}", tag)) {
+            "plaintext" => LANGUAGES.iter().find(|name| **name == tag).copied().unwrap_or("plaintext"),
+            language => language,
+        },
+    };
+    Some(language)
+}
+
+// Every name `get_file_language` returns, so a fence can name the language directly.
+pub const LANGUAGES: &[&str] = &[
+    "javascript", "typescript", "python", "rust", "go", "java", "c++", "c", "c#", "php", "ruby", "swift",
+    "kotlin", "scala", "haskell", "lua", "perl", "r", "shell", "sql", "html", "css", "markdown", "json",
+    "xml", "yaml", "toml",
+];
+
+pub fn get_file_language(file_path: &str) -> &'static str {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("");
+
+    match extension {
+        "js" => "javascript",
+        "ts" => "typescript",
+        "py" => "python",
+        "rs" => "rust",
+        "go" => "go",
+        "java" => "java",
+        "cpp" | "cc" | "cxx" => "c++",
+        "c" => "c",
+        "cs" => "c#",
+        "php" => "php",
+        "rb" => "ruby",
+        "swift" => "swift",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "hs" => "haskell",
+        "lua" => "lua",
+        "pl" => "perl",
+        "r" => "r",
+        "sh" => "shell",
+        "sql" => "sql",
+        "html" => "html",
+        "css" => "css",
+        "md" | "markdown" => "markdown",
+        "json" => "json",
+        "xml" => "xml",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        _ => "plaintext",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console;
+    use crate::HyperbolicModel;
+    use clap::Parser;
+
+    const CONTEXT: &str = "Fix add\n\nfn add(a: i32, b: i32) -> i32 {\n    a - b\n}";
+
+    fn options(args: &[&str], provider: Provider) -> GenerationOptions {
+        let cli = Cli::parse_from(["coders", "--file", "add.rs"].iter().chain(args));
+        GenerationOptions::from_cli(&cli, &config::Config::default(), provider)
+    }
+
+    // The body `provider` would send, captured against a replay directory with nothing to answer it.
+    async fn sent_body(provider: Provider, model: &str, options: &GenerationOptions) -> serde_json::Value {
+        sent_body_for(provider, model, options, CONTEXT).await
+    }
+
+    async fn sent_body_for(provider: Provider, model: &str, options: &GenerationOptions, context: &str) -> serde_json::Value {
+        let dir = tempfile::tempdir().unwrap();
+        let http = http::Transport::new(None, Some(dir.path().to_path_buf()), Vec::new(), Vec::new());
+        let deployment = azure::Deployment::resolve(Some("https://coders.openai.azure.com"), Some(model), None, None).unwrap();
+        let endpoint = Endpoint { provider, base_url: provider.default_base_url(), api_key: "test-key", azure: Some(&deployment), options };
+        let (_, sent) = http::capture(console::silent(dispatch_once(&http, &endpoint, model, context, "rust"))).await;
+        sent.into_iter().next().expect("a request was sent").body
+    }
+
+    // What `send` makes of `provider`'s `response` to the request it sends.
+    async fn answer(provider: Provider, model: &str, response: &str) -> Result<Option<String>> {
+        let dir = tempfile::tempdir().unwrap();
+        let http = http::Transport::new(None, Some(dir.path().to_path_buf()), Vec::new(), Vec::new());
+        let options = options(&[], provider);
+        let endpoint = Endpoint { provider, base_url: provider.default_base_url(), api_key: "test-key", azure: None, options: &options };
+        let (_, sent) = http::capture(console::silent(dispatch_once(&http, &endpoint, model, CONTEXT, "rust"))).await;
+        let sent = sent.into_iter().next().expect("a request was sent");
+        http::record(dir.path(), &sent.url, &sent.body, 200, response);
+        console::silent(send(&http, &endpoint, model, CONTEXT, "rust", &Recording::default())).await
+    }
+
+    // tests/fixtures/<dir>/<name>.json
+    fn fixture(dir: &str, name: &str) -> serde_json::Value {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(dir).join(format!("{}.json", name));
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+    }
+
+    fn golden(name: &str) -> serde_json::Value {
+        fixture("requests", name)
+    }
+
+    async fn assert_deterministic_body(provider: Provider, model: &str, name: &str) {
+        let body = sent_body(provider, model, &options(&["--deterministic"], provider)).await;
+        assert_eq!(body, golden(name), "{} request body", name);
+    }
+
+    #[tokio::test]
+    async fn openrouter_deterministic_body() {
+        assert_deterministic_body(Provider::OpenRouter, Provider::OpenRouter.default_model(), "openrouter-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn hyperbolic_chat_deterministic_body() {
+        assert_deterministic_body(Provider::Hyperbolic, Provider::Hyperbolic.default_model(), "hyperbolic-chat-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn hyperbolic_base_deterministic_body() {
+        assert_deterministic_body(Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str(), "hyperbolic-base-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn groq_deterministic_body() {
+        assert_deterministic_body(Provider::Groq, Provider::Groq.default_model(), "groq-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn gemini_deterministic_body() {
+        assert_deterministic_body(Provider::Gemini, Provider::Gemini.default_model(), "gemini-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn cohere_deterministic_body() {
+        assert_deterministic_body(Provider::Cohere, Provider::Cohere.default_model(), "cohere-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn azure_deterministic_body() {
+        assert_deterministic_body(Provider::Azure, "gpt-4o", "azure-deterministic").await;
+    }
+
+    #[tokio::test]
+    async fn an_explicit_seed_is_sent_with_the_usual_sampling() {
+        let body = sent_body(Provider::OpenRouter, Provider::OpenRouter.default_model(), &options(&["--seed", "7"], Provider::OpenRouter)).await;
+
+        assert_eq!(body["seed"], 7);
+        assert_eq!(body["temperature"], json!(DEFAULT_TEMPERATURE));
+        assert_eq!(body["top_p"], json!(DEFAULT_TOP_P));
+    }
+
+    #[tokio::test]
+    async fn deterministic_keeps_an_explicit_seed() {
+        let body = sent_body(Provider::Groq, Provider::Groq.default_model(), &options(&["--deterministic", "--seed", "7"], Provider::Groq)).await;
+
+        assert_eq!((body["temperature"].clone(), body["top_p"].clone(), body["seed"].clone()), (json!(0.0), json!(1.0), json!(7)));
+    }
+
+    #[tokio::test]
+    async fn no_seed_is_sent_by_default() {
+        let body = sent_body(Provider::OpenRouter, Provider::OpenRouter.default_model(), &options(&[], Provider::OpenRouter)).await;
+
+        assert!(body.get("seed").is_none());
+    }
+
+    #[tokio::test]
+    async fn a_chat_model_answers_in_the_message_content() {
+        let response = json!({"choices": [{"index": 0, "message": {"role": "assistant", "content": "```rust\nfn add() {}\n```"}, "finish_reason": "stop"}]});
+        let content = answer(Provider::Hyperbolic, Provider::Hyperbolic.default_model(), &response.to_string()).await.unwrap();
+
+        assert_eq!(content.as_deref(), Some("```rust\nfn add() {}\n```"));
+    }
+
+    #[tokio::test]
+    async fn a_base_model_answers_in_the_completion_text() {
+        let response = json!({"choices": [{"index": 0, "text": "```rust\nfn add() {}\n```", "finish_reason": "stop"}]});
+        let content = answer(Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str(), &response.to_string()).await.unwrap();
+
+        assert_eq!(content.as_deref(), Some("```rust\nfn add() {}\n```"));
+    }
+
+    #[tokio::test]
+    async fn a_base_model_does_not_read_the_chat_shape() {
+        let response = json!({"choices": [{"message": {"role": "assistant", "content": "fn add() {}"}}]});
+        let content = answer(Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str(), &response.to_string()).await.unwrap();
+
+        assert_eq!(content, None);
+    }
+
+    #[tokio::test]
+    async fn a_truncated_body_is_malformed() {
+        let error = answer(Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str(), "{\"choices\": [{\"text\": \"fn add").await.unwrap_err();
+
+        assert!(matches!(error.downcast_ref(), Some(CodersError::MalformedResponse { .. })), "{}", error);
+    }
+
+    const SAMPLING: [&str; 8] = ["--stop", "```\\n\\n", "--stop", "END", "--frequency-penalty", "0.5", "--presence-penalty", "-1.25"];
+
+    #[test]
+    fn stop_and_penalties_are_added_when_set() {
+        let mut body = json!({"model": "m"});
+        options(&SAMPLING, Provider::OpenRouter).apply_to(&mut body);
+
+        assert_eq!(body, json!({
+            "model": "m",
+            "temperature": DEFAULT_TEMPERATURE,
+            "top_p": DEFAULT_TOP_P,
+            "stop": ["```\n\n", "END"],
+            "frequency_penalty": 0.5,
+            "presence_penalty": -1.25,
+        }));
+    }
+
+    #[test]
+    fn unset_options_are_left_out() {
+        let mut body = json!({"model": "m"});
+        options(&[], Provider::OpenRouter).apply_to(&mut body);
+
+        assert_eq!(body, json!({"model": "m", "temperature": DEFAULT_TEMPERATURE, "top_p": DEFAULT_TOP_P}));
+    }
+
+    #[test]
+    fn penalties_outside_the_range_are_refused() {
+        assert!(Cli::try_parse_from(["coders", "--file", "add.rs", "--frequency-penalty", "2.5"]).is_err());
+        assert!(Cli::try_parse_from(["coders", "--file", "add.rs", "--presence-penalty", "-3"]).is_err());
+        assert!(Cli::try_parse_from(["coders", "--file", "add.rs", "--presence-penalty", "-2"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn each_provider_names_stop_and_penalties_its_own_way() {
+        for provider in [Provider::OpenRouter, Provider::Hyperbolic, Provider::Groq, Provider::Azure] {
+            let body = sent_body(provider, provider.default_model(), &options(&SAMPLING, provider)).await;
+            assert_eq!(body["stop"], json!(["```\n\n", "END"]), "{}", provider.name());
+            assert_eq!((body["frequency_penalty"].clone(), body["presence_penalty"].clone()), (json!(0.5), json!(-1.25)), "{}", provider.name());
+        }
+
+        let body = sent_body(Provider::Gemini, Provider::Gemini.default_model(), &options(&SAMPLING, Provider::Gemini)).await;
+        let config = &body["generationConfig"];
+        assert_eq!(config["stopSequences"], json!(["```\n\n", "END"]));
+        assert_eq!((config["frequencyPenalty"].clone(), config["presencePenalty"].clone()), (json!(0.5), json!(-1.25)));
+
+        let body = sent_body(Provider::Cohere, Provider::Cohere.default_model(), &options(&SAMPLING, Provider::Cohere)).await;
+        assert_eq!(body["stop_sequences"], json!(["```\n\n", "END"]));
+        assert_eq!((body["frequency_penalty"].clone(), body["presence_penalty"].clone()), (json!(0.5), json!(-1.25)));
+        assert!(body.get("stop").is_none());
+    }
+
+    #[test]
+    fn file_languages_come_from_the_extension() {
+        let table = [
+            ("app.js", "javascript"), ("app.ts", "typescript"), ("main.py", "python"), ("lib.rs", "rust"),
+            ("main.go", "go"), ("Main.java", "java"), ("a.cpp", "c++"), ("a.cc", "c++"), ("a.cxx", "c++"),
+            ("a.c", "c"), ("a.cs", "c#"), ("build.gradle.kts", "kotlin"), ("run.sh", "shell"),
+            ("README.md", "markdown"), ("notes.markdown", "markdown"), ("ci.yml", "yaml"), ("ci.yaml", "yaml"),
+            ("Cargo.toml", "toml"), ("src/dir.d/data.json", "json"), ("Makefile", "plaintext"),
+        ];
+        for (path, language) in table {
+            assert_eq!(get_file_language(path), language, "{}", path);
+        }
+    }
+
+    #[test]
+    fn info_strings_name_a_language_an_extension_or_an_alias() {
+        let table = [
+            ("rust", Some("rust")), ("rs", Some("rust")), ("Rust", Some("rust")), ("rust ignore", Some("rust")),
+            ("py", Some("python")), ("python3", Some("python")), ("js", Some("javascript")), ("jsx", Some("javascript")),
+            ("tsx", Some("typescript")), ("golang", Some("go")), ("csharp", Some("c#")), ("c++", Some("c++")),
+            ("bash", Some("shell")), ("console", Some("shell")), ("yml", Some("yaml")), ("diff", Some("plaintext")),
+            ("", None), ("   ", None),
+        ];
+        for (info, language) in table {
+            assert_eq!(fence_language(info), language, "{:?}", info);
+        }
+    }
+
+    fn extracted_code(answer: &str, language: &str) -> String {
+        extract(answer, &Extraction { language, strip_phrases: &[], rationale: false, verbatim: false, confidence: false }).code
+    }
+
+    // An answer with a JSONL block whose second line has a "confidence" key, and a trailer.
+    const JSONL: &str = "```json\n{\"id\": 1}\n{\"confidence\": 0.9, \"label\": \"spam\"}\n```\n{\"confidence\": 0.4}";
+
+    #[test]
+    fn code_with_a_confidence_key_comes_through_without_min_confidence() {
+        let extracted = extract(JSONL, &Extraction { language: "json", strip_phrases: &[], rationale: false, verbatim: false, confidence: false });
+
+        assert_eq!(extracted.code, "{\"id\": 1}\n{\"confidence\": 0.9, \"label\": \"spam\"}");
+        assert_eq!(extracted.confidence, None);
+    }
+
+    #[test]
+    fn the_confidence_trailer_is_only_read_after_the_code_block() {
+        let extraction = Extraction { language: "json", strip_phrases: &[], rationale: false, verbatim: false, confidence: true };
+
+        let extracted = extract(JSONL, &extraction);
+        assert_eq!(extracted.code, "{\"id\": 1}\n{\"confidence\": 0.9, \"label\": \"spam\"}");
+        assert_eq!(extracted.confidence, Some(0.4));
+
+        let extracted = extract(&JSONL[..JSONL.rfind('\n').unwrap()], &extraction);
+        assert_eq!(extracted.code, "{\"id\": 1}\n{\"confidence\": 0.9, \"label\": \"spam\"}");
+        assert_eq!(extracted.confidence, None);
+    }
+
+    #[test]
+    fn an_unfenced_answer_can_only_end_with_the_trailer() {
+        let extraction = Extraction { language: "json", strip_phrases: &[], rationale: false, verbatim: true, confidence: true };
+
+        let extracted = extract("{\"confidence\": 0.9}\n{\"id\": 1}\n{\"confidence\": 1.5}\n", &extraction);
+
+        assert_eq!(extracted.code, "{\"confidence\": 0.9}\n{\"id\": 1}");
+        assert_eq!(extracted.confidence, Some(1.0));
+    }
+
+    #[test]
+    fn a_mismatched_info_string_still_gives_the_block() {
+        assert_eq!(extracted_code("```js\nconst a: number = 1;\n```", "typescript"), "const a: number = 1;");
+    }
+
+    #[test]
+    fn a_missing_info_string_gives_the_block() {
+        assert_eq!(extracted_code("Here you go:\n```\nlet a = 1;\n```", "rust"), "let a = 1;");
+    }
+
+    #[test]
+    fn the_block_in_the_file_language_wins_over_an_earlier_one() {
+        let answer = "Run this first:\n```bash\ncargo add serde\n```\nThen:\n```rust\nuse serde::Serialize;\n```";
+
+        assert_eq!(extracted_code(answer, "rust"), "use serde::Serialize;");
+    }
+
+    #[test]
+    fn without_a_block_in_the_file_language_the_first_one_is_taken() {
+        let answer = "```js\nconst a = 1;\n```\n```\nconst b = 2;\n```";
+
+        assert_eq!(extracted_code(answer, "typescript"), "const a = 1;");
+    }
+
+    #[test]
+    fn a_language_prompt_is_added_for_its_language_only() {
+        let mut options = options(&[], Provider::Groq);
+        options.language_prompts.insert("python".to_string(), "Preserve type hints and black formatting".to_string());
+
+        assert!(system_prompt(DEFAULT_SYSTEM_PROMPT, "python", &options).ends_with(". Preserve type hints and black formatting"));
+        assert_eq!(system_prompt(DEFAULT_SYSTEM_PROMPT, "rust", &options), DEFAULT_SYSTEM_PROMPT);
+    }
+
+    const REASONED: &str = "```rust\nfn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n```";
+
+    #[tokio::test]
+    async fn reasoning_content_is_left_out_before_extraction() {
+        let response = json!({"choices": [{"message": {"role": "assistant", "reasoning_content": "The operator is wrong.\nUse +.", "content": REASONED}}]});
+        let content = answer(Provider::OpenRouter, "deepseek/deepseek-r1", &response.to_string()).await.unwrap().unwrap();
+
+        assert_eq!(content, REASONED);
+        assert_eq!(extracted_code(&content, "rust"), "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}");
+    }
+
+    #[tokio::test]
+    async fn an_inline_think_block_is_left_out_before_extraction() {
+        let response = json!({"choices": [{"message": {"role": "assistant", "content": format!("<think>\nMaybe `a - b` ```is``` meant?\n</think>\n\n{}", REASONED)}}]});
+        let content = answer(Provider::Groq, "deepseek-r1-distill-llama-70b", &response.to_string()).await.unwrap().unwrap();
+
+        assert_eq!(content, REASONED);
+    }
+
+    #[test]
+    fn thinking_is_split_off_however_the_tags_come() {
+        assert_eq!(split_thinking("<think>a</think>\ncode"), ("code".to_string(), Some("a".to_string())));
+        // A template that opened the block itself
+        assert_eq!(split_thinking("a\n</think>\ncode"), ("code".to_string(), Some("a".to_string())));
+        // Cut off mid-thought
+        assert_eq!(split_thinking("<think>a"), (String::new(), Some("a".to_string())));
+        // A tag inside the code is code
+        assert_eq!(split_thinking("```\nlet s = \"</think>\";\n```"), ("```\nlet s = \"</think>\";\n```".to_string(), None));
+    }
+
+    // tests/fixtures/messages holds the bodies the CLI sent for `coders -f hello.rs` and
+    // "Fix the syntax error" before every provider built its messages with `build_messages`.
+    async fn assert_unchanged_since_the_snapshot(provider: Provider, model: &str, name: &str) -> serde_json::Value {
+        let context = edit_context("Fix the syntax error", include_str!("../tests/fixtures/hello.rs"));
+        let body = sent_body_for(provider, model, &options(&[], provider), &context).await;
+        if provider != Provider::OpenRouter {
+            assert_eq!(body, fixture("messages", name), "{} request body", name);
+        }
+        body
+    }
+
+    #[tokio::test]
+    async fn the_shared_message_builder_kept_every_other_provider_s_wire_format() {
+        assert_unchanged_since_the_snapshot(Provider::Hyperbolic, Provider::Hyperbolic.default_model(), "hyperbolic-chat").await;
+        assert_unchanged_since_the_snapshot(Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str(), "hyperbolic-base").await;
+        assert_unchanged_since_the_snapshot(Provider::Groq, Provider::Groq.default_model(), "groq").await;
+        assert_unchanged_since_the_snapshot(Provider::Gemini, Provider::Gemini.default_model(), "gemini").await;
+        assert_unchanged_since_the_snapshot(Provider::Cohere, Provider::Cohere.default_model(), "cohere").await;
+        assert_unchanged_since_the_snapshot(Provider::Azure, "gpt-4o", "azure").await;
+    }
+
+    // OpenRouter had its own copy of the system prompt and the first example, which drifted;
+    // those are the only messages that changed, and now match everyone else's.
+    #[tokio::test]
+    async fn openrouter_changed_only_in_its_drifted_prompt_and_example() {
+        let body = assert_unchanged_since_the_snapshot(Provider::OpenRouter, Provider::OpenRouter.default_model(), "openrouter").await;
+        let before = fixture("messages", "openrouter");
+
+        let mut unchanged = body.clone();
+        unchanged["messages"] = json!(null);
+        let mut expected = before.clone();
+        expected["messages"] = json!(null);
+        assert_eq!(unchanged, expected);
+        let (now, then) = (body["messages"].as_array().unwrap(), before["messages"].as_array().unwrap());
+        assert_eq!(now.len(), then.len());
+        let changed: Vec<usize> = (0..now.len()).filter(|i| now[*i] != then[*i]).collect();
+        assert_eq!(changed, vec![0, 1, 2]);
+        assert_eq!(body["messages"], fixture("messages", "groq")["messages"]);
+    }
+}
@@ -0,0 +1,178 @@
+use thiserror::Error;
+
+/// Error classes callers can tell apart. Library code wraps these in `anyhow::Error` so
+/// context can still be attached; `exit_code` digs them back out of the chain.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CodersError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Config(String),
+    #[error("{provider} rejected the API key; run with --reset to enter a new one")]
+    ApiKeyInvalid { provider: String },
+    #[error("{provider} account is out of credits{}: {message}", top_up_url(provider).map(|url| format!(", top up at {}", url)).unwrap_or_default())]
+    OutOfCredits { provider: String, message: String },
+    #[error("{provider} returned status {status}: {message}")]
+    Provider { status: u16, message: String, provider: String },
+    #[error("Could not parse the response: {0}")]
+    ResponseParse(String),
+    #[error("{provider} sent a response that isn't valid JSON ({error}); body: {body}")]
+    MalformedResponse { provider: String, error: String, body: String },
+    #[error("Aborted by user")]
+    UserAbort,
+    #[error("{0}")]
+    CheckFailed(String),
+    #[error("Refusing to apply: {0}")]
+    Unparseable(String),
+    #[error("Budget exceeded: {0}")]
+    Budget(String),
+    #[error("Can't ask for confirmation: {0}")]
+    NoTerminal(String),
+    #[error("Gate failed: {0}")]
+    Gate(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Hook failed: {0}")]
+    Hook(String),
+}
+
+impl CodersError {
+    /// Classifies a non-success HTTP response from a provider.
+    pub fn from_response(provider: &str, status: u16, body: &str) -> CodersError {
+        if status == 401 || status == 403 {
+            return CodersError::ApiKeyInvalid { provider: provider.to_string() };
+        }
+
+        // Most providers wrap failures as {"error": {"message": "..."}}
+        let message = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|value| {
+                value["error"]["message"].as_str()
+                    .or_else(|| value["error"].as_str())
+                    .or_else(|| value["message"].as_str())
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| body.trim().to_string());
+
+        if CodersError::is_out_of_credits(status, body) {
+            return CodersError::OutOfCredits { provider: provider.to_string(), message };
+        }
+        CodersError::Provider { status, message, provider: provider.to_string() }
+    }
+
+    /// A billing failure rather than a transient limit, so retrying won't help. OpenRouter
+    /// answers 402 for an exhausted balance but also 429 for free-model daily limits that
+    /// only credits lift; OpenAI-style APIs send a 429 with code `insufficient_quota`.
+    pub fn is_out_of_credits(status: u16, body: &str) -> bool {
+        if status == 402 {
+            return true;
+        }
+        if status != 429 {
+            return false;
+        }
+        let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+            return false;
+        };
+        let error = &value["error"];
+        error["code"] == "insufficient_quota"
+            || error["type"] == "insufficient_quota"
+            || error["message"].as_str().is_some_and(|message| message.to_lowercase().contains("credits"))
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CodersError::Io(_) => 74,
+            CodersError::Config(_) => 78,
+            CodersError::ApiKeyInvalid { .. } => 77,
+            CodersError::Provider { .. } | CodersError::OutOfCredits { .. } => 69,
+            CodersError::ResponseParse(_) | CodersError::MalformedResponse { .. } => 76,
+            CodersError::Unparseable(_) => 65,
+            CodersError::UserAbort => 130,
+            CodersError::CheckFailed(_) | CodersError::Hook(_) => 70,
+            CodersError::Budget(_) | CodersError::Timeout(_) => 75,
+            CodersError::NoTerminal(_) => 66,
+            CodersError::Gate(_) => 3,
+        }
+    }
+}
+
+fn top_up_url(provider: &str) -> Option<&'static str> {
+    match provider {
+        "OpenRouter" => Some("https://openrouter.ai/credits"),
+        _ => None,
+    }
+}
+
+/// Exit code for an error bubbled up to `main`: the first `CodersError` in the chain
+/// decides, bare I/O errors count as `Io`, and anything else is a generic failure.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<CodersError>() {
+            return error.exit_code();
+        }
+        if cause.is::<std::io::Error>() {
+            return 74;
+        }
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_keys() {
+        for status in [401, 403] {
+            assert!(matches!(
+                CodersError::from_response("Groq", status, r#"{"error": {"message": "Invalid API Key"}}"#),
+                CodersError::ApiKeyInvalid { provider } if provider == "Groq"
+            ));
+        }
+    }
+
+    #[test]
+    fn message_from_each_error_shape() {
+        for body in [r#"{"error": {"message": "model not found"}}"#, r#"{"error": "model not found"}"#, r#"{"message": "model not found"}"#] {
+            assert!(matches!(
+                CodersError::from_response("OpenRouter", 404, body),
+                CodersError::Provider { status: 404, message, .. } if message == "model not found"
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_body_falls_back_to_the_raw_text() {
+        assert!(matches!(
+            CodersError::from_response("Hyperbolic", 502, "  <html>Bad Gateway</html>\n"),
+            CodersError::Provider { status: 502, message, .. } if message == "<html>Bad Gateway</html>"
+        ));
+    }
+
+    #[test]
+    fn out_of_credits() {
+        assert!(matches!(
+            CodersError::from_response("OpenRouter", 402, r#"{"error": {"message": "Insufficient balance"}}"#),
+            CodersError::OutOfCredits { message, .. } if message == "Insufficient balance"
+        ));
+        let quota = r#"{"error": {"message": "You exceeded your current quota", "code": "insufficient_quota"}}"#;
+        assert!(matches!(CodersError::from_response("Groq", 429, quota), CodersError::OutOfCredits { .. }));
+        let typed = r#"{"error": {"message": "quota", "type": "insufficient_quota"}}"#;
+        assert!(matches!(CodersError::from_response("Groq", 429, typed), CodersError::OutOfCredits { .. }));
+    }
+
+    #[test]
+    fn plain_rate_limit_is_not_out_of_credits() {
+        let body = r#"{"error": {"message": "Rate limit reached, retry in 2s"}}"#;
+        assert!(matches!(CodersError::from_response("Groq", 429, body), CodersError::Provider { status: 429, .. }));
+        assert!(!CodersError::is_out_of_credits(429, "not json"));
+    }
+
+    #[test]
+    fn exit_code_digs_through_context() {
+        let error = anyhow::Error::from(CodersError::ApiKeyInvalid { provider: "Groq".to_string() }).context("The key in GROQ_API_KEY was rejected");
+        assert_eq!(exit_code(&error), 77);
+        assert_eq!(exit_code(&anyhow::anyhow!("something else")), 1);
+    }
+}
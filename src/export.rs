@@ -0,0 +1,130 @@
+use crate::history;
+use anyhow::{Context, Result};
+use similar::{DiffOp, TextDiff};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0.3em 1em; }
+dt { font-weight: bold; }
+dd { margin: 0; white-space: pre-wrap; }
+h2 { font-family: monospace; border-bottom: 1px solid #ccc; padding-bottom: 0.2em; }
+table { border-collapse: collapse; width: 100%; table-layout: fixed; font-family: monospace; font-size: 13px; }
+td { vertical-align: top; white-space: pre-wrap; word-break: break-all; padding: 0 0.4em; }
+td.num { width: 3.5em; text-align: right; color: #888; user-select: none; }
+td.del { background: #fdd; }
+td.ins { background: #dfd; }
+td.mod-old { background: #fec; }
+td.mod-new { background: #ffc; }
+";
+
+/// One target file in the report.
+pub struct Section {
+    pub file: String,
+    pub summary: String,
+    pub original: String,
+    pub proposed: String,
+}
+
+/// `--export-html`: a standalone page with the prompt, model and a side-by-side diff per
+/// file. It is rewritten after every file, so it is complete even if the run is aborted.
+pub struct HtmlReport {
+    path: PathBuf,
+    prompt: String,
+    model: String,
+    timestamp: String,
+    sections: Vec<Section>,
+}
+
+impl HtmlReport {
+    pub fn new(path: PathBuf, prompt: &str, model: &str) -> HtmlReport {
+        HtmlReport {
+            path,
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+            timestamp: utc_timestamp(),
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&mut self, section: Section) -> Result<()> {
+        self.sections.push(section);
+        fs::write(&self.path, self.render())
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    fn render(&self) -> String {
+        let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>coders: proposed changes</title>\n");
+        html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n<h1>Proposed changes</h1>\n", STYLE));
+        html.push_str(&format!(
+            "<dl>\n<dt>Prompt</dt><dd>{}</dd>\n<dt>Model</dt><dd>{}</dd>\n<dt>Generated</dt><dd>{}</dd>\n</dl>\n",
+            escape(&self.prompt), escape(&self.model), self.timestamp,
+        ));
+        for section in &self.sections {
+            html.push_str(&format!("<h2>{}</h2>\n<p>{}</p>\n", escape(&section.file), escape(&section.summary)));
+            html.push_str(&side_by_side(&section.original, &section.proposed));
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+fn side_by_side(original: &str, proposed: &str) -> String {
+    let old_lines: Vec<&str> = original.lines().collect();
+    let new_lines: Vec<&str> = proposed.lines().collect();
+    let diff = TextDiff::from_lines(original, proposed);
+
+    let mut table = String::from("<table>\n");
+    for op in diff.ops() {
+        let (old_index, old_len, new_index, new_len, class) = match *op {
+            DiffOp::Equal { old_index, new_index, len } => (old_index, len, new_index, len, ("", "")),
+            DiffOp::Delete { old_index, old_len, new_index } => (old_index, old_len, new_index, 0, ("del", "")),
+            DiffOp::Insert { old_index, new_index, new_len } => (old_index, 0, new_index, new_len, ("", "ins")),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => (old_index, old_len, new_index, new_len, ("mod-old", "mod-new")),
+        };
+        // A replaced block is paired line by line; the shorter side is padded with blanks
+        for i in 0..old_len.max(new_len) {
+            table.push_str("<tr>");
+            table.push_str(&cell(&old_lines, old_index, i, old_len, class.0));
+            table.push_str(&cell(&new_lines, new_index, i, new_len, class.1));
+            table.push_str("</tr>\n");
+        }
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+fn cell(lines: &[&str], start: usize, offset: usize, len: usize, class: &str) -> String {
+    if offset >= len {
+        return "<td class=\"num\"></td><td></td>".to_string();
+    }
+    let index = start + offset;
+    let text = lines.get(index).copied().unwrap_or("");
+    format!("<td class=\"num\">{}</td><td class=\"{}\">{}</td>", index + 1, class, escape(text))
+}
+
+// Everything from the file goes through here, so `<script>` in the code stays text.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// e.g. 2024-09-01 14:03:22 UTC
+fn utc_timestamp() -> String {
+    history::rfc3339(history::now()).replacen('T', " ", 1).replace('Z', " UTC")
+}
@@ -0,0 +1,110 @@
+use crate::edit::GenerationOptions;
+use crate::error::CodersError;
+use crate::http;
+use crate::messages;
+use crate::openai_compat;
+use crate::prompts::{chat_messages, system_prompt, user_message, DEFAULT_EXAMPLES, DEFAULT_SYSTEM_PROMPT};
+use crate::render::display_waiting_message;
+use crate::Capabilities;
+use anyhow::Result;
+use serde_json::json;
+
+pub async fn send_request_to_gemini(http: &http::Transport, base_url: &str, api_key: &str, context: &str, model: &str, language: &str, options: &GenerationOptions) -> Result<Option<String>> {
+    let url = format!("{}/models/{}:generateContent", base_url, model);
+
+    let user_message = user_message(language, context);
+    let system = system_prompt(DEFAULT_SYSTEM_PROMPT, language, options);
+    // The system prompt goes in `systemInstruction`, which every Gemini model accepts
+    let messages = chat_messages(&system, options.examples(DEFAULT_EXAMPLES), &user_message, None, Capabilities::FULL, options.messages.as_deref());
+    let system = match &options.messages {
+        Some(preset) => messages::system(preset),
+        None => Some(system),
+    };
+
+    let mut request_body = json!({
+        "contents": gemini_contents(&messages),
+        "generationConfig": generation_config(options),
+    });
+    if let Some(system) = system {
+        request_body["systemInstruction"] = json!({"parts": [{"text": system}]});
+    }
+
+    http.review(&url, &request_body)?;
+    let spinner = display_waiting_message("Sending request...");
+    let response = http.post_json(&url, &[("x-goog-api-key", api_key.to_string())], &request_body).await?;
+    spinner.finish_and_clear();
+
+    if !response.status.is_success() {
+        return Err(CodersError::from_response("Gemini", response.status.as_u16(), &response.body).into());
+    }
+
+    say!("Response body: {}", response.body);
+    if response.body.is_empty() {
+        return Ok(None);
+    }
+    let json_response = openai_compat::parse_body("Gemini", &response.body)?;
+    extract_gemini_content(&json_response)
+}
+
+// Gemini takes the system prompt separately and calls the assistant role "model".
+fn gemini_contents(messages: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    messages.iter()
+        .filter(|message| message["role"] != "system")
+        .map(|message| {
+            let role = if message["role"] == "assistant" { "model" } else { "user" };
+            json!({"role": role, "parts": [{"text": message["content"]}]})
+        })
+        .collect()
+}
+
+fn generation_config(options: &GenerationOptions) -> serde_json::Value {
+    let mut config = json!({
+        "temperature": options.temperature,
+        "topP": options.top_p,
+    });
+    if let Some(max_tokens) = options.max_tokens {
+        config["maxOutputTokens"] = json!(max_tokens);
+    }
+    if let Some(seed) = options.seed {
+        config["seed"] = json!(seed);
+    }
+    if !options.stop.is_empty() {
+        config["stopSequences"] = json!(options.stop);
+    }
+    if let Some(frequency_penalty) = options.frequency_penalty {
+        config["frequencyPenalty"] = json!(frequency_penalty);
+    }
+    if let Some(presence_penalty) = options.presence_penalty {
+        config["presencePenalty"] = json!(presence_penalty);
+    }
+    config
+}
+
+fn extract_gemini_content(json_response: &serde_json::Value) -> Result<Option<String>> {
+    if let Some(reason) = json_response["promptFeedback"]["blockReason"].as_str() {
+        return Err(safety_error(&format!("Gemini refused the prompt ({})", reason)).into());
+    }
+
+    let candidate = &json_response["candidates"][0];
+    if candidate["finishReason"] == "SAFETY" {
+        return Err(safety_error("Gemini stopped the response because it tripped a safety filter").into());
+    }
+
+    let text: String = candidate["content"]["parts"]
+        .as_array()
+        .map(|parts| parts.iter().filter_map(|part| part["text"].as_str()).collect())
+        .unwrap_or_default();
+    if text.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(text))
+    }
+}
+
+fn safety_error(message: &str) -> CodersError {
+    CodersError::Provider {
+        status: 200,
+        message: format!("{}; try rephrasing the prompt or use another provider", message),
+        provider: "Gemini".to_string(),
+    }
+}
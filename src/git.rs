@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git; is it installed and on your PATH?")?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn repo_root(dir: &Path) -> Result<PathBuf> {
+    let root = run_git(dir, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(root.trim()))
+}
+
+/// Files changed between `since` and the working tree, as paths relative to the current
+/// directory. Deleted files and anything matched by the repository's ignore rules are skipped.
+pub fn changed_files_since(since: &str) -> Result<Vec<String>> {
+    let cwd = std::env::current_dir()?;
+    let root = repo_root(&cwd)?;
+    let output = run_git(&root, &["diff", "--name-only", "--diff-filter=d", since, "--"])
+        .with_context(|| format!("Failed to list files changed since '{}'", since))?;
+
+    let mut files = Vec::new();
+    for name in output.lines().filter(|line| !line.trim().is_empty()) {
+        if is_ignored(&root, name)? {
+            continue;
+        }
+        let path = worktree_path(&root, name);
+        if path.is_file() {
+            let path = path.strip_prefix(&cwd).map(Path::to_path_buf).unwrap_or(path);
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(files)
+}
+
+/// Tracked and untracked-but-not-ignored files under `root`, relative to it.
+pub fn list_files(root: &Path) -> Result<Vec<String>> {
+    let output = run_git(root, &["ls-files", "--cached", "--others", "--exclude-standard"])?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+/// `name` as git prints it (always `/`-separated) joined onto `root` one component at a
+/// time, so displayed paths use the platform's separator.
+pub fn worktree_path(root: &Path, name: &str) -> PathBuf {
+    name.split('/').fold(root.to_path_buf(), |path, component| path.join(component))
+}
+
+// Rough budget for --git-context, using the usual ~4 characters per token estimate.
+const DIFF_TOKEN_BUDGET: usize = 2000;
+
+/// The diff of the working tree against `HEAD` (or `HEAD~commits` when `commits > 0`) for
+/// the repository containing `dir`, with binary hunks dropped and the result truncated to
+/// the token budget. `None` means there is nothing to show.
+pub fn recent_diff(dir: &Path, commits: usize) -> Result<Option<String>> {
+    let root = repo_root(dir)?;
+    let base = if commits == 0 { "HEAD".to_string() } else { format!("HEAD~{}", commits) };
+    let output = run_git(&root, &["diff", &base])?;
+
+    let mut diff = String::new();
+    for section in file_sections(&output) {
+        let is_binary = section.lines().any(|line| {
+            (line.starts_with("Binary files") && line.ends_with("differ")) || line == "GIT binary patch"
+        });
+        if !is_binary {
+            diff.push_str(section);
+        }
+    }
+
+    if diff.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let budget = DIFF_TOKEN_BUDGET * 4;
+    if diff.len() > budget {
+        let mut end = budget;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        let end = diff[..end].rfind('\n').map(|i| i + 1).unwrap_or(end);
+        diff.truncate(end);
+        diff.push_str("[diff truncated]\n");
+    }
+    Ok(Some(diff))
+}
+
+// Splits `git diff` output into one chunk per file, each starting at its `diff --git` line.
+fn file_sections(diff: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = diff.match_indices("diff --git")
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || diff.as_bytes()[i - 1] == b'\n')
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts.iter()
+        .zip(starts.iter().skip(1).chain(std::iter::once(&diff.len())))
+        .map(|(&start, &end)| &diff[start..end])
+        .collect()
+}
+
+pub fn is_ignored(root: &Path, name: &str) -> Result<bool> {
+    // `git check-ignore` exits with 1 when the path is not ignored
+    let status = Command::new("git")
+        .current_dir(root)
+        .args(["check-ignore", "-q", "--no-index", name])
+        .status()
+        .context("Failed to run git check-ignore")?;
+    Ok(status.success())
+}
@@ -0,0 +1,129 @@
+use crate::paths;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One provider request, appended to `history.jsonl` in the state directory.
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub provider: String,
+    pub model: String,
+    /// Until the response headers arrived; missing for streamed requests.
+    pub headers_ms: Option<u64>,
+    /// Until the whole response was read, retries included.
+    pub total_ms: u64,
+    pub ok: bool,
+    /// ~4 characters per token, since not every provider reports usage.
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+fn history_file() -> Result<PathBuf> {
+    Ok(paths::state_dir()?.join("history.jsonl"))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Seconds since the Unix epoch as e.g. `2024-09-01T14:03:22Z`, without pulling in a date
+/// crate.
+pub fn rfc3339(seconds: u64) -> String {
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+pub fn record(request: &Request) -> Result<()> {
+    let path = history_file()?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new().append(true).create(true).open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(request)?)?;
+    Ok(())
+}
+
+// Lines that don't parse (e.g. a write cut short) are skipped rather than failing the report.
+fn load(since: u64) -> Result<Vec<Request>> {
+    let path = history_file()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(contents.lines()
+        .filter_map(|line| serde_json::from_str::<Request>(line).ok())
+        .filter(|request| request.timestamp >= since)
+        .collect())
+}
+
+/// `coders stats`: latency, failure rate and token totals per provider and model over the
+/// last `days` days.
+pub fn print_stats(days: u64) -> Result<()> {
+    let requests = load(now().saturating_sub(days * 86_400))?;
+    if requests.is_empty() {
+        println!("No requests recorded in the last {} day(s).", days);
+        return Ok(());
+    }
+
+    let mut groups: BTreeMap<(&str, &str), Vec<&Request>> = BTreeMap::new();
+    for request in &requests {
+        groups.entry((&request.provider, &request.model)).or_default().push(request);
+    }
+
+    println!("Last {} day(s), {} request(s):\n", days, requests.len());
+    println!("{:<11} {:<45} {:>6} {:>9} {:>9} {:>11} {:>8} {:>10} {:>8}",
+        "provider", "model", "count", "p50 ms", "p95 ms", "p50 hdr ms", "failed", "tokens", "tok/s");
+    for ((provider, model), requests) in groups {
+        let mut total: Vec<u64> = requests.iter().map(|r| r.total_ms).collect();
+        let mut headers: Vec<u64> = requests.iter().filter_map(|r| r.headers_ms).collect();
+        total.sort_unstable();
+        headers.sort_unstable();
+        let failed = requests.iter().filter(|r| !r.ok).count();
+        let tokens: usize = requests.iter().map(|r| r.prompt_tokens + r.completion_tokens).sum();
+
+        // Throughput only counts successful requests, where the completion was produced
+        let (completion_tokens, completion_ms) = requests.iter()
+            .filter(|r| r.ok)
+            .fold((0, 0), |(tokens, ms), r| (tokens + r.completion_tokens as u64, ms + r.total_ms));
+        let tokens_per_second = if completion_ms == 0 { 0.0 } else { completion_tokens as f64 * 1000.0 / completion_ms as f64 };
+
+        println!("{:<11} {:<45} {:>6} {:>9} {:>9} {:>11} {:>7.0}% {:>10} {:>8.1}",
+            provider,
+            model,
+            requests.len(),
+            percentile(&total, 50).map_or("-".to_string(), |ms| ms.to_string()),
+            percentile(&total, 95).map_or("-".to_string(), |ms| ms.to_string()),
+            percentile(&headers, 50).map_or("-".to_string(), |ms| ms.to_string()),
+            failed as f64 * 100.0 / requests.len() as f64,
+            tokens,
+            tokens_per_second);
+    }
+    Ok(())
+}
+
+// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
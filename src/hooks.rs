@@ -0,0 +1,304 @@
+use crate::check;
+use crate::error::CodersError;
+use anyhow::Result;
+use serde::Serialize;
+use std::future::Future;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+// How long a hook may run when the config file sets no `hook_timeout`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// Only the tail of what a failed hook wrote to stderr goes into the error.
+const MAX_STDERR_CHARS: usize = 1000;
+
+/// The config file's `pre_send_hook` and `post_receive_hook`: commands run through the shell
+/// on each completion request body before it is sent, and on each successful response body
+/// before the answer is read from it.
+pub struct Hooks {
+    pre_send: Option<String>,
+    post_receive: Option<String>,
+    timeout: Duration,
+    verbose: bool,
+}
+
+/// What a hook is told about the request, as `CODERS_FILE`, `CODERS_PROVIDER` and
+/// `CODERS_MODEL`.
+#[derive(Clone)]
+pub struct Metadata {
+    pub file: String,
+    pub provider: String,
+    pub model: String,
+}
+
+/// One run of a hook, as the audit artifact of its request records it.
+#[derive(Clone, Serialize)]
+pub struct Invocation {
+    pub hook: &'static str,
+    pub command: String,
+    /// `None` when the hook didn't finish (timed out, killed, or could not be started).
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// Whether the hook's output replaced the request or response.
+    pub changed: bool,
+    pub error: Option<String>,
+}
+
+tokio::task_local! {
+    static METADATA: Metadata;
+}
+
+/// Runs `future` with the hooks applying to the completion requests it sends. Requests sent
+/// outside it (key validation, model lists) don't go through the hooks.
+pub async fn scope<T>(metadata: Metadata, future: impl Future<Output = T>) -> T {
+    METADATA.scope(metadata, future).await
+}
+
+impl Hooks {
+    /// `None` when neither hook is set.
+    pub fn new(pre_send: Option<String>, post_receive: Option<String>, timeout: Option<u64>, verbose: bool) -> Option<Hooks> {
+        let set = |command: Option<String>| command.filter(|command| !command.trim().is_empty());
+        let (pre_send, post_receive) = (set(pre_send), set(post_receive));
+        if pre_send.is_none() && post_receive.is_none() {
+            return None;
+        }
+        Some(Hooks { pre_send, post_receive, timeout: timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs), verbose })
+    }
+
+    /// The request body as `pre_send_hook` rewrote it and the run that did it, `None` outside
+    /// `scope` or without the hook. Empty output leaves the body as it was. A hook that fails,
+    /// times out or prints something other than JSON stops the request.
+    pub async fn pre_send(&self, url: &str, body: &serde_json::Value) -> Option<(Result<serde_json::Value>, Invocation)> {
+        let command = self.pre_send.as_ref()?;
+        let metadata = METADATA.try_with(Metadata::clone).ok()?;
+        let (output, mut invocation) = self.run("pre_send", command, &metadata, url, None, body.to_string()).await;
+        let result = match output {
+            Ok(output) if output.trim().is_empty() => Ok(body.clone()),
+            Ok(output) => serde_json::from_str(&output)
+                .map_err(|e| format!("printed something other than a JSON request body ({})", e)),
+            Err(e) => Err(e),
+        };
+        let result = match result {
+            Ok(rewritten) => {
+                invocation.changed = rewritten != *body;
+                Ok(rewritten)
+            }
+            Err(e) => {
+                invocation.error = Some(e.clone());
+                Err(CodersError::Hook(format!("pre_send_hook `{}` {}; the request was not sent", command, e)).into())
+            }
+        };
+        self.report(&invocation);
+        Some((result, invocation))
+    }
+
+    /// The response body as `post_receive_hook` transformed it, `None` outside `scope` or
+    /// without the hook. A hook that fails or times out is reported with a warning, and the
+    /// response is used as it came.
+    pub async fn post_receive(&self, url: &str, status: u16, body: &str) -> Option<(String, Invocation)> {
+        let command = self.post_receive.as_ref()?;
+        let metadata = METADATA.try_with(Metadata::clone).ok()?;
+        let (output, mut invocation) = self.run("post_receive", command, &metadata, url, Some(status), body.to_string()).await;
+        let transformed = match output {
+            Ok(output) if output.trim().is_empty() => body.to_string(),
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("Warning: post_receive_hook `{}` {}; using the response as it came", command, e);
+                invocation.error = Some(e);
+                body.to_string()
+            }
+        };
+        invocation.changed = transformed != body;
+        self.report(&invocation);
+        Some((transformed, invocation))
+    }
+
+    // The hook's stdout, or why it failed, along with the invocation so far.
+    async fn run(&self, hook: &'static str, command: &str, metadata: &Metadata, url: &str, status: Option<u16>, input: String) -> (Result<String, String>, Invocation) {
+        let started = Instant::now();
+        let mut shell = tokio::process::Command::from(check::shell(command));
+        shell.env("CODERS_HOOK", hook)
+            .env("CODERS_FILE", &metadata.file)
+            .env("CODERS_PROVIDER", &metadata.provider)
+            .env("CODERS_MODEL", &metadata.model)
+            .env("CODERS_URL", url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(status) = status {
+            shell.env("CODERS_STATUS", status.to_string());
+        }
+        let output = async {
+            let mut child = shell.spawn().map_err(|e| format!("could not be started: {}", e))?;
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            // A hook that doesn't read its input is fine; the pipe closes when it's dropped
+            let write = async move {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            };
+            let (_, output) = tokio::join!(write, child.wait_with_output());
+            output.map_err(|e| format!("failed: {}", e))
+        };
+        let output = tokio::time::timeout(self.timeout, output).await
+            .unwrap_or_else(|_| Err(format!("did not finish within {}s (hook_timeout)", self.timeout.as_secs())));
+        let mut invocation = Invocation {
+            hook,
+            command: command.to_string(),
+            exit_code: None,
+            duration_ms: started.elapsed().as_millis() as u64,
+            changed: false,
+            error: None,
+        };
+        let result = output.and_then(|output| {
+            invocation.exit_code = output.status.code();
+            if output.status.success() {
+                String::from_utf8(output.stdout).map_err(|_| "printed output that isn't UTF-8".to_string())
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = stderr.trim();
+                let tail: String = stderr.chars().skip(stderr.chars().count().saturating_sub(MAX_STDERR_CHARS)).collect();
+                Err(if tail.is_empty() {
+                    format!("exited with {}", output.status)
+                } else {
+                    format!("exited with {}: {}", output.status, tail)
+                })
+            }
+        });
+        (result, invocation)
+    }
+
+    fn report(&self, invocation: &Invocation) {
+        if self.verbose {
+            let outcome = match (&invocation.error, invocation.changed) {
+                (Some(_), _) => "failed",
+                (None, true) => "changed the body",
+                (None, false) => "left the body as it was",
+            };
+            say!(
+                "Ran {}_hook `{}`: {} (exit {}, {} ms)",
+                invocation.hook, invocation.command, outcome,
+                invocation.exit_code.map_or("none".to_string(), |code| code.to_string()), invocation.duration_ms
+            );
+        }
+    }
+}
+
+// The hooks below are sh commands, and `check::shell` runs cmd on Windows.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const URL: &str = "https://api.groq.com/openai/v1/chat/completions";
+
+    fn metadata() -> Metadata {
+        Metadata { file: "src/add.rs".to_string(), provider: "groq".to_string(), model: "llama3-70b-8192".to_string() }
+    }
+
+    // Hooks that time out after a second.
+    fn hooks(pre_send: &str, post_receive: &str) -> Hooks {
+        Hooks::new(Some(pre_send.to_string()), Some(post_receive.to_string()), Some(1), false).unwrap()
+    }
+
+    // What pre_send_hook `command` makes of `body`, inside `scope`.
+    async fn pre_send(command: &str, body: serde_json::Value) -> (Result<serde_json::Value>, Invocation) {
+        scope(metadata(), hooks(command, "true").pre_send(URL, &body)).await.expect("the hook runs")
+    }
+
+    #[test]
+    fn blank_commands_set_no_hooks() {
+        assert!(Hooks::new(None, None, None, false).is_none());
+        assert!(Hooks::new(Some("  ".to_string()), Some(String::new()), Some(5), false).is_none());
+        assert!(Hooks::new(None, Some("cat".to_string()), None, false).is_some());
+    }
+
+    #[tokio::test]
+    async fn hooks_only_run_inside_scope() {
+        let hooks = hooks("echo '{}'", "echo changed");
+
+        assert!(hooks.pre_send(URL, &json!({"model": "m"})).await.is_none());
+        assert!(hooks.post_receive(URL, 200, "{}").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn pre_send_rewrites_the_body() {
+        let (body, invocation) = pre_send(r#"sed 's/"m"/"rewritten"/'"#, json!({"model": "m"})).await;
+
+        assert_eq!(body.unwrap(), json!({"model": "rewritten"}));
+        assert!(invocation.changed);
+        assert_eq!(invocation.exit_code, Some(0));
+        assert_eq!(invocation.hook, "pre_send");
+    }
+
+    #[tokio::test]
+    async fn pre_send_printing_nothing_or_the_same_body_leaves_it_unchanged() {
+        let (body, invocation) = pre_send("cat > /dev/null", json!({"model": "m"})).await;
+        assert_eq!(body.unwrap(), json!({"model": "m"}));
+        assert!(!invocation.changed);
+
+        let (body, invocation) = pre_send("cat", json!({"model": "m"})).await;
+        assert_eq!(body.unwrap(), json!({"model": "m"}));
+        assert!(!invocation.changed);
+    }
+
+    #[tokio::test]
+    async fn pre_send_sees_the_request_metadata() {
+        let command = r#"printf '{"hook":"%s","file":"%s","provider":"%s","model":"%s","url":"%s"}' "$CODERS_HOOK" "$CODERS_FILE" "$CODERS_PROVIDER" "$CODERS_MODEL" "$CODERS_URL""#;
+
+        let (body, _) = pre_send(command, json!({})).await;
+
+        assert_eq!(body.unwrap(), json!({
+            "hook": "pre_send", "file": "src/add.rs", "provider": "groq", "model": "llama3-70b-8192", "url": URL,
+        }));
+    }
+
+    #[tokio::test]
+    async fn a_failing_pre_send_stops_the_request_with_its_stderr() {
+        let (body, invocation) = pre_send("echo 'no paths allowed' >&2; exit 3", json!({})).await;
+
+        let message = body.unwrap_err().to_string();
+        assert!(message.contains("exited with exit status: 3: no paths allowed"), "{}", message);
+        assert!(message.ends_with("the request was not sent"), "{}", message);
+        assert_eq!(invocation.exit_code, Some(3));
+        assert!(invocation.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn pre_send_printing_something_other_than_json_stops_the_request() {
+        let (body, invocation) = pre_send("echo not json", json!({})).await;
+
+        assert!(body.unwrap_err().to_string().contains("printed something other than a JSON request body"));
+        assert_eq!(invocation.exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn a_pre_send_that_hangs_is_stopped_at_the_timeout() {
+        let (body, invocation) = pre_send("sleep 5", json!({})).await;
+
+        assert!(body.unwrap_err().to_string().contains("did not finish within 1s (hook_timeout)"));
+        assert_eq!(invocation.exit_code, None);
+        assert!(invocation.duration_ms < 5000);
+    }
+
+    #[tokio::test]
+    async fn post_receive_transforms_the_response_and_sees_the_status() {
+        let hooks = hooks("true", r#"tr a-z A-Z; printf ' %s' "$CODERS_STATUS""#);
+
+        let (body, invocation) = scope(metadata(), hooks.post_receive(URL, 200, "answer")).await.unwrap();
+
+        assert_eq!(body, "ANSWER 200");
+        assert!(invocation.changed);
+    }
+
+    #[tokio::test]
+    async fn a_failing_post_receive_falls_back_to_the_response_as_it_came() {
+        let hooks = hooks("true", "echo partial; exit 1");
+
+        let (body, invocation) = scope(metadata(), hooks.post_receive(URL, 200, "answer")).await.unwrap();
+
+        assert_eq!(body, "answer");
+        assert!(!invocation.changed);
+        assert_eq!(invocation.exit_code, Some(1));
+        assert_eq!(invocation.error.as_deref(), Some("exited with exit status: 1"));
+    }
+}
@@ -0,0 +1,447 @@
+use crate::error::CodersError;
+use crate::hooks;
+use anyhow::{Context, Result};
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// 429s are retried this many times, waiting 1s, 2s, 4s unless the provider says how long.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+// A longer Retry-After means a quota window rather than a burst limit; give up instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+type Review = Box<dyn Fn(&str, &serde_json::Value) -> Result<()>>;
+
+/// A POST sent through the transport while `capture` was running, for `--audit-dir`.
+pub struct Exchange {
+    pub url: String,
+    pub body: serde_json::Value,
+    /// The status and body of the final attempt; `None` when no response arrived.
+    pub response: Option<(u16, String)>,
+    /// The `pre_send_hook` and `post_receive_hook` runs on it, in order.
+    pub hooks: Vec<hooks::Invocation>,
+}
+
+tokio::task_local! {
+    static EXCHANGES: RefCell<Vec<Exchange>>;
+}
+
+/// Runs `future` and returns the POSTs it sent along with its output. Scoped to the future
+/// rather than the transport, so requests in flight for other files aren't mixed in.
+pub async fn capture<T>(future: impl Future<Output = T>) -> (T, Vec<Exchange>) {
+    EXCHANGES.scope(RefCell::new(Vec::new()), async {
+        let output = future.await;
+        (output, EXCHANGES.with(RefCell::take))
+    }).await
+}
+
+// All three are no-ops outside `capture`.
+fn capture_request(url: &str, body: &serde_json::Value, hooks: Vec<hooks::Invocation>) {
+    let _ = EXCHANGES.try_with(|exchanges| exchanges.borrow_mut().push(Exchange { url: url.to_string(), body: body.clone(), response: None, hooks }));
+}
+
+fn capture_hook(invocation: hooks::Invocation) {
+    let _ = EXCHANGES.try_with(|exchanges| {
+        if let Some(exchange) = exchanges.borrow_mut().last_mut() {
+            exchange.hooks.push(invocation);
+        }
+    });
+}
+
+fn capture_response(response: &HttpResponse) {
+    let _ = EXCHANGES.try_with(|exchanges| {
+        if let Some(exchange) = exchanges.borrow_mut().last_mut() {
+            exchange.response = Some((response.status.as_u16(), response.body.clone()));
+        }
+    });
+}
+
+enum Mode {
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// All provider traffic goes through here so that sessions can be recorded to disk with
+/// `--record` and served back without any network access with `--replay`.
+pub struct Transport {
+    client: Client,
+    mode: Mode,
+    extra_headers: Vec<(String, String)>,
+    allowed_hosts: Vec<String>,
+    headers_latency: Cell<Option<Duration>>,
+    // Set by the first streamed token, for --fallback-model
+    answering: Cell<bool>,
+    // `--timeout`, for each attempt of a request
+    timeout: Option<Duration>,
+    review: Option<Review>,
+    hooks: Option<hooks::Hooks>,
+    // Keys of the bodies `review` let through, so a retry of the same body isn't asked again
+    reviewed: RefCell<HashSet<String>>,
+}
+
+impl Transport {
+    pub fn new(record: Option<PathBuf>, replay: Option<PathBuf>, extra_headers: Vec<(String, String)>, allowed_hosts: Vec<String>) -> Transport {
+        let mode = match (record, replay) {
+            (_, Some(dir)) => Mode::Replay(dir),
+            (Some(dir), None) => Mode::Record(dir),
+            (None, None) => Mode::Live,
+        };
+        Transport {
+            client: Client::new(),
+            mode,
+            extra_headers,
+            allowed_hosts,
+            headers_latency: Cell::new(None),
+            answering: Cell::new(false),
+            timeout: None,
+            review: None,
+            hooks: None,
+            reviewed: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Gives up on an attempt that hasn't received its whole response within `timeout`; the
+    /// rate-limit retries each get their own.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Has every POST body pass through `review` before it is sent (or replayed); an error
+    /// from it stops the request. Used by `--show-context`.
+    pub fn set_review(&mut self, review: impl Fn(&str, &serde_json::Value) -> Result<()> + 'static) {
+        self.review = Some(Box::new(review));
+    }
+
+    /// Runs completion requests through the config file's `pre_send_hook` and
+    /// `post_receive_hook`; see `hooks::scope` for which requests count.
+    pub fn set_hooks(&mut self, hooks: Option<hooks::Hooks>) {
+        self.hooks = hooks;
+    }
+
+    /// Runs the `set_review` hook on a body that hasn't been through it yet. Callers that
+    /// show a spinner during the request call this first so the hook can prompt; `send`
+    /// calls it regardless, so nothing goes out unreviewed.
+    pub fn review(&self, url: &str, body: &serde_json::Value) -> Result<()> {
+        let Some(review) = &self.review else {
+            return Ok(());
+        };
+        let key = request_key(&Method::POST, url, Some(body));
+        if !self.reviewed.borrow().contains(&key) {
+            review(url, body)?;
+            self.reviewed.borrow_mut().insert(key);
+        }
+        Ok(())
+    }
+
+    pub fn is_live(&self) -> bool {
+        matches!(self.mode, Mode::Live)
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// How long the last live `get`/`post_json` waited for response headers, for the
+    /// request history. Cleared by reading it.
+    pub fn take_headers_latency(&self) -> Option<Duration> {
+        self.headers_latency.take()
+    }
+
+    /// Notes that a streamed answer has started arriving.
+    pub fn mark_answering(&self) {
+        self.answering.set(true);
+    }
+
+    /// Whether a streamed answer started arriving since the last call. Streaming has one
+    /// request in flight at a time, so there is no telling whose answer it was to get wrong.
+    pub fn take_answering(&self) -> bool {
+        self.answering.take()
+    }
+
+    /// A POST request carrying the user's `--header` values, for callers that need to
+    /// drive the response themselves (streaming).
+    pub fn post(&self, url: &str) -> Result<RequestBuilder> {
+        self.check_allowed(url)?;
+        let mut request = self.client.post(url);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        for (name, value) in &self.extra_headers {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+
+    /// A `--timeout` that ran out as a `CodersError::Timeout`; other errors as they are.
+    pub fn timed_out(&self, url: &str, error: reqwest::Error) -> anyhow::Error {
+        match self.timeout.filter(|_| error.is_timeout()) {
+            Some(timeout) => CodersError::Timeout(format!("no complete answer from {} within {}s (--timeout)", url, timeout.as_secs())).into(),
+            None => error.into(),
+        }
+    }
+
+    /// Refuses URLs whose host isn't in the config's `allowed_hosts` (when it is set), so no
+    /// request can leave for an endpoint the user hasn't approved.
+    fn check_allowed(&self, url: &str) -> Result<(), CodersError> {
+        if self.allowed_hosts.is_empty() {
+            return Ok(());
+        }
+        let host = Url::parse(url).ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        if self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host)) {
+            Ok(())
+        } else {
+            Err(CodersError::Config(format!("Refusing to contact {}: host '{}' is not in allowed_hosts", url, host)))
+        }
+    }
+
+    pub async fn get(&self, url: &str, headers: &[(&str, String)]) -> Result<HttpResponse> {
+        self.send(Method::GET, url, headers, None).await
+    }
+
+    pub async fn post_json(&self, url: &str, headers: &[(&str, String)], body: &serde_json::Value) -> Result<HttpResponse> {
+        self.send(Method::POST, url, headers, Some(body)).await
+    }
+
+    // The hooks go around everything else: the rewritten body is what is reviewed, recorded
+    // and sent, while recordings and audit artifacts keep the response as it came.
+    async fn send(&self, method: Method, url: &str, headers: &[(&str, String)], body: Option<&serde_json::Value>) -> Result<HttpResponse> {
+        self.check_allowed(url)?;
+        let hooks = self.hooks.as_ref().filter(|_| method == Method::POST);
+        let mut invocations = Vec::new();
+        let rewritten;
+        let body = match (hooks, body) {
+            (Some(hooks), Some(body)) => match hooks.pre_send(url, body).await {
+                Some((result, invocation)) => {
+                    invocations.push(invocation);
+                    match result {
+                        Ok(body) => {
+                            rewritten = body;
+                            Some(&rewritten)
+                        }
+                        Err(e) => {
+                            capture_request(url, body, invocations);
+                            return Err(e);
+                        }
+                    }
+                }
+                None => Some(body),
+            },
+            (_, body) => body,
+        };
+        if let Some(body) = body.filter(|_| method == Method::POST) {
+            self.review(url, body)?;
+            capture_request(url, body, invocations);
+        }
+
+        let mut response = self.exchange(method, url, headers, body).await?;
+        if let Some(hooks) = hooks.filter(|_| response.status.is_success()) {
+            if let Some((transformed, invocation)) = hooks.post_receive(url, response.status.as_u16(), &response.body).await {
+                response.body = transformed;
+                capture_hook(invocation);
+            }
+        }
+        Ok(response)
+    }
+
+    async fn exchange(&self, method: Method, url: &str, headers: &[(&str, String)], body: Option<&serde_json::Value>) -> Result<HttpResponse> {
+        let key = request_key(&method, url, body);
+
+        if let Mode::Replay(dir) = &self.mode {
+            let path = dir.join(format!("{}.json", key));
+            let recording = fs::read_to_string(&path)
+                .with_context(|| format!("No recorded response for {} {} (expected {})", method, url, path.display()))?;
+            let recording: serde_json::Value = serde_json::from_str(&recording)
+                .with_context(|| format!("Invalid recording: {}", path.display()))?;
+            let status = recording["response"]["status"].as_u64().unwrap_or(0) as u16;
+            let response = HttpResponse {
+                status: StatusCode::from_u16(status)
+                    .with_context(|| format!("Invalid status in recording: {}", path.display()))?,
+                body: recording["response"]["body"].as_str().unwrap_or_default().to_string(),
+            };
+            if method == Method::POST {
+                capture_response(&response);
+            }
+            return Ok(response);
+        }
+
+        let mut attempt = 0;
+        let response = loop {
+            let mut request = self.client.request(method.clone(), url);
+            if let Some(timeout) = self.timeout {
+                request = request.timeout(timeout);
+            }
+            for (name, value) in &self.extra_headers {
+                request = request.header(name, value);
+            }
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+            if let Some(body) = body {
+                request = request.header("Content-Type", "application/json").json(body);
+            }
+            let started = Instant::now();
+            let response = request.send().await.map_err(|e| self.timed_out(url, e))?;
+            self.headers_latency.set(Some(started.elapsed()));
+            let retry_after = retry_after(&response);
+            let response = HttpResponse {
+                status: response.status(),
+                body: response.text().await.map_err(|e| self.timed_out(url, e))?,
+            };
+
+            if response.status != StatusCode::TOO_MANY_REQUESTS
+                || attempt == MAX_RATE_LIMIT_RETRIES
+                || CodersError::is_out_of_credits(response.status.as_u16(), &response.body)
+            {
+                break response;
+            }
+            let wait = retry_after.unwrap_or(Duration::from_secs(1 << attempt));
+            if wait > MAX_RETRY_AFTER {
+                break response;
+            }
+            attempt += 1;
+            if crate::console::enabled() {
+                eprintln!("Rate limited by {}; retrying in {}s ({}/{})", url, wait.as_secs(), attempt, MAX_RATE_LIMIT_RETRIES);
+            }
+            tokio::time::sleep(wait).await;
+        };
+        if method == Method::POST {
+            capture_response(&response);
+        }
+
+        if let Mode::Record(dir) = &self.mode {
+            let recorded_headers: serde_json::Map<String, serde_json::Value> = self.extra_headers.iter()
+                .map(|(name, value)| (name.as_str(), value))
+                .chain(headers.iter().map(|(name, value)| (*name, value)))
+                .filter(|(name, _)| !is_sensitive_header(name))
+                .map(|(name, value)| (name.to_string(), json!(value)))
+                .collect();
+            let recording = json!({
+                "request": {
+                    "method": method.as_str(),
+                    "url": url,
+                    "headers": recorded_headers,
+                    "body": body,
+                },
+                "response": {
+                    "status": response.status.as_u16(),
+                    "body": response.body,
+                },
+            });
+            fs::create_dir_all(dir)?;
+            let path = dir.join(format!("{}.json", key));
+            fs::write(&path, serde_json::to_string_pretty(&recording)?)
+                .with_context(|| format!("Failed to write recording: {}", path.display()))?;
+        }
+
+        Ok(response)
+    }
+}
+
+// Only the delay-seconds form; an HTTP date falls back to the default backoff.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response.headers().get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Parses a `--header "Name: Value"` argument.
+pub fn parse_header(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value.split_once(':')
+        .ok_or_else(|| format!("'{}' is not in the form \"Name: Value\"", value))?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("'{}' is not a valid header name", name));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+fn is_sensitive_header(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "authorization" || name.contains("api-key") || name.contains("token")
+}
+
+// FNV-1a rather than `DefaultHasher`, whose output isn't guaranteed to be stable across
+// Rust releases and would invalidate recordings.
+fn request_key(method: &Method, url: &str, body: Option<&serde_json::Value>) -> String {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in method.as_str().bytes().chain(url.bytes()).chain(body.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Saves what `--replay` answers a POST of `body` to `url` with, for tests that send
+/// requests without the network.
+#[cfg(test)]
+pub fn record(dir: &std::path::Path, url: &str, body: &serde_json::Value, status: u16, response: &str) {
+    let recording = json!({
+        "request": { "method": "POST", "url": url, "headers": {}, "body": body },
+        "response": { "status": status, "body": response },
+    });
+    let path = dir.join(format!("{}.json", request_key(&Method::POST, url, Some(body))));
+    fs::write(path, recording.to_string()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPENROUTER: &str = "https://openrouter.ai/api/v1/chat/completions";
+    const GROQ: &str = "https://api.groq.com/openai/v1/chat/completions";
+
+    // A replay transport that could answer both OpenRouter and Groq, so only `allowed_hosts`
+    // stands between a request and its answer.
+    fn transport(dir: &std::path::Path, allowed_hosts: &[&str]) -> Transport {
+        let body = json!({"model": "m", "messages": []});
+        record(dir, OPENROUTER, &body, 200, "{}");
+        record(dir, GROQ, &body, 200, "{}");
+        Transport::new(None, Some(dir.to_path_buf()), Vec::new(), allowed_hosts.iter().map(|host| host.to_string()).collect())
+    }
+
+    #[tokio::test]
+    async fn a_host_outside_allowed_hosts_is_refused_before_anything_is_sent() {
+        let dir = tempfile::tempdir().unwrap();
+        let http = transport(dir.path(), &["api.groq.com"]);
+
+        let (result, sent) = capture(http.post_json(OPENROUTER, &[], &json!({"model": "m", "messages": []}))).await;
+
+        let error = result.err().expect("openrouter.ai is not allowed");
+        assert!(matches!(error.downcast_ref(), Some(CodersError::Config(message)) if message.contains("host 'openrouter.ai' is not in allowed_hosts")), "{}", error);
+        assert!(sent.is_empty());
+        assert!(http.post(OPENROUTER).is_err());
+    }
+
+    #[tokio::test]
+    async fn an_allowed_host_is_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let http = transport(dir.path(), &["API.Groq.com"]);
+
+        let response = http.post_json(GROQ, &[], &json!({"model": "m", "messages": []})).await.unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn no_allowed_hosts_allows_every_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let http = transport(dir.path(), &[]);
+
+        assert!(http.post_json(OPENROUTER, &[], &json!({"model": "m", "messages": []})).await.is_ok());
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+// What OpenAI accepts per image; most compatible providers take less, and say so.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// `--image`: the file as a `data:` URL for an `image_url` content part. The format is taken
+/// from the file's first bytes, not its extension, and must be PNG, JPEG, GIF or WebP.
+pub fn data_url(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read image: {}", path.display()))?;
+    let Some(mime) = mime_type(&bytes) else {
+        return Err(anyhow::anyhow!("{} is not a PNG, JPEG, GIF or WebP image", path.display()));
+    };
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(anyhow::anyhow!("{} is {} bytes; images can be at most {} MB", path.display(), bytes.len(), MAX_IMAGE_BYTES / 1024 / 1024));
+    }
+    Ok(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(&bytes)))
+}
+
+fn mime_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How a file indents its blocks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Style {
+    Tabs,
+    Spaces(usize),
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Style::Tabs => write!(f, "tabs"),
+            Style::Spaces(width) => write!(f, "{} spaces", width),
+        }
+    }
+}
+
+// A line indented by a single space is alignment (e.g. ` * ` in a block comment), not a block.
+const MIN_SPACE_INDENT: usize = 2;
+// The less common style may indent up to this share of the lines before a file counts as mixed.
+const MAX_MINORITY_PERCENT: usize = 10;
+
+/// The dominant style of `content`, or `None` when nothing in it is indented. An error
+/// describes a file where tabs and spaces are both common.
+pub fn detect(content: &str) -> Result<Option<Style>, String> {
+    let (mut tabs, mut spaces) = (0, 0);
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            tabs += 1;
+        } else if leading_spaces(line) >= MIN_SPACE_INDENT {
+            spaces += 1;
+        }
+    }
+    if tabs + spaces == 0 {
+        return Ok(None);
+    }
+    if tabs.min(spaces) * 100 > (tabs + spaces) * MAX_MINORITY_PERCENT {
+        return Err(format!("{} lines are indented with tabs and {} with spaces", tabs, spaces));
+    }
+    Ok(Some(if tabs > spaces { Style::Tabs } else { Style::Spaces(space_width(content)) }))
+}
+
+/// Re-indents the lines of `code` that aren't in `original` to the original's style, keeping
+/// the nesting depth and any alignment past it (e.g. continuation lines). Code whose style
+/// already matches, or can't be told, is returned unchanged. Fails when the original's own
+/// indentation is mixed.
+pub fn conform(original: &str, code: &str) -> Result<String, String> {
+    let Some(target) = detect(original)? else {
+        return Ok(code.to_string());
+    };
+    let unchanged: HashSet<&str> = original.lines().collect();
+    let changed: Vec<&str> = code.lines().filter(|line| !unchanged.contains(line)).collect();
+    let source = match detect(&changed.join("\n")) {
+        Ok(Some(source)) if source != target => source,
+        _ => return Ok(code.to_string()),
+    };
+
+    let mut conformed: Vec<String> = code.lines()
+        .map(|line| if unchanged.contains(line) { line.to_string() } else { reindent(line, source, target) })
+        .collect();
+    if code.ends_with('\n') {
+        conformed.push(String::new());
+    }
+    Ok(conformed.join("\n"))
+}
+
+fn leading_spaces(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+// The most common step between the indentation of consecutive lines, preferring the
+// narrower one on a tie; 4 when no line is indented deeper than the one before.
+fn space_width(content: &str) -> usize {
+    let mut steps: HashMap<usize, usize> = HashMap::new();
+    let mut previous = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('\t')) {
+        let indent = leading_spaces(line);
+        if (MIN_SPACE_INDENT..=8).contains(&indent.saturating_sub(previous)) {
+            *steps.entry(indent - previous).or_default() += 1;
+        }
+        previous = indent;
+    }
+    steps.into_iter()
+        .max_by(|(a_width, a_count), (b_width, b_count)| a_count.cmp(b_count).then(b_width.cmp(a_width)))
+        .map_or(4, |(width, _)| width)
+}
+
+// Whole levels of `source` become levels of `target`; spaces left over past the last level
+// are alignment and stay spaces. Lines mixing both kinds of indentation are left alone.
+fn reindent(line: &str, source: Style, target: Style) -> String {
+    let text = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - text.len()];
+    if text.is_empty() {
+        return line.to_string();
+    }
+    let (levels, alignment) = match source {
+        Style::Tabs => {
+            let tabs = indent.len() - indent.trim_start_matches('\t').len();
+            if indent[tabs..].contains('\t') {
+                return line.to_string();
+            }
+            (tabs, indent.len() - tabs)
+        }
+        Style::Spaces(width) => {
+            if indent.contains('\t') {
+                return line.to_string();
+            }
+            (indent.len() / width, indent.len() % width)
+        }
+    };
+    let levels = match target {
+        Style::Tabs => "\t".repeat(levels),
+        Style::Spaces(width) => " ".repeat(levels * width),
+    };
+    format!("{}{}{}", levels, " ".repeat(alignment), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_dominant_style_is_detected() {
+        assert_eq!(detect("fn a() {\n\tb();\n\t\tc();\n}"), Ok(Some(Style::Tabs)));
+        assert_eq!(detect("def a():\n  b()\n  if c:\n    d()"), Ok(Some(Style::Spaces(2))));
+        assert_eq!(detect("fn a() {\n    b();\n        c();\n}"), Ok(Some(Style::Spaces(4))));
+        assert_eq!(detect("a\n * b\nc"), Ok(None));
+    }
+
+    #[test]
+    fn a_file_mixing_tabs_and_spaces_is_refused() {
+        assert_eq!(detect("a\n\tb\n    c\n\td\n    e"), Err("2 lines are indented with tabs and 2 with spaces".to_string()));
+    }
+
+    #[test]
+    fn spaces_in_new_code_become_the_original_tabs() {
+        let original = "func a() {\n\tb()\n}\n";
+        let code = "func a() {\n    b()\n    if c {\n        d()\n    }\n}\n";
+
+        assert_eq!(conform(original, code), Ok("func a() {\n\tb()\n\tif c {\n\t\td()\n\t}\n}\n".to_string()));
+    }
+
+    #[test]
+    fn tabs_in_new_code_become_the_original_spaces() {
+        let original = "def a():\n  b()\n";
+        let code = "def a():\n\tb()\n\tif c:\n\t\td()\n";
+
+        assert_eq!(conform(original, code), Ok("def a():\n  b()\n  if c:\n    d()\n".to_string()));
+    }
+
+    #[test]
+    fn alignment_past_the_last_level_stays_spaces() {
+        let original = "fn a() {\n\tb();\n}";
+        let code = "fn a() {\n    call(one,\n         two);\n}";
+
+        assert_eq!(conform(original, code), Ok("fn a() {\n\tcall(one,\n\t\t two);\n}".to_string()));
+    }
+
+    #[test]
+    fn unchanged_lines_and_matching_code_are_left_alone() {
+        let original = "fn a() {\n\tb();\n}";
+
+        assert_eq!(conform(original, "fn a() {\n\tb();\n\tc();\n}"), Ok("fn a() {\n\tb();\n\tc();\n}".to_string()));
+        assert_eq!(conform("a\nb", "a\n    b"), Ok("a\n    b".to_string()));
+    }
+
+    #[test]
+    fn a_mixed_original_fails_the_conversion() {
+        assert!(conform("a\n\tb\n    c", "a\n  b").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::azure;
+use crate::catalog;
+use crate::cli::{choose_model, fuzzy_match_models, get_or_prompt_for_api_key, Cli};
+use crate::config;
+use crate::error::CodersError;
+use crate::http;
+use crate::paths;
+use crate::prompt::ask;
+use crate::Provider;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::fs;
+
+// Longer live lists (OpenRouter has hundreds of models) are searched by name instead of
+// printed as a menu.
+const MAX_MENU_MODELS: usize = 20;
+
+/// `coders init`: picks a default provider, stores and validates its key, chooses a default
+/// model and writes the result to the config file, keeping any settings already there.
+pub async fn run_init(cli: &Cli, config: &config::Config) -> Result<()> {
+    println!("Let's set up coders. Available providers:");
+    let providers = Provider::value_variants();
+    for (i, provider) in providers.iter().enumerate() {
+        println!("{}. {:<11} {}", i + 1, provider.id(), description(*provider));
+    }
+    let provider = loop {
+        let choice = ask("Default provider [1]: ")?;
+        if choice.is_empty() {
+            break providers[0];
+        }
+        match choice.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= providers.len() => break providers[n - 1],
+            _ => println!("Please enter a number between 1 and {}.", providers.len()),
+        }
+    };
+
+    let mut section = toml::Table::new();
+    let azure = if provider == Provider::Azure {
+        let endpoint = ask("Azure endpoint (e.g. https://my-resource.openai.azure.com): ")?;
+        let deployment = ask("Azure deployment name: ")?;
+        let deployment = azure::Deployment::resolve(Some(&endpoint), Some(&deployment), None, None)?;
+        section.insert("endpoint".to_string(), deployment.endpoint.clone().into());
+        section.insert("deployment".to_string(), deployment.name.clone().into());
+        Some(deployment)
+    } else {
+        None
+    };
+
+    let base_url = cli.base_url(config, provider);
+    let http = http::Transport::new(None, None, cli.headers.clone(), config.allowed_hosts.clone());
+    let api_key = get_or_prompt_for_api_key(&http, provider.name(), &base_url, azure.as_ref(), true, true).await?;
+
+    if azure.is_none() {
+        let model = choose_default_model(&http, provider, &base_url, &api_key, cli.refresh).await?;
+        section.insert("default_model".to_string(), model.into());
+    }
+
+    let color = !ask("Colored diff output? [Y/n]: ")?.eq_ignore_ascii_case("n");
+
+    let path = paths::config_file()?;
+    let mut table = if path.exists() {
+        fs::read_to_string(&path)?.parse::<toml::Table>()
+            .map_err(|e| CodersError::Config(format!("Failed to parse config file {}: {}", path.display(), e)))?
+    } else {
+        toml::Table::new()
+    };
+    table.insert("provider".to_string(), provider.id().into());
+    table.insert("color".to_string(), color.into());
+    let providers_table = table.entry("providers")
+        .or_insert_with(|| toml::Table::new().into())
+        .as_table_mut()
+        .ok_or_else(|| CodersError::Config("`providers` in the config file is not a table".to_string()))?;
+    let provider_table = providers_table.entry(provider.id())
+        .or_insert_with(|| toml::Table::new().into())
+        .as_table_mut()
+        .ok_or_else(|| CodersError::Config(format!("`providers.{}` in the config file is not a table", provider.id())))?;
+    provider_table.extend(section);
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, toml::to_string(&table)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    println!("\nSaved {}", path.display());
+    println!("Try it with:\n  coders -f src/main.rs");
+    Ok(())
+}
+
+fn description(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Hyperbolic => "Llama 3.1 405B and other open models, free signup credits",
+        Provider::OpenRouter => "one key for many hosted models, including free Hermes 3",
+        Provider::Gemini => "Google's Gemini 1.5 models",
+        Provider::Groq => "very fast Llama 3.1 inference",
+        Provider::Cohere => "Command R and R+ models",
+        Provider::Azure => "your organization's Azure OpenAI deployment",
+    }
+}
+
+async fn choose_default_model(http: &http::Transport, provider: Provider, base_url: &str, api_key: &str, refresh: bool) -> Result<String> {
+    let live = match catalog::load(http, provider, base_url, api_key, refresh).await {
+        Ok(models) if !models.is_empty() => models,
+        _ => {
+            println!("Could not fetch the live model list; choosing from the known models.");
+            Vec::new()
+        }
+    };
+    let models: Vec<&str> = if live.is_empty() {
+        provider.models()
+    } else {
+        live.iter().map(|model| model.id.as_str()).collect()
+    };
+
+    if models.len() <= MAX_MENU_MODELS {
+        return choose_model(&models, &live);
+    }
+
+    println!("{} has {} models.", provider.name(), models.len());
+    loop {
+        let query = ask(&format!("Default model (part of a name, Enter for {}): ", provider.default_model()))?;
+        if query.is_empty() {
+            return Ok(provider.default_model().to_string());
+        }
+        match fuzzy_match_models(&query, &models).as_slice() {
+            [] => println!("No model matches '{}'.", query),
+            [model] => return Ok(model.to_string()),
+            matches => return choose_model(matches, &live),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::azure;
+use crate::cli::{models_endpoint, Cli};
+use crate::config;
+use crate::error::CodersError;
+use crate::http;
+use crate::paths;
+use crate::Provider;
+use anyhow::Result;
+use clap::ValueEnum;
+use futures_util::future::join_all;
+use std::fs;
+
+enum Status {
+    Valid,
+    Invalid,
+    OutOfCredits,
+    NoKey,
+    Failed(String),
+}
+
+/// `coders keys`: checks the key of every provider against its models endpoint, without
+/// sending a completion or prompting for anything. Fails when a key was rejected.
+pub async fn run_check(cli: &Cli, config: &config::Config) -> Result<()> {
+    let http = http::Transport::new(None, None, cli.headers.clone(), config.allowed_hosts.clone());
+    let providers = Provider::value_variants();
+    let results = join_all(providers.iter().map(|provider| check(cli, config, &http, *provider))).await;
+
+    println!("{:<11} {:<20} status", "provider", "key");
+    let mut rejected = None;
+    for (provider, (source, status)) in providers.iter().zip(results) {
+        let status = match status {
+            Status::Valid => "valid".to_string(),
+            Status::Invalid => {
+                rejected.get_or_insert(provider.name());
+                "invalid (rejected or expired)".to_string()
+            }
+            Status::OutOfCredits => "valid, out of credits".to_string(),
+            Status::NoKey => "no key".to_string(),
+            Status::Failed(reason) => format!("not checked: {}", reason),
+        };
+        println!("{:<11} {:<20} {}", provider.id(), source, status);
+    }
+
+    match rejected {
+        Some(provider) => Err(CodersError::ApiKeyInvalid { provider: provider.to_string() }.into()),
+        None => Ok(()),
+    }
+}
+
+async fn check(cli: &Cli, config: &config::Config, http: &http::Transport, provider: Provider) -> (String, Status) {
+    let Some((source, api_key)) = stored_key(provider) else {
+        return ("-".to_string(), Status::NoKey);
+    };
+
+    if provider == Provider::Azure {
+        let deployment = match azure::Deployment::resolve(
+            cli.azure_endpoint.as_deref(),
+            cli.azure_deployment.as_deref(),
+            cli.azure_api_version.as_deref(),
+            config.provider(provider.id()),
+        ) {
+            Ok(deployment) => deployment,
+            Err(e) => return (source, Status::Failed(e.to_string())),
+        };
+        let status = match azure::validate_api_key(http, &deployment, &api_key).await {
+            Ok(true) => Status::Valid,
+            Ok(false) => Status::Invalid,
+            Err(e) => Status::Failed(format!("{:#}", e)),
+        };
+        return (source, status);
+    }
+
+    let Some((url, auth)) = models_endpoint(provider.name(), &base_url(cli, config, provider), &api_key) else {
+        return (source, Status::Failed("no models endpoint".to_string()));
+    };
+    let status = match http.get(&url, &[auth]).await {
+        Ok(response) if response.status.is_success() => Status::Valid,
+        Ok(response) => match CodersError::from_response(provider.name(), response.status.as_u16(), &response.body) {
+            CodersError::ApiKeyInvalid { .. } => Status::Invalid,
+            CodersError::OutOfCredits { .. } => Status::OutOfCredits,
+            e => Status::Failed(e.to_string()),
+        },
+        Err(e) => Status::Failed(format!("{:#}", e)),
+    };
+    (source, status)
+}
+
+/// The provider's base URL; `--base-url` is meant for the selected provider only.
+pub fn base_url(cli: &Cli, config: &config::Config, provider: Provider) -> String {
+    if provider == cli.provider(config) {
+        cli.base_url(config, provider)
+    } else {
+        config.endpoints.get(provider.id()).map_or(provider.default_base_url(), String::as_str).trim_end_matches('/').to_string()
+    }
+}
+
+/// Where the key comes from ("saved" or the environment variable) and the key, with the
+/// same precedence as a normal run: the environment variable, then the saved key file.
+pub fn stored_key(provider: Provider) -> Option<(String, String)> {
+    let env_var = format!("{}_API_KEY", provider.name().to_uppercase());
+    if let Some(api_key) = std::env::var(&env_var).ok().filter(|key| !key.trim().is_empty()) {
+        return Some((env_var, api_key.trim().to_string()));
+    }
+    let saved = fs::read_to_string(paths::api_key_file(provider.name()).ok()?).ok()?;
+    Some(("saved".to_string(), saved.trim().to_string())).filter(|(_, key)| !key.is_empty())
+}
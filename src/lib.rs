@@ -0,0 +1,384 @@
+//! Edits code with a language model. The `coders` binary is the command line; as a library
+//! the crate exposes [`run`], which makes one edit of in-memory content and returns the
+//! result instead of showing and writing it:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut request = coders::EditRequest::new(
+//!     "fn add(a: i32, b: i32) -> i32 {\n    a - b\n}\n",
+//!     "rust",
+//!     "Fix the bug in add",
+//!     coders::api::Provider::OpenRouter,
+//!     "nousresearch/hermes-3-llama-3.1-405b",
+//!     std::env::var("OPENROUTER_API_KEY")?,
+//! );
+//! request.params.temperature = Some(0.2);
+//!
+//! let outcome = coders::run(request).await?;
+//! for change in &outcome.changes {
+//!     println!("{:?} line {}: {}", change.kind, change.line, change.content);
+//! }
+//! println!("~{} tokens in {:?}", outcome.usage.prompt_tokens + outcome.usage.completion_tokens, outcome.latency);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`run`] needs a Tokio runtime. The [`api`] module, the re-exports here and [`Error`]
+//! follow semantic versioning; nothing else in the crate is public.
+
+use clap::ValueEnum;
+
+pub use api::{run, EditOutcome, EditRequest};
+pub use cli::cli_main;
+pub use error::CodersError as Error;
+
+// First, so its `say!` macro is available to the modules after it
+#[macro_use]
+mod console;
+
+pub mod api;
+mod apply;
+mod audit;
+mod azure;
+mod batch;
+mod catalog;
+mod check;
+mod cleanup;
+mod cli;
+mod cohere;
+mod config;
+mod edit;
+mod error;
+mod export;
+mod gemini;
+mod git;
+mod history;
+mod hooks;
+mod init;
+mod keys;
+mod http;
+mod image;
+mod indent;
+mod memory;
+mod merge;
+mod messages;
+mod moves;
+mod openai_compat;
+mod patch;
+mod paths;
+mod preview;
+mod privacy;
+mod profile;
+mod prompt;
+mod prompts;
+mod render;
+mod repo_map;
+mod routing;
+mod scope;
+mod secrets;
+mod slim;
+mod stream;
+mod structured;
+mod syntax;
+mod taskfile;
+mod tasks;
+mod undo;
+
+#[derive(Clone, ValueEnum)]
+enum OpenRouterModel {
+    #[value(name = "nousresearch/hermes-3-llama-3.1-405b")]
+    NousHermes3Llama31405B,
+    #[value(name = "nousresearch/hermes-3-llama-3.1-405b:extended")]
+    NousHermes3Llama31405BExtended,
+    #[value(name = "meta-llama/llama-3.1-8b-instruct:free")]
+    MetaLlama318BInstructFree,
+}
+
+#[derive(Clone, ValueEnum)]
+enum HyperbolicModel {
+    #[value(name = "nous-hermes-3-llama-3-1-70b")]
+    NousHermes3Llama3170B,
+    #[value(name = "meta-llama-3-1-70b-instruct")]
+    MetaLlama3170BInstruct,
+    #[value(name = "meta-llama-3-1-8b-instruct")]
+    MetaLlama318BInstruct,
+    #[value(name = "meta-llama-3-1-405b-instruct")]
+    MetaLlama31405BInstruct,
+    #[value(name = "meta-llama-3-1-405b")]
+    MetaLlama31405B,
+}
+
+#[derive(Clone, ValueEnum)]
+enum GroqModel {
+    #[value(name = "llama-3.1-70b-versatile")]
+    Llama3170BVersatile,
+    #[value(name = "llama-3.1-8b-instant")]
+    Llama318BInstant,
+}
+
+impl GroqModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GroqModel::Llama3170BVersatile => "llama-3.1-70b-versatile",
+            GroqModel::Llama318BInstant => "llama-3.1-8b-instant",
+        }
+    }
+
+    fn all() -> Vec<GroqModel> {
+        vec![
+            GroqModel::Llama3170BVersatile,
+            GroqModel::Llama318BInstant,
+        ]
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum GeminiModel {
+    #[value(name = "gemini-1.5-flash")]
+    Gemini15Flash,
+    #[value(name = "gemini-1.5-pro")]
+    Gemini15Pro,
+}
+
+impl GeminiModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GeminiModel::Gemini15Flash => "gemini-1.5-flash",
+            GeminiModel::Gemini15Pro => "gemini-1.5-pro",
+        }
+    }
+
+    fn all() -> Vec<GeminiModel> {
+        vec![
+            GeminiModel::Gemini15Flash,
+            GeminiModel::Gemini15Pro,
+        ]
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum CohereModel {
+    #[value(name = "command-r-plus")]
+    CommandRPlus,
+    #[value(name = "command-r")]
+    CommandR,
+    #[value(name = "command")]
+    Command,
+}
+
+impl CohereModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CohereModel::CommandRPlus => "command-r-plus",
+            CohereModel::CommandR => "command-r",
+            CohereModel::Command => "command",
+        }
+    }
+
+    fn all() -> Vec<CohereModel> {
+        vec![
+            CohereModel::CommandRPlus,
+            CohereModel::CommandR,
+            CohereModel::Command,
+        ]
+    }
+}
+
+// What a model accepts in a request, so the message builder can fit the request to it.
+#[derive(Clone, Copy)]
+struct Capabilities {
+    // A `system` message; without it the instructions lead the first user message
+    system_role: bool,
+    // The few-shot example turns
+    few_shot: bool,
+    // Chat messages rather than a plain completions prompt
+    chat: bool,
+    // `image_url` content parts in the user message
+    vision: bool,
+}
+
+impl Capabilities {
+    const FULL: Capabilities = Capabilities { system_role: true, few_shot: true, chat: true, vision: true };
+
+    // The fallback after a provider rejected the message layout.
+    fn degraded(self) -> Capabilities {
+        Capabilities { system_role: false, few_shot: false, ..self }
+    }
+
+    // Models without metadata are assumed to accept everything; a 400 about the layout
+    // still gets the degraded retry.
+    fn of(provider: Provider, model: &str) -> Capabilities {
+        Capabilities::known(provider, model).unwrap_or(Capabilities::FULL)
+    }
+
+    // What the built-in model tables say, for the models they have.
+    fn known(provider: Provider, model: &str) -> Option<Capabilities> {
+        match provider {
+            Provider::OpenRouter => OpenRouterModel::from_id(model).map(|model| model.capabilities()),
+            Provider::Hyperbolic => HyperbolicModel::from_id(model).map(|model| model.capabilities()),
+            _ => None,
+        }
+    }
+}
+
+impl OpenRouterModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OpenRouterModel::NousHermes3Llama31405B => "nousresearch/hermes-3-llama-3.1-405b",
+            OpenRouterModel::NousHermes3Llama31405BExtended => "nousresearch/hermes-3-llama-3.1-405b:extended",
+            OpenRouterModel::MetaLlama318BInstructFree => "meta-llama/llama-3.1-8b-instruct:free",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<OpenRouterModel> {
+        OpenRouterModel::all().into_iter().find(|model| model.as_str() == id)
+    }
+
+    // Context window in tokens as OpenRouter lists each variant; `:extended` is the
+    // long-context endpoint of the same model.
+    fn context_length(&self) -> u32 {
+        match self {
+            OpenRouterModel::NousHermes3Llama31405B => 8_192,
+            OpenRouterModel::NousHermes3Llama31405BExtended => 128_000,
+            OpenRouterModel::MetaLlama318BInstructFree => 8_192,
+        }
+    }
+
+    // `:free` variants cost nothing but are rate limited per account.
+    fn is_free(&self) -> bool {
+        matches!(self, OpenRouterModel::MetaLlama318BInstructFree)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        match self {
+            OpenRouterModel::NousHermes3Llama31405B
+            | OpenRouterModel::NousHermes3Llama31405BExtended
+            | OpenRouterModel::MetaLlama318BInstructFree => Capabilities { vision: false, ..Capabilities::FULL },
+        }
+    }
+
+    // A quarter of the context for the answer, so a small window still has room for the file.
+    fn default_max_tokens(&self) -> u32 {
+        (self.context_length() / 4).clamp(1024, 8192)
+    }
+
+    fn all() -> Vec<OpenRouterModel> {
+        vec![
+            OpenRouterModel::NousHermes3Llama31405B,
+            OpenRouterModel::NousHermes3Llama31405BExtended,
+            OpenRouterModel::MetaLlama318BInstructFree,
+        ]
+    }
+}
+
+impl HyperbolicModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HyperbolicModel::NousHermes3Llama3170B => "NousResearch/Hermes-3-Llama-3.1-70B",
+            HyperbolicModel::MetaLlama3170BInstruct => "meta-llama/Meta-Llama-3.1-70B-Instruct",
+            HyperbolicModel::MetaLlama318BInstruct => "meta-llama/Meta-Llama-3.1-8B-Instruct",
+            HyperbolicModel::MetaLlama31405BInstruct => "meta-llama/Meta-Llama-3.1-405B-Instruct",
+            HyperbolicModel::MetaLlama31405B => "meta-llama/Meta-Llama-3.1-405B",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<HyperbolicModel> {
+        HyperbolicModel::all().into_iter().find(|model| model.as_str() == id)
+    }
+
+    // Base models are served by the plain completions endpoint rather than chat.
+    fn capabilities(&self) -> Capabilities {
+        match self {
+            HyperbolicModel::MetaLlama31405B => Capabilities { chat: false, vision: false, ..Capabilities::FULL },
+            _ => Capabilities { vision: false, ..Capabilities::FULL },
+        }
+    }
+
+    fn all() -> Vec<HyperbolicModel> {
+        vec![
+            HyperbolicModel::NousHermes3Llama3170B,
+            HyperbolicModel::MetaLlama3170BInstruct,
+            HyperbolicModel::MetaLlama318BInstruct,
+            HyperbolicModel::MetaLlama31405BInstruct,
+            HyperbolicModel::MetaLlama31405B,
+        ]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Provider {
+    #[value(name = "hyperbolic")]
+    Hyperbolic,
+    #[value(name = "openrouter")]
+    OpenRouter,
+    #[value(name = "gemini")]
+    Gemini,
+    #[value(name = "groq")]
+    Groq,
+    #[value(name = "azure")]
+    Azure,
+    #[value(name = "cohere")]
+    Cohere,
+}
+
+impl Provider {
+    // Matches the `--provider` value and the `[providers.<id>]` config section.
+    fn id(&self) -> &'static str {
+        match self {
+            Provider::Hyperbolic => "hyperbolic",
+            Provider::OpenRouter => "openrouter",
+            Provider::Gemini => "gemini",
+            Provider::Groq => "groq",
+            Provider::Azure => "azure",
+            Provider::Cohere => "cohere",
+        }
+    }
+
+    // Also used to name the provider's key file and in messages.
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Hyperbolic => "Hyperbolic",
+            Provider::OpenRouter => "OpenRouter",
+            Provider::Gemini => "Gemini",
+            Provider::Groq => "Groq",
+            Provider::Azure => "Azure",
+            Provider::Cohere => "Cohere",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            Provider::Hyperbolic => HyperbolicModel::MetaLlama31405BInstruct.as_str(),
+            Provider::OpenRouter => OpenRouterModel::NousHermes3Llama31405B.as_str(),
+            Provider::Gemini => GeminiModel::Gemini15Flash.as_str(),
+            Provider::Groq => GroqModel::Llama3170BVersatile.as_str(),
+            // Azure routes by deployment name, so there is no model to pick
+            Provider::Azure => "",
+            Provider::Cohere => CohereModel::CommandRPlus.as_str(),
+        }
+    }
+
+    fn models(&self) -> Vec<&'static str> {
+        match self {
+            Provider::Hyperbolic => HyperbolicModel::all().iter().map(HyperbolicModel::as_str).collect(),
+            Provider::OpenRouter => OpenRouterModel::all().iter().map(OpenRouterModel::as_str).collect(),
+            Provider::Gemini => GeminiModel::all().iter().map(GeminiModel::as_str).collect(),
+            Provider::Groq => GroqModel::all().iter().map(GroqModel::as_str).collect(),
+            Provider::Azure => Vec::new(),
+            Provider::Cohere => CohereModel::all().iter().map(CohereModel::as_str).collect(),
+        }
+    }
+
+    // Request paths such as `/chat/completions` are appended to this. Azure's endpoint is
+    // per resource, see `azure::Deployment`.
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            Provider::Hyperbolic => "https://api.hyperbolic.xyz/v1",
+            Provider::OpenRouter => "https://openrouter.ai/api/v1",
+            Provider::Gemini => "https://generativelanguage.googleapis.com/v1beta",
+            Provider::Groq => "https://api.groq.com/openai/v1",
+            Provider::Azure => "",
+            Provider::Cohere => "https://api.cohere.ai/v1",
+        }
+    }
+}
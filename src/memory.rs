@@ -0,0 +1,248 @@
+use crate::apply;
+use crate::history;
+use crate::paths;
+use crate::slim;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Per file; older proposals are dropped as new ones are recorded.
+const MAX_ENTRIES: usize = 10;
+// What the summary may add to a request, newest proposals first.
+const TOKEN_BUDGET: usize = 500;
+// Changed lines kept per proposal, and the characters kept of each.
+const EXCERPT_LINES: usize = 4;
+const EXCERPT_WIDTH: usize = 100;
+
+/// `--with-memory`: the proposals made for one file in earlier runs, in
+/// `memory/<hash of its path>.json` in the state directory.
+#[derive(Default, Serialize, Deserialize)]
+struct Memory {
+    /// Absolute path of the file, for `coders memory show`.
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+/// One proposal that was shown for the file, and what the developer did with it.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The request, or the feedback that asked for this revision.
+    pub prompt: String,
+    /// The change summary, e.g. "+12 -3 ~7 lines across 2 hunks".
+    pub summary: String,
+    /// The first changed lines, as the plain diff shows them.
+    pub excerpt: Vec<String>,
+    pub accepted: bool,
+}
+
+impl Entry {
+    pub fn new(prompt: &str, summary: &str, changed_lines: &[String], accepted: bool) -> Entry {
+        let excerpt = changed_lines.iter()
+            .take(EXCERPT_LINES)
+            .map(|line| match line.char_indices().nth(EXCERPT_WIDTH) {
+                Some((end, _)) => format!("{}...", &line[..end]),
+                None => line.clone(),
+            })
+            .collect();
+        Entry { timestamp: history::now(), prompt: prompt.to_string(), summary: summary.to_string(), excerpt, accepted }
+    }
+}
+
+fn memory_file(file: &str) -> Result<(PathBuf, PathBuf)> {
+    let path = apply::resolved_path(file);
+    let hash = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+    Ok((path, paths::state_dir()?.join("memory").join(format!("{}.json", &hash[..16]))))
+}
+
+fn load(memory_path: &Path) -> Result<Option<Memory>> {
+    match fs::read_to_string(memory_path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid memory file {}", memory_path.display()))?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to read {}", memory_path.display()))),
+    }
+}
+
+/// Adds a proposal to the memory of `file`.
+pub fn record(file: &str, entry: Entry) -> Result<()> {
+    let (path, memory_path) = memory_file(file)?;
+    remember(&memory_path, path, entry)
+}
+
+fn remember(memory_path: &Path, path: PathBuf, entry: Entry) -> Result<()> {
+    let mut memory = load(memory_path)?.unwrap_or_default();
+    memory.path = path;
+    memory.entries.push(entry);
+    if memory.entries.len() > MAX_ENTRIES {
+        memory.entries.drain(..memory.entries.len() - MAX_ENTRIES);
+    }
+    if let Some(dir) = memory_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(memory_path, serde_json::to_string_pretty(&memory)?)
+        .with_context(|| format!("Failed to write {}", memory_path.display()))
+}
+
+/// What earlier runs proposed for `file`, to add to the request: one line per proposal with
+/// its prompt and outcome, and its first changed lines. The newest proposals are kept when
+/// they don't all fit in the token budget. `None` when there is nothing to tell.
+pub fn context(file: &str) -> Result<Option<String>> {
+    let (_, memory_path) = memory_file(file)?;
+    Ok(load(&memory_path)?.and_then(|memory| describe(&memory)))
+}
+
+fn describe(memory: &Memory) -> Option<String> {
+    let mut described = Vec::new();
+    let mut tokens = 0;
+    for entry in memory.entries.iter().rev() {
+        let mut text = format!(
+            "- {}, asked \"{}\": {} ({})",
+            &history::rfc3339(entry.timestamp)[..10],
+            entry.prompt,
+            if entry.accepted { "accepted" } else { "rejected" },
+            entry.summary
+        );
+        for line in &entry.excerpt {
+            text.push_str(&format!("\n    {}", line));
+        }
+        tokens += slim::estimate_tokens(&text);
+        if tokens > TOKEN_BUDGET {
+            break;
+        }
+        described.push(text);
+    }
+    if described.is_empty() {
+        return None;
+    }
+    described.reverse();
+    Some(format!(
+        "Changes proposed for this file in earlier sessions, oldest first:\n{}\nDon't propose a rejected change again unless asked to.",
+        described.join("\n")
+    ))
+}
+
+/// `coders memory show <FILE>`.
+pub fn show(file: &str) -> Result<()> {
+    let (path, memory_path) = memory_file(file)?;
+    let entries = load(&memory_path)?.map(|memory| memory.entries).unwrap_or_default();
+    if entries.is_empty() {
+        println!("No memory of {}.", path.display());
+        return Ok(());
+    }
+    println!("{} proposal(s) remembered for {}:", entries.len(), path.display());
+    for entry in &entries {
+        println!("\n{}  {}  \"{}\"", history::rfc3339(entry.timestamp), if entry.accepted { "accepted" } else { "rejected" }, entry.prompt);
+        println!("  {}", entry.summary);
+        for line in &entry.excerpt {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
+
+/// `coders memory clear <FILE>`, or every file's memory with `file` left out.
+pub fn clear(file: Option<&str>) -> Result<()> {
+    let Some(file) = file else {
+        let dir = paths::state_dir()?.join("memory");
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => println!("Cleared the memory of every file."),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => println!("No memory to clear."),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to remove {}", dir.display()))),
+        }
+        return Ok(());
+    };
+    let (path, memory_path) = memory_file(file)?;
+    match fs::remove_file(&memory_path) {
+        Ok(()) => println!("Cleared the memory of {}.", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => println!("No memory of {}.", path.display()),
+        Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to remove {}", memory_path.display()))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-01T00:00:00Z
+    const MARCH_1: u64 = 1_709_251_200;
+
+    // An entry recorded on March 1st with `prompt`, changing one line.
+    fn entry(prompt: &str, accepted: bool) -> Entry {
+        Entry { timestamp: MARCH_1, prompt: prompt.to_string(), summary: "+1 -1 lines".to_string(), excerpt: vec!["-a".to_string(), "+b".to_string()], accepted }
+    }
+
+    fn memory(entries: Vec<Entry>) -> Memory {
+        Memory { path: PathBuf::from("/work/add.rs"), entries }
+    }
+
+    #[test]
+    fn the_summary_lists_proposals_oldest_first_with_their_outcome() {
+        let summary = describe(&memory(vec![entry("Use checked_add", false), entry("Add a doc comment", true)])).unwrap();
+
+        assert_eq!(summary, "Changes proposed for this file in earlier sessions, oldest first:\n\
+            - 2024-03-01, asked \"Use checked_add\": rejected (+1 -1 lines)\n    -a\n    +b\n\
+            - 2024-03-01, asked \"Add a doc comment\": accepted (+1 -1 lines)\n    -a\n    +b\n\
+            Don't propose a rejected change again unless asked to.");
+    }
+
+    #[test]
+    fn the_token_budget_keeps_the_newest_proposals() {
+        let entries = (0..MAX_ENTRIES).map(|i| entry(&format!("request {} {}", i, "x".repeat(300)), true)).collect();
+
+        let summary = describe(&memory(entries)).unwrap();
+
+        assert!(summary.contains("request 9 "));
+        assert!(!summary.contains("request 0 "));
+        assert!(slim::estimate_tokens(&summary) < TOKEN_BUDGET + 50);
+    }
+
+    #[test]
+    fn nothing_to_tell_is_no_summary() {
+        assert_eq!(describe(&memory(Vec::new())), None);
+        assert_eq!(describe(&memory(vec![entry(&"x".repeat(4 * TOKEN_BUDGET), true)])), None);
+    }
+
+    #[test]
+    fn entries_keep_the_first_changed_lines_cut_to_width() {
+        let lines: Vec<String> = vec!["+".repeat(EXCERPT_WIDTH + 20), "-b".to_string(), "+c".to_string(), "-d".to_string(), "+e".to_string()];
+
+        let entry = Entry::new("Fix it", "+3 -2 lines", &lines, true);
+
+        assert_eq!(entry.excerpt.len(), EXCERPT_LINES);
+        assert_eq!(entry.excerpt[0], format!("{}...", "+".repeat(EXCERPT_WIDTH)));
+        assert_eq!(entry.excerpt[1..], ["-b", "+c", "-d"]);
+    }
+
+    #[test]
+    fn recording_keeps_the_last_entries_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory_path = dir.path().join("memory").join("add.json");
+
+        for i in 0..MAX_ENTRIES + 3 {
+            remember(&memory_path, PathBuf::from("/work/add.rs"), entry(&format!("request {}", i), true)).unwrap();
+        }
+
+        let memory = load(&memory_path).unwrap().unwrap();
+        assert_eq!(memory.path, Path::new("/work/add.rs"));
+        assert_eq!(memory.entries.len(), MAX_ENTRIES);
+        assert_eq!(memory.entries[0].prompt, "request 3");
+        assert_eq!(memory.entries[MAX_ENTRIES - 1].prompt, format!("request {}", MAX_ENTRIES + 2));
+    }
+
+    #[test]
+    fn a_missing_memory_file_is_no_memory_and_a_corrupt_one_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory_path = dir.path().join("add.json");
+        assert!(load(&memory_path).unwrap().is_none());
+
+        fs::write(&memory_path, "{").unwrap();
+        let error = load(&memory_path).err().expect("a corrupt file is refused");
+        assert!(error.to_string().starts_with("Invalid memory file"));
+    }
+}
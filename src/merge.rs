@@ -0,0 +1,268 @@
+use crate::indent;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum EditMode {
+    /// Merge the model's code into the file line by line
+    Merge,
+    /// Add the model's code after the original content, leaving every existing line alone
+    Append,
+    /// Write the model's code as the whole file; set by --whole-file-diff
+    #[value(skip)]
+    Replace,
+}
+
+#[derive(Debug)]
+pub enum ChangeType {
+    Insert,
+    Delete,
+    Modify,
+}
+
+#[derive(Debug)]
+pub struct Change {
+    pub change_type: ChangeType,
+    pub line_number: usize,
+    pub content: String,
+}
+
+// The model's code re-indented to the file's tabs or spaces, so touched lines don't switch
+// style. `verbose` says so, or warns when the file's own indentation is mixed.
+pub fn match_indentation(file: &str, original: &str, code: &str, verbose: bool) -> String {
+    match indent::conform(original, code) {
+        Ok(conformed) => {
+            if verbose && conformed != code {
+                if let Ok(Some(style)) = indent::detect(original) {
+                    say!("Re-indented the proposal with {} to match {}.", style, file);
+                }
+            }
+            conformed
+        }
+        Err(reason) => {
+            if verbose {
+                say!("Warning: {} mixes indentation ({}); leaving the proposal's indentation as it is.", file, reason);
+            }
+            code.to_string()
+        }
+    }
+}
+
+// The file as it would be written, with the changes to show for it.
+pub fn combine(mode: EditMode, original: &str, code: &str) -> (String, Vec<Change>) {
+    match mode {
+        EditMode::Merge => smart_merge(original, code),
+        EditMode::Append => append(original, code),
+        EditMode::Replace => replace(original, code),
+    }
+}
+
+// `--whole-file-diff`: the code is the file, verbatim, and the diff compares it with the
+// original line for line however much the two differ.
+pub fn replace(original: &str, code: &str) -> (String, Vec<Change>) {
+    let original_lines: Vec<&str> = original.lines().collect();
+    let new_lines: Vec<&str> = code.lines().collect();
+    (code.to_string(), full_file_diff(&original_lines, &new_lines).1)
+}
+
+// `--mode append`: the code goes after the original, separated by a blank line, and every
+// line of it shows as an insert.
+pub fn append(original: &str, code: &str) -> (String, Vec<Change>) {
+    let code = code.trim_matches('\n');
+    if code.trim().is_empty() {
+        return (original.to_string(), Vec::new());
+    }
+    let mut appended = original.to_string();
+    if !appended.is_empty() {
+        if !appended.ends_with('\n') {
+            appended.push('\n');
+        }
+        appended.push('\n');
+    }
+    let first_line = appended.lines().count() + 1;
+    let changes = code.lines().enumerate()
+        .map(|(i, line)| Change { change_type: ChangeType::Insert, line_number: first_line + i, content: line.to_string() })
+        .collect();
+    appended.push_str(code);
+    appended.push('\n');
+    (appended, changes)
+}
+
+pub fn smart_merge(original: &str, new: &str) -> (String, Vec<Change>) {
+    let original_lines: Vec<&str> = original.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // A new or empty file has nothing to merge into; every line is an insert
+    if original_lines.is_empty() {
+        return full_file_diff(&original_lines, &new_lines);
+    }
+    // If the number of lines is significantly different, treat it as a full file replacement
+    if (new_lines.len() as f32 / original_lines.len() as f32).abs() > 0.5 {
+        return full_file_diff(&original_lines, &new_lines);
+    }
+
+    let mut updated_lines = original_lines.clone();
+    let mut changes = Vec::new();
+
+    for (i, (old_line, new_line)) in original_lines.iter().zip(new_lines.iter()).enumerate() {
+        if old_line != new_line {
+            changes.push(Change {
+                change_type: ChangeType::Modify,
+                line_number: i + 1,
+                content: new_line.to_string(),
+            });
+            updated_lines[i] = new_line;
+        }
+    }
+
+    for (i, new_line) in new_lines.iter().enumerate().skip(original_lines.len()) {
+        changes.push(Change {
+            change_type: ChangeType::Insert,
+            line_number: i + 1,
+            content: new_line.to_string(),
+        });
+        updated_lines.push(new_line);
+    }
+
+
+    for (i, old_line) in original_lines.iter().enumerate().skip(new_lines.len()) {
+        changes.push(Change {
+            change_type: ChangeType::Delete,
+            line_number: i + 1,
+            content: old_line.to_string(),
+        });
+    }
+
+    (updated_lines.join("\n"), changes)
+}
+
+pub fn full_file_diff(original_lines: &[&str], new_lines: &[&str]) -> (String, Vec<Change>) {
+    // An answer of blank lines gives an empty file nothing
+    if original_lines.is_empty() && new_lines.iter().all(|line| line.trim().is_empty()) {
+        return (String::new(), Vec::new());
+    }
+    let mut changes = Vec::new();
+
+    for (i, line) in new_lines.iter().enumerate() {
+        if i < original_lines.len() {
+            if line != &original_lines[i] {
+                changes.push(Change {
+                    change_type: ChangeType::Modify,
+                    line_number: i + 1,
+                    content: line.to_string(),
+                });
+            }
+        } else {
+            changes.push(Change {
+                change_type: ChangeType::Insert,
+                line_number: i + 1,
+                content: line.to_string(),
+            });
+        }
+    }
+
+    for (i, old_line) in original_lines.iter().enumerate().skip(new_lines.len()) {
+        changes.push(Change {
+            change_type: ChangeType::Delete,
+            line_number: i + 1,
+            content: old_line.to_string(),
+        });
+    }
+
+    (new_lines.join("\n"), changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (kind, line, content) of each change, for comparing.
+    fn summary(changes: &[Change]) -> Vec<(&'static str, usize, &str)> {
+        changes.iter()
+            .map(|change| {
+                let kind = match change.change_type {
+                    ChangeType::Insert => "insert",
+                    ChangeType::Delete => "delete",
+                    ChangeType::Modify => "modify",
+                };
+                (kind, change.line_number, change.content.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merging_into_an_empty_file_inserts_every_line() {
+        let (merged, changes) = smart_merge("", "pub struct RateLimiter;\n\nimpl RateLimiter {}\n");
+
+        assert_eq!(merged, "pub struct RateLimiter;\n\nimpl RateLimiter {}");
+        assert_eq!(summary(&changes), vec![("insert", 1, "pub struct RateLimiter;"), ("insert", 2, ""), ("insert", 3, "impl RateLimiter {}")]);
+    }
+
+    #[test]
+    fn merging_nothing_into_an_empty_file_changes_nothing() {
+        let (merged, changes) = smart_merge("", "");
+
+        assert_eq!(merged, "");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn a_file_of_one_newline_has_one_empty_line_to_replace() {
+        let (merged, changes) = smart_merge("\n", "fn main() {}\n");
+
+        assert_eq!(merged, "fn main() {}");
+        assert_eq!(summary(&changes), vec![("modify", 1, "fn main() {}")]);
+    }
+
+    #[test]
+    fn an_empty_answer_for_an_empty_file_leaves_it_empty() {
+        for answer in ["\n", "\n\n", "  \n\t\n"] {
+            let (merged, changes) = smart_merge("", answer);
+            assert_eq!(merged, "");
+            assert!(changes.is_empty(), "{:?}", answer);
+
+            let (replaced, changes) = replace("", answer);
+            assert!(changes.is_empty(), "{:?}", answer);
+            assert!(replaced.trim().is_empty());
+        }
+    }
+
+    #[test]
+    fn the_full_diff_from_nothing_is_all_inserts() {
+        let (content, changes) = full_file_diff(&[], &["a", "b"]);
+
+        assert_eq!(content, "a\nb");
+        assert_eq!(summary(&changes), vec![("insert", 1, "a"), ("insert", 2, "b")]);
+    }
+
+    #[test]
+    fn the_full_diff_to_nothing_is_all_deletes() {
+        let (content, changes) = full_file_diff(&["a", "b"], &[]);
+
+        assert_eq!(content, "");
+        assert_eq!(summary(&changes), vec![("delete", 1, "a"), ("delete", 2, "b")]);
+    }
+
+    #[test]
+    fn the_full_diff_of_two_empty_files_is_empty() {
+        let (content, changes) = full_file_diff(&[], &[]);
+
+        assert_eq!(content, "");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn appending_to_an_empty_file_adds_no_blank_line() {
+        let (content, changes) = append("", "fn main() {}");
+
+        assert_eq!(content, "fn main() {}\n");
+        assert_eq!(summary(&changes), vec![("insert", 1, "fn main() {}")]);
+    }
+
+    #[test]
+    fn replacing_an_empty_file_keeps_the_code_verbatim() {
+        let (content, changes) = replace("", "fn main() {}\n");
+
+        assert_eq!(content, "fn main() {}\n");
+        assert_eq!(summary(&changes), vec![("insert", 1, "fn main() {}")]);
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// One message of a `--messages-file`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// Reads a `--messages-file`: a JSON array of `{"role", "content"}` objects, sent in place of
+/// the built-in system prompt and examples. A malformed file is an error before anything is
+/// sent.
+pub fn read(path: &Path) -> Result<Vec<Message>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let messages: Vec<Message> = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("{}: {} (expected an array of {{\"role\", \"content\"}} objects with role system, user or assistant)", path.display(), e))?;
+    if messages.is_empty() {
+        return Err(anyhow::anyhow!("{}: there are no messages", path.display()));
+    }
+    Ok(messages)
+}
+
+/// The messages as OpenAI-compatible chat messages, followed by the request itself.
+pub fn chat_messages(messages: &[Message], user_message: &str) -> Vec<serde_json::Value> {
+    messages.iter()
+        .map(|message| json!({"role": message.role.name(), "content": message.content}))
+        .chain([json!({"role": "user", "content": user_message})])
+        .collect()
+}
+
+/// The same instruction document `completion_prompt` makes for base models, with the
+/// messages as its sections.
+pub fn completion_prompt(messages: &[Message], user_message: &str) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        let heading = match message.role {
+            Role::System => "Instruction",
+            Role::User => "Request",
+            Role::Assistant => "Response",
+        };
+        prompt.push_str(&format!("### {}:\n{}\n\n", heading, message.content));
+    }
+    prompt.push_str(&format!("### Request:\n{}\n\n### Response:\n", user_message));
+    prompt
+}
+
+/// The system messages joined, for providers that take the system prompt on its own.
+pub fn system(messages: &[Message]) -> Option<String> {
+    let system: Vec<&str> = messages.iter()
+        .filter(|message| message.role == Role::System)
+        .map(|message| message.content.as_str())
+        .collect();
+    (!system.is_empty()).then(|| system.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_str(contents: &str) -> Result<Vec<Message>> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), contents).unwrap();
+        read(file.path())
+    }
+
+    #[test]
+    fn a_messages_file_is_sent_before_the_request() {
+        let messages = read_str(r#"[{"role": "system", "content": "Be terse."}, {"role": "user", "content": "Hi"}, {"role": "assistant", "content": "Ok."}]"#).unwrap();
+
+        assert_eq!(chat_messages(&messages, "Fix it"), vec![
+            json!({"role": "system", "content": "Be terse."}),
+            json!({"role": "user", "content": "Hi"}),
+            json!({"role": "assistant", "content": "Ok."}),
+            json!({"role": "user", "content": "Fix it"}),
+        ]);
+        assert_eq!(
+            completion_prompt(&messages, "Fix it"),
+            "### Instruction:\nBe terse.\n\n### Request:\nHi\n\n### Response:\nOk.\n\n### Request:\nFix it\n\n### Response:\n"
+        );
+    }
+
+    #[test]
+    fn the_system_messages_are_joined() {
+        let messages = read_str(r#"[{"role": "system", "content": "One."}, {"role": "user", "content": "Hi"}, {"role": "system", "content": "Two."}]"#).unwrap();
+
+        assert_eq!(system(&messages).as_deref(), Some("One.\n\nTwo."));
+        assert_eq!(system(&messages[1..2]), None);
+    }
+
+    #[test]
+    fn a_malformed_messages_file_is_refused() {
+        for contents in ["[]", r#"[{"role": "tool", "content": "x"}]"#, r#"[{"role": "user", "content": "x", "name": "me"}]"#, r#"{"role": "user"}"#] {
+            assert!(read_str(contents).is_err(), "{}", contents);
+        }
+    }
+}
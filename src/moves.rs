@@ -0,0 +1,161 @@
+use similar::{DiffOp, TextDiff};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+// Shorter runs (a closing brace and a blank line) repeat too often to say anything was moved.
+const MIN_MOVED_LINES: usize = 3;
+
+/// A run of lines the proposal removed in one place and put back unchanged in another, as
+/// 0-based line ranges of the original and the proposal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Move {
+    pub from: Range<usize>,
+    pub to: Range<usize>,
+}
+
+/// One entry of the review diff, in file order: a removed line of the original, an added line
+/// of the proposal (both 0-based), or a moved block, listed where it was taken from.
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    Deleted(usize),
+    Inserted(usize),
+    Moved(Move),
+}
+
+/// The line diff of `original` and `proposed`, with the blocks that were only moved picked out
+/// of the deletions and insertions. `None` when nothing was moved, so the usual diff applies.
+pub fn classify(original: &str, proposed: &str) -> Option<Vec<Entry>> {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = proposed.lines().collect();
+    let diff = TextDiff::from_slices(&old, &new);
+
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Equal { .. } => {}
+            DiffOp::Delete { old_index, old_len, .. } => deleted.push(old_index..old_index + old_len),
+            DiffOp::Insert { new_index, new_len, .. } => inserted.push(new_index..new_index + new_len),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                deleted.push(old_index..old_index + old_len);
+                inserted.push(new_index..new_index + new_len);
+            }
+        }
+    }
+    let moves = find_moves(&old, &new, &deleted, &inserted);
+    if moves.is_empty() {
+        return None;
+    }
+
+    let moved_from: HashMap<usize, &Move> = moves.iter().map(|moved| (moved.from.start, moved)).collect();
+    let moved_old: HashSet<usize> = moves.iter().flat_map(|moved| moved.from.clone()).collect();
+    let moved_new: HashSet<usize> = moves.iter().flat_map(|moved| moved.to.clone()).collect();
+    let mut entries = Vec::new();
+    for op in diff.ops() {
+        let (old_range, new_range) = match *op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete { old_index, old_len, .. } => (old_index..old_index + old_len, 0..0),
+            DiffOp::Insert { new_index, new_len, .. } => (0..0, new_index..new_index + new_len),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => (old_index..old_index + old_len, new_index..new_index + new_len),
+        };
+        for line in old_range {
+            if let Some(moved) = moved_from.get(&line) {
+                entries.push(Entry::Moved((*moved).clone()));
+            } else if !moved_old.contains(&line) {
+                entries.push(Entry::Deleted(line));
+            }
+        }
+        entries.extend(new_range.filter(|line| !moved_new.contains(line)).map(Entry::Inserted));
+    }
+    Some(entries)
+}
+
+// Walks the deleted lines in order and, at each, takes the longest run of identical lines
+// that starts on an inserted line not claimed yet. Runs stay within one deleted and one
+// inserted range, and must have something besides blank lines.
+fn find_moves(old: &[&str], new: &[&str], deleted: &[Range<usize>], inserted: &[Range<usize>]) -> Vec<Move> {
+    let mut starts: HashMap<&str, Vec<usize>> = HashMap::new();
+    for range in inserted {
+        for line in range.clone() {
+            starts.entry(new[line]).or_default().push(line);
+        }
+    }
+    let range_end = |ranges: &[Range<usize>], line: usize| ranges.iter().find(|range| range.contains(&line)).map_or(line, |range| range.end);
+
+    let mut claimed = vec![false; new.len()];
+    let mut moves = Vec::new();
+    for range in deleted {
+        let mut at = range.start;
+        while at < range.end {
+            let longest = starts.get(old[at]).into_iter().flatten()
+                .filter(|start| !claimed[**start])
+                .map(|&start| {
+                    let end = range_end(inserted, start);
+                    let len = (0..)
+                        .take_while(|offset| at + offset < range.end && start + offset < end && !claimed[start + offset] && old[at + offset] == new[start + offset])
+                        .count();
+                    (start, len)
+                })
+                .max_by_key(|(start, len)| (*len, std::cmp::Reverse(*start)));
+            match longest {
+                Some((start, len)) if len >= MIN_MOVED_LINES && old[at..at + len].iter().any(|line| !line.trim().is_empty()) => {
+                    claimed[start..start + len].iter_mut().for_each(|claimed| *claimed = true);
+                    moves.push(Move { from: at..at + len, to: start..start + len });
+                    at += len;
+                }
+                _ => at += 1,
+            }
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "use x;\nfn a() {\n    one();\n    uno();\n}\nfn b() {\n    two();\n    dos();\n    tres();\n}\nfn main() {}";
+    // `a` (lines 1-4) moved to the end
+    const MOVED: &str = "use x;\nfn b() {\n    two();\n    dos();\n    tres();\n}\nfn main() {}\nfn a() {\n    one();\n    uno();\n}";
+
+    #[test]
+    fn a_moved_function_is_one_moved_block() {
+        assert_eq!(classify(ORIGINAL, MOVED), Some(vec![Entry::Moved(Move { from: 1..5, to: 7..11 })]));
+    }
+
+    #[test]
+    fn lines_removed_and_added_around_a_move_are_deleted_and_inserted() {
+        let proposed = format!("{}\n// end", MOVED.strip_prefix("use x;\n").unwrap());
+
+        assert_eq!(classify(ORIGINAL, &proposed), Some(vec![
+            Entry::Deleted(0),
+            Entry::Moved(Move { from: 1..5, to: 6..10 }),
+            Entry::Inserted(10),
+        ]));
+    }
+
+    #[test]
+    fn an_edited_line_next_to_a_move_is_a_plain_change() {
+        let proposed = MOVED.replace("dos()", "DOS()");
+
+        assert_eq!(classify(ORIGINAL, &proposed), Some(vec![
+            Entry::Moved(Move { from: 1..5, to: 7..11 }),
+            Entry::Deleted(7),
+            Entry::Inserted(3),
+        ]));
+    }
+
+    #[test]
+    fn runs_shorter_than_three_lines_are_not_moves() {
+        assert_eq!(classify("a\nb\nc\nd", "c\nd\na\nb"), None);
+    }
+
+    #[test]
+    fn blank_runs_are_not_moves() {
+        assert_eq!(classify("x\n\n\n\ny", "y\n\n\n\nx"), None);
+    }
+
+    #[test]
+    fn an_edit_without_moves_is_left_to_the_usual_diff() {
+        assert_eq!(classify(ORIGINAL, &ORIGINAL.replace("one()", "uno()")), None);
+    }
+}
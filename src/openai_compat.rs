@@ -0,0 +1,162 @@
+use crate::error::CodersError;
+use crate::http;
+use crate::render::display_waiting_message;
+use anyhow::Result;
+use serde_json::json;
+use std::time::Instant;
+
+const EDIT_TOOL: &str = "apply_edit";
+// How much of a bad response body goes into the error.
+const MAX_BODY_EXCERPT: usize = 2000;
+
+/// Sends a request body in the OpenAI chat-completions shape to any provider that speaks it
+/// and returns `choices[0].message.content`.
+pub async fn send_chat_completion(http: &http::Transport, provider: &str, url: &str, api_key: &str, request_body: &serde_json::Value) -> Result<Option<String>> {
+    let response = post_timed(http, provider, url, &[("Authorization", format!("Bearer {}", api_key))], request_body).await?;
+    // `GenerationOptions::apply_to` adds the tool under --tools
+    let tools = request_body.get("tools").is_some();
+    chat_completion_content(provider, response, tools)
+}
+
+/// Posts the request behind a spinner and reports how long the provider took.
+pub async fn post_timed(http: &http::Transport, provider: &str, url: &str, headers: &[(&str, String)], request_body: &serde_json::Value) -> Result<http::HttpResponse> {
+    http.review(url, request_body)?;
+    let spinner = display_waiting_message("Sending request...");
+    let started = Instant::now();
+
+    let response = http.post_json(url, headers, request_body).await?;
+
+    spinner.finish_and_clear();
+    say!("Received response from {} in {} ms", provider, started.elapsed().as_millis());
+    Ok(response)
+}
+
+/// Extracts `choices[0].message.content` (or the `apply_edit` call with `tools`) from a
+/// chat-completions response, or classifies the provider's error.
+pub fn chat_completion_content(provider: &str, response: http::HttpResponse, tools: bool) -> Result<Option<String>> {
+    if response.status.is_success() {
+        let spinner = display_waiting_message("Processing response...");
+        let body = response.body;
+        say!("Response body: {}", body);
+        if body.is_empty() {
+            spinner.finish_and_clear();
+            return Ok(None);
+        }
+        let json_response = parse_body(provider, &body)?;
+        spinner.finish_and_clear();
+        message_content(&first_choice(provider, &json_response)?["message"], tools)
+    } else {
+        Err(CodersError::from_response(provider, response.status.as_u16(), &response.body).into())
+    }
+}
+
+/// Parses a successful response body. A body that isn't JSON, usually a truncated one, is a
+/// `MalformedResponse` carrying the start of the body, which `send_request` retries.
+pub fn parse_body(provider: &str, body: &str) -> Result<serde_json::Value, CodersError> {
+    serde_json::from_str(body).map_err(|e| CodersError::MalformedResponse {
+        provider: provider.to_string(),
+        error: e.to_string(),
+        body: excerpt(body),
+    })
+}
+
+/// `choices[0]` of a chat or text completion. Valid JSON without it is reported with the
+/// body, or as the provider's error when it sent one with a success status.
+pub fn first_choice<'a>(provider: &str, json_response: &'a serde_json::Value) -> Result<&'a serde_json::Value, CodersError> {
+    match json_response["choices"].get(0) {
+        Some(choice) => Ok(choice),
+        None if json_response.get("error").is_some() => Err(CodersError::from_response(provider, 200, &json_response.to_string())),
+        None => Err(CodersError::ResponseParse(format!(
+            "{} answered with valid JSON but no choices: {}", provider, excerpt(&json_response.to_string())
+        ))),
+    }
+}
+
+fn excerpt(body: &str) -> String {
+    match body.char_indices().nth(MAX_BODY_EXCERPT) {
+        Some((end, _)) => format!("{}... ({} more bytes)", &body[..end], body.len() - end),
+        None => body.to_string(),
+    }
+}
+
+/// `--tools`: declares an `apply_edit(new_content)` function and forces the model to call
+/// it, so the code arrives as a JSON argument instead of inside a fence.
+pub fn add_edit_tool(request_body: &mut serde_json::Value) {
+    request_body["tools"] = json!([{
+        "type": "function",
+        "function": {
+            "name": EDIT_TOOL,
+            "description": "Submit the edited code.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "new_content": {
+                        "type": "string",
+                        "description": "The code you would otherwise put in the fenced code block, without the fence.",
+                    },
+                },
+                "required": ["new_content"],
+            },
+        },
+    }]);
+    request_body["tool_choice"] = json!({"type": "function", "function": {"name": EDIT_TOOL}});
+}
+
+/// A chat message's text, or with `tools` the `new_content` argument of its `apply_edit`
+/// call. A missing call means the provider or model ignored the tool. Reasoning sent in a
+/// `reasoning_content` or `reasoning` field comes back in front of the text as the
+/// `<think>` block other reasoning models write inline, so both are handled alike.
+pub fn message_content(message: &serde_json::Value, tools: bool) -> Result<Option<String>> {
+    if !tools {
+        let content = message["content"].as_str();
+        let reasoning = message["reasoning_content"].as_str()
+            .or(message["reasoning"].as_str())
+            .filter(|reasoning| !reasoning.trim().is_empty());
+        return Ok(match reasoning {
+            Some(reasoning) => Some(format!("<think>{}</think>\n{}", reasoning, content.unwrap_or_default())),
+            None => content.map(String::from),
+        });
+    }
+    let call = message["tool_calls"].as_array()
+        .and_then(|calls| calls.iter().find(|call| call["function"]["name"] == EDIT_TOOL))
+        .ok_or_else(|| CodersError::ResponseParse(format!("the model did not call {}; it may not support tools, try again without --tools", EDIT_TOOL)))?;
+    // The arguments arrive as a JSON-encoded string
+    let arguments: serde_json::Value = serde_json::from_str(call["function"]["arguments"].as_str().unwrap_or_default())
+        .map_err(|e| CodersError::ResponseParse(format!("invalid {} arguments: {}", EDIT_TOOL, e)))?;
+    Ok(arguments["new_content"].as_str().map(String::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reasoning_is_wrapped_in_a_think_block_before_the_content() {
+        let message = json!({"role": "assistant", "reasoning_content": "Use +.", "content": "fn add() {}"});
+
+        assert_eq!(message_content(&message, false).unwrap().as_deref(), Some("<think>Use +.</think>\nfn add() {}"));
+    }
+
+    #[test]
+    fn openrouter_reasoning_is_wrapped_too() {
+        let message = json!({"role": "assistant", "reasoning": "Use +.", "content": "fn add() {}"});
+
+        assert_eq!(message_content(&message, false).unwrap().as_deref(), Some("<think>Use +.</think>\nfn add() {}"));
+    }
+
+    #[test]
+    fn blank_reasoning_is_no_think_block() {
+        let message = json!({"role": "assistant", "reasoning_content": " ", "content": "fn add() {}"});
+
+        assert_eq!(message_content(&message, false).unwrap().as_deref(), Some("fn add() {}"));
+    }
+
+    #[test]
+    fn a_tool_call_gives_its_new_content() {
+        let arguments = json!({"new_content": "fn add() {}"}).to_string();
+        let message = json!({"role": "assistant", "content": null, "tool_calls": [{"type": "function", "function": {"name": EDIT_TOOL, "arguments": arguments}}]});
+
+        assert_eq!(message_content(&message, true).unwrap().as_deref(), Some("fn add() {}"));
+        assert!(message_content(&json!({"role": "assistant", "content": "fn add() {}"}), true).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// One `@@` block of a unified diff.
+struct Hunk {
+    // 1-based, as in the header; only a hint for where to look
+    old_start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+/// Applies a unified diff to `original`. Each hunk is looked for by its context and removed
+/// lines, first at the line its header gives and then further and further away, so a diff
+/// with miscounted line numbers (as models write them) still applies. Fails when the diff
+/// has no hunks or one of them can't be found.
+pub fn apply(original: &str, diff: &str) -> Result<String, String> {
+    let hunks = parse(diff);
+    if hunks.is_empty() {
+        return Err("it contains no @@ hunks".to_string());
+    }
+
+    let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+    // Hunks are in file order, so each is searched for after the previous one
+    let mut floor = 0;
+    let mut shift: isize = 0;
+    for (i, hunk) in hunks.iter().enumerate() {
+        let hint = (hunk.old_start.saturating_sub(1) as isize + shift).max(0) as usize;
+        let at = find(&lines, &hunk.old, hint, floor)
+            .ok_or_else(|| format!("hunk {} doesn't match the file", i + 1))?;
+        lines.splice(at..at + hunk.old.len(), hunk.new.iter().cloned());
+        floor = at + hunk.new.len();
+        shift += hunk.new.len() as isize - hunk.old.len() as isize;
+    }
+
+    let mut patched = lines.join("\n");
+    if original.ends_with('\n') && !patched.is_empty() {
+        patched.push('\n');
+    }
+    Ok(patched)
+}
+
+fn parse(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for line in diff.lines() {
+        if let Some(header) = line.strip_prefix("@@") {
+            // `@@ -12,5 +12,6 @@`; a missing count means one line
+            let old_start = header.split_whitespace()
+                .find_map(|range| range.strip_prefix('-'))
+                .and_then(|range| range.split(',').next()?.parse().ok())
+                .unwrap_or(1);
+            hunks.push(Hunk { old_start, old: Vec::new(), new: Vec::new() });
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        if line.starts_with("---") || line.starts_with("+++") {
+            continue;
+        }
+        match line.chars().next() {
+            Some('-') => hunk.old.push(line[1..].to_string()),
+            Some('+') => hunk.new.push(line[1..].to_string()),
+            Some('\\') => {}
+            // Context, including blank context lines whose leading space was trimmed away
+            _ => {
+                let text = line.strip_prefix(' ').unwrap_or(line);
+                hunk.old.push(text.to_string());
+                hunk.new.push(text.to_string());
+            }
+        }
+    }
+    // A trailing blank line after the last hunk is the end of the answer, not context
+    for hunk in &mut hunks {
+        while hunk.old.last().is_some_and(String::is_empty) && hunk.new.last().is_some_and(String::is_empty) {
+            hunk.old.pop();
+            hunk.new.pop();
+        }
+    }
+    hunks
+}
+
+// The start of the closest run of `lines` equal to `old` at or after `floor`, preferring
+// `hint`. Trailing whitespace is ignored, since it rarely survives a model's answer.
+fn find(lines: &[String], old: &[String], hint: usize, floor: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    let matches = |at: usize| lines[at..at + old.len()].iter().zip(old).all(|(line, old)| line.trim_end() == old.trim_end());
+    let hint = hint.clamp(floor, last.max(floor));
+    (0..=last.max(hint))
+        .flat_map(|distance| [hint.checked_add(distance), hint.checked_sub(distance).filter(|_| distance > 0)])
+        .flatten()
+        .filter(|at| (floor..=last).contains(at))
+        .find(|at| matches(*at))
+}
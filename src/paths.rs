@@ -0,0 +1,150 @@
+use crate::error::CodersError;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Set from --config-dir before anything reads a path.
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_config_dir_override(dir: PathBuf) {
+    let _ = CONFIG_DIR_OVERRIDE.set(dir);
+}
+
+// --config-dir, then CODERS_CONFIG_DIR. When either is set, state and cache live inside
+// it too, so a portable install or a test never touches the user's real directories.
+fn override_dir() -> Option<PathBuf> {
+    first_override(CONFIG_DIR_OVERRIDE.get().cloned(), std::env::var_os("CODERS_CONFIG_DIR"))
+}
+
+// An empty CODERS_CONFIG_DIR counts as unset.
+fn first_override(flag: Option<PathBuf>, env: Option<OsString>) -> Option<PathBuf> {
+    flag.or_else(|| env.filter(|dir| !dir.is_empty()).map(PathBuf::from))
+}
+
+/// Config file and API keys: `~/.config/coders` on Linux.
+pub fn config_dir() -> Result<PathBuf, CodersError> {
+    if let Some(dir) = override_dir() {
+        return Ok(dir);
+    }
+    dirs::config_dir()
+        .map(|dir| dir.join("coders"))
+        .ok_or_else(|| CodersError::Config("Failed to get config directory".to_string()))
+}
+
+pub fn config_file() -> Result<PathBuf, CodersError> {
+    Ok(config_dir()?.join("config.toml"))
+}
+
+/// History and other state worth keeping but not worth backing up: `$XDG_STATE_HOME/coders`,
+/// or the local data directory on platforms without one.
+pub fn state_dir() -> Result<PathBuf, CodersError> {
+    if let Some(dir) = override_dir() {
+        return Ok(dir.join("state"));
+    }
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("coders"))
+        .ok_or_else(|| CodersError::Config("Failed to get state directory".to_string()))
+}
+
+/// Data that can be rebuilt at any time: `$XDG_CACHE_HOME/coders`.
+pub fn cache_dir() -> Result<PathBuf, CodersError> {
+    if let Some(dir) = override_dir() {
+        return Ok(dir.join("cache"));
+    }
+    dirs::cache_dir()
+        .map(|dir| dir.join("coders"))
+        .ok_or_else(|| CodersError::Config("Failed to get cache directory".to_string()))
+}
+
+/// Where the saved key for `provider` lives. Keys used to sit directly in the config
+/// directory (e.g. `~/.config/hyperbolic_api_key.txt`); such a file is moved into place the
+/// first time it is looked up.
+pub fn api_key_file(provider: &str) -> Result<PathBuf, CodersError> {
+    let name = format!("{}_api_key.txt", provider.to_lowercase());
+    let path = config_dir()?.join(&name);
+    match dirs::config_dir().filter(|_| override_dir().is_none()) {
+        Some(legacy_dir) => Ok(migrate_legacy_key(&legacy_dir.join(&name), path)),
+        None => Ok(path),
+    }
+}
+
+// Where the key is read from: `path`, after moving a key saved at `legacy` there if `path`
+// has none yet.
+fn migrate_legacy_key(legacy: &Path, path: PathBuf) -> PathBuf {
+    if path.exists() || !legacy.is_file() {
+        return path;
+    }
+    let moved = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| {
+        fs::rename(legacy, &path).or_else(|_| {
+            fs::copy(legacy, &path)?;
+            fs::remove_file(legacy)
+        })
+    });
+    // On a read-only filesystem the key is still read from where it is
+    if let Err(e) = moved {
+        eprintln!("Warning: could not move {} to {}: {}", legacy.display(), path.display(), e);
+        return legacy.to_path_buf();
+    }
+    say!("Moved {} to {}", legacy.display(), path.display());
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_flag_wins_over_the_variable() {
+        let chosen = first_override(Some(PathBuf::from("/flag")), Some(OsString::from("/env")));
+
+        assert_eq!(chosen, Some(PathBuf::from("/flag")));
+    }
+
+    #[test]
+    fn the_variable_is_used_without_the_flag() {
+        assert_eq!(first_override(None, Some(OsString::from("/env"))), Some(PathBuf::from("/env")));
+    }
+
+    #[test]
+    fn an_empty_variable_is_no_override() {
+        assert_eq!(first_override(None, Some(OsString::new())), None);
+        assert_eq!(first_override(None, None), None);
+    }
+
+    #[test]
+    fn a_legacy_key_is_moved_into_the_config_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("groq_api_key.txt");
+        let path = dir.path().join("coders").join("groq_api_key.txt");
+        fs::write(&legacy, "gsk-legacy").unwrap();
+
+        assert_eq!(migrate_legacy_key(&legacy, path.clone()), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "gsk-legacy");
+        assert!(!legacy.exists());
+    }
+
+    #[test]
+    fn a_key_already_in_place_is_kept_over_a_legacy_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("groq_api_key.txt");
+        let path = dir.path().join("coders").join("groq_api_key.txt");
+        fs::write(&legacy, "gsk-legacy").unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "gsk-current").unwrap();
+
+        assert_eq!(migrate_legacy_key(&legacy, path.clone()), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "gsk-current");
+        assert_eq!(fs::read_to_string(&legacy).unwrap(), "gsk-legacy");
+    }
+
+    #[test]
+    fn without_a_legacy_key_nothing_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("coders").join("groq_api_key.txt");
+
+        assert_eq!(migrate_legacy_key(&dir.path().join("groq_api_key.txt"), path.clone()), path);
+        assert!(!dir.path().join("coders").exists());
+    }
+}
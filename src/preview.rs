@@ -0,0 +1,83 @@
+use crate::error::CodersError;
+use crate::prompt;
+use crate::slim;
+use anyhow::Result;
+use serde_json::{json, Value};
+
+/// `--show-context`: prints the messages of a request body as they are about to be sent,
+/// each with its estimated token count, and asks before the request goes out. With `json`
+/// only the messages array is printed. Declining stops the run with `CodersError::UserAbort`.
+pub fn confirm(url: &str, body: &Value, json: bool) -> Result<()> {
+    let messages = messages(body);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&messages)?);
+    } else {
+        println!("\nAbout to send {} message(s) to {}:", messages.len(), url);
+        let mut total = 0;
+        for message in &messages {
+            let content = message["content"].as_str().unwrap_or_default();
+            let tokens = slim::estimate_tokens(content);
+            total += tokens;
+            println!("\n--- {} (~{} tokens) ---\n{}", message["role"].as_str().unwrap_or_default(), tokens, content);
+        }
+        println!("\n~{} tokens in total", total);
+    }
+    if prompt::confirm("send? (y/n) ")? {
+        Ok(())
+    } else {
+        Err(CodersError::UserAbort.into())
+    }
+}
+
+// Every provider's body as `{role, content}` pairs: the OpenAI-style `messages` (or a base
+// model's single `prompt`), Gemini's `systemInstruction` and `contents`, and Cohere's
+// `preamble`, `chat_history` and `message`.
+fn messages(body: &Value) -> Vec<Value> {
+    if let Some(messages) = body["messages"].as_array() {
+        return messages.iter()
+            .map(|message| {
+                let content = match &message["content"] {
+                    Value::String(content) => content.clone(),
+                    // An assistant turn that answered with a tool call
+                    Value::Null if message.get("tool_calls").is_some() => message["tool_calls"].to_string(),
+                    // Text and image parts; an image shows as its size rather than its data
+                    Value::Array(parts) => parts.iter()
+                        .filter_map(|part| match part["type"].as_str()? {
+                            "image_url" => Some(format!("[image, {} KB]", part["image_url"]["url"].as_str()?.len() / 1024)),
+                            _ => part["text"].as_str().map(str::to_string),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    content => content.to_string(),
+                };
+                json!({"role": message["role"], "content": content})
+            })
+            .collect();
+    }
+    if let Some(prompt) = body["prompt"].as_str() {
+        return vec![json!({"role": "prompt", "content": prompt})];
+    }
+    if let Some(contents) = body["contents"].as_array() {
+        let system = parts_text(&body["systemInstruction"]);
+        return system.into_iter().map(|system| json!({"role": "system", "content": system}))
+            .chain(contents.iter().map(|content| json!({"role": content["role"], "content": parts_text(content).unwrap_or_default()})))
+            .collect();
+    }
+    let mut messages = Vec::new();
+    if let Some(preamble) = body["preamble"].as_str() {
+        messages.push(json!({"role": "system", "content": preamble}));
+    }
+    for turn in body["chat_history"].as_array().into_iter().flatten() {
+        let role = turn["role"].as_str().unwrap_or_default().to_lowercase();
+        messages.push(json!({"role": role, "content": turn["message"]}));
+    }
+    if let Some(message) = body["message"].as_str() {
+        messages.push(json!({"role": "user", "content": message}));
+    }
+    messages
+}
+
+fn parts_text(content: &Value) -> Option<String> {
+    let parts = content["parts"].as_array()?;
+    Some(parts.iter().filter_map(|part| part["text"].as_str()).collect::<Vec<_>>().join("\n"))
+}
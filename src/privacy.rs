@@ -0,0 +1,155 @@
+use crate::config;
+use crate::error::CodersError;
+use crate::history;
+use crate::paths;
+use crate::prompt;
+use crate::Provider;
+use anyhow::Result;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// When each provider's privacy notice was acknowledged, in `privacy.json` in the state
+/// directory, keyed by provider id.
+#[derive(Default, Serialize, Deserialize)]
+struct Acknowledged(BTreeMap<String, Acknowledgment>);
+
+#[derive(Serialize, Deserialize)]
+struct Acknowledgment {
+    /// Where requests were going when the notice was shown.
+    host: String,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+}
+
+/// Before the first request to `provider`, says where code and prompts are about to go and
+/// what the config file notes about the provider's data retention, and asks to go on. An
+/// acknowledgment is remembered, so later runs say nothing. `accept` (`--accept-terms`)
+/// acknowledges without asking, and `terms_accepted` in `[providers.<id>]` skips the notice
+/// altogether, for machines set up in advance.
+pub fn acknowledge(config: &config::Config, provider: Provider, url: &str, accept: bool) -> Result<()> {
+    acknowledge_in(&state_file()?, config, provider, url, accept)?;
+    Ok(())
+}
+
+// `acknowledge` with the acknowledgments kept in `path`; whether the notice was shown.
+fn acknowledge_in(path: &Path, config: &config::Config, provider: Provider, url: &str, accept: bool) -> Result<bool> {
+    let provider_config = config.provider(provider.id());
+    if provider_config.is_some_and(|provider_config| provider_config.terms_accepted) {
+        return Ok(false);
+    }
+    let mut acknowledged = read(path);
+    if acknowledged.0.contains_key(provider.id()) {
+        return Ok(false);
+    }
+
+    let host = Url::parse(url).ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string());
+    say!("First request to {}: the files you edit, your prompts and any extra context (git changes, repo map, examples) are sent to {}.", provider.name(), host);
+    match provider_config.and_then(|provider_config| provider_config.retention_note.as_deref()) {
+        Some(note) => say!("Data retention: {}", note.trim()),
+        None => say!(
+            "No note on how long {} keeps that data is configured; check its data policy before sending proprietary code (retention_note under [providers.{}] records one).",
+            provider.name(), provider.id()
+        ),
+    }
+    if let Some(notice) = config.privacy_notice.as_deref().filter(|notice| !notice.trim().is_empty()) {
+        say!("{}", notice.trim());
+    }
+
+    if accept {
+        say!("Acknowledged with --accept-terms.");
+    } else {
+        let Some(answer) = prompt::answer("Continue? [y/N] ")? else {
+            return Err(CodersError::NoTerminal(format!(
+                "the {} privacy notice needs a one-time acknowledgment; pass --accept-terms, or set terms_accepted = true under [providers.{}]",
+                provider.name(), provider.id()
+            )).into());
+        };
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            say!("Nothing was sent.");
+            return Err(CodersError::UserAbort.into());
+        }
+    }
+
+    acknowledged.0.insert(provider.id().to_string(), Acknowledgment { host, timestamp: history::now() });
+    // Asking again next time is the worst that can happen
+    let saved = path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(path, serde_json::to_string_pretty(&acknowledged)?));
+    if let Err(e) = saved {
+        eprintln!("Warning: could not record the acknowledgment in {}: {}", path.display(), e);
+    }
+    Ok(true)
+}
+
+fn state_file() -> Result<PathBuf, CodersError> {
+    Ok(paths::state_dir()?.join("privacy.json"))
+}
+
+// A missing or unreadable file means nothing was acknowledged yet.
+fn read(path: &Path) -> Acknowledged {
+    fs::read_to_string(path).ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://api.groq.com/openai/v1/chat/completions";
+
+    // A fresh state directory and where the acknowledgments go in it.
+    fn state() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("privacy.json");
+        (dir, path)
+    }
+
+    #[test]
+    fn the_notice_shows_on_the_first_run_only() {
+        let (_dir, path) = state();
+        let config = config::Config::default();
+
+        assert!(acknowledge_in(&path, &config, Provider::Groq, URL, true).unwrap());
+        let recorded: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(recorded["groq"]["host"], "api.groq.com");
+        // Without --accept-terms or a terminal, a notice would be refused; it's skipped instead
+        assert!(!acknowledge_in(&path, &config, Provider::Groq, URL, false).unwrap());
+        assert!(!acknowledge_in(&path, &config, Provider::Groq, URL, true).unwrap());
+    }
+
+    #[test]
+    fn an_acknowledgment_covers_only_its_own_provider() {
+        let (_dir, path) = state();
+        let config = config::Config::default();
+
+        assert!(acknowledge_in(&path, &config, Provider::Groq, URL, true).unwrap());
+        assert!(acknowledge_in(&path, &config, Provider::Cohere, "https://api.cohere.ai/v1/chat", true).unwrap());
+        let recorded: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(recorded.as_object().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn terms_accepted_skips_the_notice_without_recording_anything() {
+        let (_dir, path) = state();
+        let config: config::Config = toml::from_str("[providers.groq]\nterms_accepted = true").unwrap();
+
+        assert!(!acknowledge_in(&path, &config, Provider::Groq, URL, false).unwrap());
+        assert!(!path.exists());
+        assert!(acknowledge_in(&path, &config, Provider::Cohere, "https://api.cohere.ai/v1/chat", true).unwrap());
+    }
+
+    #[test]
+    fn an_unreadable_state_file_shows_the_notice_again() {
+        let (_dir, path) = state();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not json").unwrap();
+
+        assert!(acknowledge_in(&path, &config::Config::default(), Provider::Groq, URL, true).unwrap());
+        assert!(!acknowledge_in(&path, &config::Config::default(), Provider::Groq, URL, false).unwrap());
+    }
+}
@@ -0,0 +1,53 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// `--profile`: wall time per phase of the run, summed over every file and request and
+/// printed when the run ends. Disabled profiles still measure but print nothing.
+pub struct Profile {
+    enabled: bool,
+    started: Instant,
+    // Phases in the order they were first seen
+    phases: RefCell<Vec<(&'static str, Duration)>>,
+}
+
+impl Profile {
+    pub fn new(enabled: bool) -> Profile {
+        Profile { enabled, started: Instant::now(), phases: RefCell::new(Vec::new()) }
+    }
+
+    pub fn add(&self, phase: &'static str, elapsed: Duration) {
+        let mut phases = self.phases.borrow_mut();
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+    }
+
+    pub fn time<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.add(phase, started.elapsed());
+        result
+    }
+
+    pub fn print(&self) {
+        if !self.enabled {
+            return;
+        }
+        let total = self.started.elapsed();
+        let phases = self.phases.borrow();
+        let measured: Duration = phases.iter().map(|(_, elapsed)| *elapsed).sum();
+
+        println!("\nProfile:");
+        for (phase, elapsed) in phases.iter() {
+            println!("  {:<16} {:>10.1} ms", phase, millis(*elapsed));
+        }
+        // Mostly time spent waiting at prompts and printing
+        println!("  {:<16} {:>10.1} ms", "other", millis(total.saturating_sub(measured)));
+        println!("  {:<16} {:>10.1} ms", "total", millis(total));
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
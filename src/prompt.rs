@@ -0,0 +1,164 @@
+use crate::console;
+use crate::error::CodersError;
+use anyhow::Result;
+use clap::ValueEnum;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::Path;
+
+// Unrecognized answers to a confirmation are asked again this many times in all.
+const MAX_ATTEMPTS: usize = 3;
+
+/// `--on-no-tty`: what a confirmation does when nobody can answer it, because input ended or
+/// there is no terminal to ask on.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum NoTty {
+    Apply,
+    Discard,
+    Error,
+}
+
+/// Prints `question` and returns the trimmed answer. "q", Esc and end of input cancel with
+/// `CodersError::UserAbort`, so every prompt can be left the same way and none can spin on
+/// a closed stdin.
+pub fn ask(question: &str) -> Result<String> {
+    if console::quiet() {
+        return Err(CodersError::NoTerminal(format!("--quiet never asks questions, and this one needs an answer: {}", question.trim())).into());
+    }
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        println!();
+        return Err(CodersError::UserAbort.into());
+    }
+    let answer = answer.trim();
+    // In line mode the terminal passes Esc through as a literal escape character
+    if answer.eq_ignore_ascii_case("q") || answer.starts_with('\x1b') {
+        return Err(CodersError::UserAbort.into());
+    }
+    Ok(answer.to_string())
+}
+
+/// A y/n question; any answer other than "y" is a no.
+pub fn confirm(question: &str) -> Result<bool> {
+    Ok(ask(question)?.eq_ignore_ascii_case("y"))
+}
+
+/// Like `ask`, with line editing and up-arrow recall of earlier answers saved in `history`.
+/// Ctrl-C cancels as well. Piped input goes through `ask`, so scripts see no difference.
+pub fn ask_with_history(question: &str, history: &Path) -> Result<String> {
+    if console::quiet() {
+        return piped_line();
+    }
+    if !io::stdin().is_terminal() {
+        return ask(question);
+    }
+    let mut editor = DefaultEditor::new()?;
+    // Missing until the first answer is saved
+    let _ = editor.load_history(history);
+    let answer = match editor.readline(question) {
+        Ok(line) => line.trim().to_string(),
+        Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Err(CodersError::UserAbort.into()),
+        Err(e) => return Err(e.into()),
+    };
+    if answer.eq_ignore_ascii_case("q") {
+        return Err(CodersError::UserAbort.into());
+    }
+    if !answer.is_empty() {
+        editor.add_history_entry(answer.as_str())?;
+        let saved = history.parent().map_or(Ok(()), fs::create_dir_all)
+            .map_err(ReadlineError::from)
+            .and_then(|()| editor.append_history(history));
+        if let Err(e) = saved {
+            eprintln!("Warning: could not save the prompt history to {}: {}", history.display(), e);
+        }
+    }
+    Ok(answer)
+}
+
+/// A y/n question about applying changes, where Enter means no. "yes"/"no" work too, other
+/// answers are asked again up to `MAX_ATTEMPTS` times and then count as no. When stdin is
+/// piped the answer is read from the controlling terminal; with no terminal, or at end of
+/// input, `on_no_tty` decides.
+pub fn confirm_change(question: &str, on_no_tty: NoTty) -> Result<bool> {
+    for _ in 0..MAX_ATTEMPTS {
+        let Some(answer) = answer(&format!("{} [y/N] ", question))? else {
+            return no_tty(on_no_tty);
+        };
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "" | "n" | "no" => return Ok(false),
+            "q" | "quit" => return Err(CodersError::UserAbort.into()),
+            _ => println!("Please answer y or n."),
+        }
+    }
+    println!("No valid answer after {} tries; treating it as no.", MAX_ATTEMPTS);
+    Ok(false)
+}
+
+// Under --quiet, the one answer a run may read is its prompt from a pipe, without asking.
+fn piped_line() -> Result<String> {
+    if io::stdin().is_terminal() {
+        return Err(CodersError::NoTerminal("--quiet never asks for the prompt; pipe it in or use --batch".to_string()).into());
+    }
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Err(CodersError::UserAbort.into());
+    }
+    Ok(answer.trim().to_string())
+}
+
+/// Like `confirm_change`, for questions with their own answers: the trimmed answer, or
+/// `None` when nobody can answer, which is always the case under `--quiet`.
+pub fn answer(question: &str) -> Result<Option<String>> {
+    if console::quiet() {
+        return Ok(None);
+    }
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    let read = if io::stdin().is_terminal() {
+        io::stdin().read_line(&mut answer)?
+    } else {
+        // The prompt itself may have come through the pipe, so ask the user's terminal
+        match controlling_terminal() {
+            Ok(terminal) => BufReader::new(terminal).read_line(&mut answer)?,
+            Err(_) => 0,
+        }
+    };
+    if read == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// What `on_no_tty` makes of an unanswerable confirmation.
+pub fn no_tty(on_no_tty: NoTty) -> Result<bool> {
+    match on_no_tty {
+        NoTty::Apply => {
+            say!("No terminal to confirm on; applying (--on-no-tty apply).");
+            Ok(true)
+        }
+        NoTty::Discard => {
+            say!("No terminal to confirm on; discarding (--on-no-tty discard).");
+            Ok(false)
+        }
+        NoTty::Error => Err(CodersError::NoTerminal(
+            "stdin is closed or not a terminal; pass --on-no-tty apply or --on-no-tty discard to decide without asking".to_string()
+        ).into()),
+    }
+}
+
+#[cfg(unix)]
+fn controlling_terminal() -> io::Result<fs::File> {
+    fs::File::open("/dev/tty")
+}
+
+#[cfg(windows)]
+fn controlling_terminal() -> io::Result<fs::File> {
+    fs::File::open("CONIN$")
+}
@@ -0,0 +1,213 @@
+use crate::edit::GenerationOptions;
+use crate::messages;
+use crate::slim;
+use crate::Capabilities;
+use serde_json::json;
+
+pub const RATIONALE_INSTRUCTION: &str = "Return the code in a single fenced code block, then after the closing fence give a short explanation of why you made the change.";
+
+pub const TOOLS_INSTRUCTION: &str = "Submit the code by calling the apply_edit function instead of writing a code block.";
+
+pub const CONFIDENCE_INSTRUCTION: &str = "After the code block, output a single line of JSON of the form {\"confidence\": <number between 0 and 1>} rating how confident you are that the change is correct.";
+
+// The base prompt plus any `[language_prompts]` entry from the config for the file's
+// language and the instructions required by enabled options.
+pub fn system_prompt(base: &str, language: &str, options: &GenerationOptions) -> String {
+    let mut prompt = if options.concise { CONCISE_SYSTEM_PROMPT } else { base }.to_string();
+    if let Some(addition) = options.language_prompts.get(language) {
+        append_sentence(&mut prompt, addition);
+    }
+    if options.request_rationale {
+        append_sentence(&mut prompt, RATIONALE_INSTRUCTION);
+    }
+    if options.request_confidence {
+        append_sentence(&mut prompt, CONFIDENCE_INSTRUCTION);
+    }
+    if options.tools {
+        append_sentence(&mut prompt, TOOLS_INSTRUCTION);
+    }
+    prompt
+}
+
+pub fn append_sentence(prompt: &mut String, sentence: &str) {
+    let sentence = sentence.trim();
+    if sentence.is_empty() {
+        return;
+    }
+    if !prompt.ends_with('.') {
+        prompt.push('.');
+    }
+    prompt.push(' ');
+    prompt.push_str(sentence);
+}
+
+pub const VERIFY_INSTRUCTION: &str = "Review the proposed change below, a unified diff against the original file. If it is correct and complete, answer with APPROVE and nothing else. Otherwise answer with a corrected unified diff against the original file in a ```diff block, keeping the context lines.";
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are an assistant helping a developer construct code. Follow instructions carefully and only output the code. Output only the changes, not the entire code";
+
+// Replaces the base prompt under --concise, where there are no examples to copy the
+// format from.
+pub const CONCISE_SYSTEM_PROMPT: &str = "Edit the code as instructed. Reply with only the changed code in one fenced code block";
+
+// Few-shot (user, assistant) turns shown before the real request.
+pub const DEFAULT_EXAMPLES: &[(&str, &str)] = &[
+    ("add a var sydney to this code | var yemen = yemen ", "```javascript\nvar yemen = yemen;\nvar sydney = sydney;```"),
+    ("Add a function to calculate factorial in Python | def square(n): return n * n", "```python\ndef square(n): return n * n\ndef factorial(n):\n    if n == 0 or n == 1:\n        return 1\n    else:\n        return n * factorial(n - 1)```"),
+    ("Fix the syntax error in this Rust code | fn main() { println(\"Hello, world!\"); }", "```rust\nfn main() {\n    println!(\"Hello, world!\");\n}```"),
+    ("Add error handling to this JavaScript function | function divide(a, b) { return a / b; }", "```javascript\nfunction divide(a, b) {\n    if (b === 0) {\n        throw new Error(\"Division by zero\");\n    }\n    return a / b;\n}```"),
+];
+
+// The chat messages of an edit request, the same for every provider that takes OpenAI-style
+// messages; only the model's capabilities change their layout. Providers with their own
+// message format start from `system_prompt`, `DEFAULT_EXAMPLES` and `user_message` instead.
+pub fn build_messages(language: &str, context: &str, options: &GenerationOptions, capabilities: Capabilities) -> Vec<serde_json::Value> {
+    let system = system_prompt(DEFAULT_SYSTEM_PROMPT, language, options);
+    chat_messages(&system, options.examples(DEFAULT_EXAMPLES), &user_message(language, context), options.image.as_deref(), capabilities, options.messages.as_deref())
+}
+
+pub fn user_message(language: &str, context: &str) -> String {
+    format!("The following code is in {}. {}", language, context)
+}
+
+// `preset` (--messages-file) replaces the system prompt and examples, whatever the model's
+// capabilities.
+pub fn chat_messages(system: &str, examples: &[(&str, &str)], user_message: &str, image: Option<&str>, capabilities: Capabilities, preset: Option<&[messages::Message]>) -> Vec<serde_json::Value> {
+    let mut messages = Vec::new();
+    if let Some(preset) = preset {
+        messages = messages::chat_messages(preset, user_message);
+    } else {
+        let examples = if capabilities.few_shot { examples } else { &[] };
+        if capabilities.system_role {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        for (user, assistant) in examples {
+            messages.push(json!({"role": "user", "content": user}));
+            messages.push(json!({"role": "assistant", "content": assistant}));
+        }
+        messages.push(json!({"role": "user", "content": user_message}));
+        if !capabilities.system_role {
+            let first = &mut messages[0];
+            first["content"] = json!(format!("{}\n\n{}", system, first["content"].as_str().unwrap_or_default()));
+        }
+    }
+    // The image goes with the request itself, after its text
+    if let Some(url) = image {
+        let last = messages.last_mut().expect("the user message was just added");
+        let text = last["content"].take();
+        last["content"] = json!([
+            {"type": "text", "text": text},
+            {"type": "image_url", "image_url": {"url": url}},
+        ]);
+    }
+    messages
+}
+
+// Base (non-chat) models just continue text, so the system prompt and examples are laid
+// out as an instruction document that ends where the model should start writing code.
+pub fn completion_prompt(system: &str, examples: &[(&str, &str)], user_message: &str) -> String {
+    let mut prompt = format!("### Instruction:\n{}\n\n", system);
+    for (user, assistant) in examples {
+        prompt.push_str(&format!("### Request:\n{}\n\n### Response:\n{}\n\n", user, assistant));
+    }
+    prompt.push_str(&format!("### Request:\n{}\n\n### Response:\n", user_message));
+    prompt
+}
+
+// Rough size of an answer that repeats the whole input, plus 20% for the edit itself.
+pub fn whole_file_tokens(context: &str) -> u32 {
+    (slim::estimate_tokens(context) * 6 / 5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{Message, Role};
+    use crate::HyperbolicModel;
+
+    const EXAMPLES: &[(&str, &str)] = &[("add b | a", "```\na\nb```")];
+
+    fn capabilities(system_role: bool, few_shot: bool) -> Capabilities {
+        Capabilities { system_role, few_shot, ..Capabilities::FULL }
+    }
+
+    fn built(capabilities: Capabilities) -> Vec<serde_json::Value> {
+        chat_messages("Only output code.", EXAMPLES, "Fix it", None, capabilities, None)
+    }
+
+    #[test]
+    fn a_full_model_gets_the_system_message_and_the_examples() {
+        assert_eq!(built(capabilities(true, true)), vec![
+            json!({"role": "system", "content": "Only output code."}),
+            json!({"role": "user", "content": "add b | a"}),
+            json!({"role": "assistant", "content": "```\na\nb```"}),
+            json!({"role": "user", "content": "Fix it"}),
+        ]);
+    }
+
+    #[test]
+    fn without_the_system_role_the_instructions_lead_the_first_example() {
+        assert_eq!(built(capabilities(false, true)), vec![
+            json!({"role": "user", "content": "Only output code.\n\nadd b | a"}),
+            json!({"role": "assistant", "content": "```\na\nb```"}),
+            json!({"role": "user", "content": "Fix it"}),
+        ]);
+    }
+
+    #[test]
+    fn without_few_shot_the_examples_are_dropped() {
+        assert_eq!(built(capabilities(true, false)), vec![
+            json!({"role": "system", "content": "Only output code."}),
+            json!({"role": "user", "content": "Fix it"}),
+        ]);
+    }
+
+    #[test]
+    fn the_degraded_layout_is_one_user_message() {
+        assert_eq!(built(Capabilities::FULL.degraded()), vec![
+            json!({"role": "user", "content": "Only output code.\n\nFix it"}),
+        ]);
+        assert_eq!(built(capabilities(false, false)), built(Capabilities::FULL.degraded()));
+    }
+
+    #[test]
+    fn an_image_goes_with_the_request_after_its_text() {
+        let messages = chat_messages("Only output code.", &[], "Fix it", Some("data:image/png;base64,AAAA"), Capabilities::FULL, None);
+
+        assert_eq!(messages[1], json!({"role": "user", "content": [
+            {"type": "text", "text": "Fix it"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}},
+        ]}));
+    }
+
+    #[test]
+    fn preset_messages_replace_the_system_prompt_and_examples_whatever_the_capabilities() {
+        let preset = [
+            Message { role: Role::System, content: "Be terse.".to_string() },
+            Message { role: Role::Assistant, content: "Ok.".to_string() },
+        ];
+        let expected = vec![
+            json!({"role": "system", "content": "Be terse."}),
+            json!({"role": "assistant", "content": "Ok."}),
+            json!({"role": "user", "content": "Fix it"}),
+        ];
+
+        for capabilities in [Capabilities::FULL, Capabilities::FULL.degraded()] {
+            assert_eq!(chat_messages("Only output code.", EXAMPLES, "Fix it", None, capabilities, Some(&preset)), expected);
+        }
+    }
+
+    #[test]
+    fn a_base_model_gets_an_instruction_document() {
+        assert_eq!(
+            completion_prompt("Only output code.", EXAMPLES, "Fix it"),
+            "### Instruction:\nOnly output code.\n\n### Request:\nadd b | a\n\n### Response:\n```\na\nb```\n\n### Request:\nFix it\n\n### Response:\n"
+        );
+    }
+
+    #[test]
+    fn the_base_model_is_the_one_without_chat() {
+        assert!(!Capabilities::of(crate::Provider::Hyperbolic, HyperbolicModel::MetaLlama31405B.as_str()).chat);
+        assert!(Capabilities::of(crate::Provider::Hyperbolic, HyperbolicModel::MetaLlama31405BInstruct.as_str()).chat);
+        assert!(Capabilities::of(crate::Provider::OpenRouter, "some/unknown-model").system_role);
+    }
+}
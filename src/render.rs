@@ -0,0 +1,225 @@
+use crate::console;
+use crate::merge::{Change, ChangeType};
+use crate::moves;
+use crate::scope;
+use crate::structured;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum DiffFormat {
+    /// ANSI-colored markers
+    Color,
+    /// `+`/`-`/`~` markers and `line:content` only, never any escape codes
+    Plain,
+    /// One JSON object per change, for scripts
+    Json,
+    /// `--ascii`: words instead of markers ("ADDED line 12: ..."), no colors, ASCII only
+    #[value(skip)]
+    Ascii,
+}
+
+// The changes --strict-scope left out, as the diff shows removed and added lines.
+pub fn render_hunks(hunks: &[scope::Hunk], format: DiffFormat, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for hunk in hunks {
+        let removed = hunk.removed.iter().zip(hunk.old.clone())
+            .map(|(content, line)| Change { change_type: ChangeType::Delete, line_number: line + 1, content: content.clone() });
+        let added = hunk.added.iter().zip(hunk.new.clone())
+            .map(|(content, line)| Change { change_type: ChangeType::Insert, line_number: line + 1, content: content.clone() });
+        lines.extend(removed.chain(added).map(|change| render_change(&change, format, width)));
+    }
+    lines
+}
+
+pub fn display_waiting_message(message: &str) -> ProgressBar {
+    if !console::enabled() {
+        return ProgressBar::hidden();
+    }
+    // A dumb terminal can't redraw a line, so it gets the message once instead
+    if console::ascii() && std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        say!("{}", message);
+        return ProgressBar::hidden();
+    }
+    // A request cancelled by --deadline drops its spinner without finishing it
+    let spinner = ProgressBar::new_spinner().with_finish(indicatif::ProgressFinish::AndClear);
+    let style = if console::ascii() {
+        ProgressStyle::default_spinner().tick_chars("-\\|/ ").template("{spinner} {msg}")
+    } else {
+        ProgressStyle::default_spinner().tick_chars("▰▱").template("{spinner:.blue} {msg}")
+    };
+    spinner.set_style(style.unwrap());
+
+    spinner.set_message(message.blue().to_string());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+
+    spinner
+}
+
+pub fn append_diff(path: &Path, file: &str, summary: &str, lines: &[String]) -> Result<()> {
+    let mut diff = format!("proposed: {} ({})\n", summary, file);
+    for line in lines {
+        diff.push_str(line);
+        diff.push('\n');
+    }
+    diff.push('\n');
+    fs::OpenOptions::new().append(true).create(true).open(path)
+        .and_then(|mut out| out.write_all(diff.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// One line for triage, e.g. "+12 -3 ~7 lines across 2 hunks". A hunk is a run of changes
+// on adjacent lines.
+pub fn change_summary(changes: &[Change]) -> String {
+    if changes.is_empty() {
+        return "no changes".to_string();
+    }
+    let (mut inserted, mut deleted, mut modified) = (0, 0, 0);
+    for change in changes {
+        match change.change_type {
+            ChangeType::Insert => inserted += 1,
+            ChangeType::Delete => deleted += 1,
+            ChangeType::Modify => modified += 1,
+        }
+    }
+    let mut lines: Vec<usize> = changes.iter().map(|change| change.line_number).collect();
+    lines.sort_unstable();
+    lines.dedup();
+    let hunks = 1 + lines.windows(2).filter(|pair| pair[1] > pair[0] + 1).count();
+    format!("+{} -{} ~{} lines across {} hunk{}", inserted, deleted, modified, hunks, if hunks == 1 { "" } else { "s" })
+}
+
+// Digits in the highest line number of any of the versions, so the diff's numbers line up.
+pub fn line_number_width(versions: &[&str]) -> usize {
+    let lines = versions.iter().map(|content| content.lines().count()).max().unwrap_or(0);
+    lines.max(1).to_string().len()
+}
+
+// Line numbers are right-aligned to `width` and dimmed in color output, apart from the
+// marker and content colors.
+pub fn render_change(change: &Change, format: DiffFormat, width: usize) -> String {
+    let (marker, color) = match change.change_type {
+        ChangeType::Insert => ('+', "32"),
+        ChangeType::Delete => ('-', "31"),
+        ChangeType::Modify => ('~', "33"),
+    };
+    match format {
+        DiffFormat::Color => format!(
+            "\x1b[{}m{}\x1b[0m \x1b[2m{:>width$}:\x1b[0m\x1b[{}m{}\x1b[0m",
+            color, marker, change.line_number, color, change.content, width = width
+        ),
+        DiffFormat::Plain => format!("{} {:>width$}:{}", marker, change.line_number, change.content, width = width),
+        DiffFormat::Ascii => {
+            let word = match change.change_type {
+                ChangeType::Insert => "ADDED",
+                ChangeType::Delete => "REMOVED",
+                ChangeType::Modify => "CHANGED",
+            };
+            format!("{} line {}: {}", word, change.line_number, change.content)
+        }
+        DiffFormat::Json => {
+            let change_type = match change.change_type {
+                ChangeType::Insert => "insert",
+                ChangeType::Delete => "delete",
+                ChangeType::Modify => "modify",
+            };
+            json!({"type": change_type, "line": change.line_number, "content": change.content}).to_string()
+        }
+    }
+}
+
+// The line diff with moved blocks picked out: deleted and inserted lines as in
+// `render_change`, and each moved block as one line in magenta, followed by its lines when
+// `expand` is set.
+pub fn render_moves(entries: &[moves::Entry], original: &str, merged: &str, format: DiffFormat, width: usize, expand: bool) -> Vec<String> {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = merged.lines().collect();
+    let mut lines = Vec::new();
+    for entry in entries {
+        let moved = match entry {
+            moves::Entry::Deleted(line) => {
+                let change = Change { change_type: ChangeType::Delete, line_number: line + 1, content: old[*line].to_string() };
+                lines.push(render_change(&change, format, width));
+                continue;
+            }
+            moves::Entry::Inserted(line) => {
+                let change = Change { change_type: ChangeType::Insert, line_number: line + 1, content: new[*line].to_string() };
+                lines.push(render_change(&change, format, width));
+                continue;
+            }
+            moves::Entry::Moved(moved) => moved,
+        };
+        let (from, to) = ((moved.from.start + 1, moved.from.end), (moved.to.start + 1, moved.to.end));
+        let header = format!("moved (lines {}\u{2013}{} \u{2192} {}\u{2013}{})", from.0, from.1, to.0, to.1);
+        let content = || new[moved.to.clone()].iter().zip(to.0..);
+        match format {
+            DiffFormat::Color => {
+                lines.push(format!("\x1b[35m> {}\x1b[0m", header));
+                if expand {
+                    lines.extend(content().map(|(line, number)| format!("\x1b[35m>\x1b[0m \x1b[2m{:>width$}:\x1b[0m\x1b[35m{}\x1b[0m", number, line, width = width)));
+                }
+            }
+            DiffFormat::Plain => {
+                lines.push(format!("> {}", header));
+                if expand {
+                    lines.extend(content().map(|(line, number)| format!("> {:>width$}:{}", number, line, width = width)));
+                }
+            }
+            DiffFormat::Ascii => {
+                lines.push(format!("MOVED lines {}-{} to lines {}-{}", from.0, from.1, to.0, to.1));
+                if expand {
+                    lines.extend(content().map(|(line, number)| format!("MOVED line {}: {}", number, line)));
+                }
+            }
+            DiffFormat::Json => {
+                let mut change = json!({"type": "moved", "from": [from.0, from.1], "to": [to.0, to.1]});
+                if expand {
+                    change["content"] = json!(content().map(|(line, _)| line).collect::<Vec<_>>());
+                }
+                lines.push(change.to_string());
+            }
+        }
+    }
+    lines
+}
+
+// e.g. "+3 -1 lines, 2 blocks moved", for a diff with moves in it.
+pub fn moves_summary(entries: &[moves::Entry]) -> String {
+    let count = |matches: fn(&moves::Entry) -> bool| entries.iter().filter(|entry| matches(entry)).count();
+    let inserted = count(|entry| matches!(entry, moves::Entry::Inserted(_)));
+    let deleted = count(|entry| matches!(entry, moves::Entry::Deleted(_)));
+    let moved = count(|entry| matches!(entry, moves::Entry::Moved(_)));
+    format!("+{} -{} lines, {} block{} moved", inserted, deleted, moved, if moved == 1 { "" } else { "s" })
+}
+
+pub fn render_structured_change(change: &structured::Change, format: DiffFormat) -> String {
+    let (marker, color) = match change {
+        structured::Change::Added { .. } => ('+', "32"),
+        structured::Change::Removed { .. } => ('-', "31"),
+        structured::Change::Changed { .. } => ('~', "33"),
+    };
+    match format {
+        DiffFormat::Color => format!("\x1b[{}m{} {}\x1b[0m", color, marker, structured::describe(change)),
+        DiffFormat::Plain => format!("{} {}", marker, structured::describe(change)),
+        DiffFormat::Ascii => {
+            let word = match change {
+                structured::Change::Added { .. } => "ADDED",
+                structured::Change::Removed { .. } => "REMOVED",
+                structured::Change::Changed { .. } => "CHANGED",
+            };
+            format!("{} {}", word, structured::describe(change))
+        }
+        DiffFormat::Json => match change {
+            structured::Change::Added { path, value } => json!({"type": "added", "path": path, "value": value}),
+            structured::Change::Removed { path, value } => json!({"type": "removed", "path": path, "value": value}),
+            structured::Change::Changed { path, old, new } => json!({"type": "changed", "path": path, "old": old, "new": new}),
+        }.to_string(),
+    }
+}
@@ -0,0 +1,240 @@
+use crate::edit::get_file_language;
+use crate::git;
+use crate::paths;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Rough budget for the whole listing, using the usual ~4 characters per token estimate.
+const REPO_MAP_TOKEN_BUDGET: usize = 1500;
+
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "vendor", "dist", "build", "__pycache__"];
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cache {
+    files: HashMap<String, CachedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    mtime: u64,
+    symbols: Vec<String>,
+}
+
+/// Builds a compact listing of top-level symbols in the project containing `target`,
+/// most relevant files (by overlap with the prompt's words) first, cut to the token budget.
+pub fn build_repo_map(target: &str, prompt: &str) -> Result<String> {
+    let target = fs::canonicalize(target)?;
+    let root = target.parent()
+        .and_then(|dir| git::repo_root(dir).ok())
+        .unwrap_or(std::env::current_dir()?);
+
+    let mut cache = load_cache();
+    let mut entries = Vec::new();
+    for path in project_files(&root)? {
+        if path == target || get_file_language(&path.to_string_lossy()) == "plaintext" {
+            continue;
+        }
+        let Ok(mtime) = fs::metadata(&path).and_then(|m| m.modified()) else {
+            continue;
+        };
+        let mtime = mtime.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let key = path.to_string_lossy().into_owned();
+
+        let symbols = match cache.files.get(&key) {
+            Some(cached) if cached.mtime == mtime => cached.symbols.clone(),
+            _ => {
+                let Ok(source) = fs::read_to_string(&path) else {
+                    continue;
+                };
+                let symbols = extract_symbols(get_file_language(&key), &source);
+                cache.files.insert(key.clone(), CachedFile { mtime, symbols: symbols.clone() });
+                symbols
+            }
+        };
+        if !symbols.is_empty() {
+            let display = path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().into_owned();
+            entries.push((display, symbols));
+        }
+    }
+    save_cache(&cache);
+
+    let keywords = keywords(prompt);
+    entries.sort_by_cached_key(|(path, symbols)| {
+        let score = relevance(&keywords, path, symbols);
+        (std::cmp::Reverse(score), path.clone())
+    });
+
+    let mut map = String::new();
+    let mut omitted = 0;
+    for (path, symbols) in &entries {
+        let mut section = String::new();
+        for symbol in symbols {
+            section.push_str(&format!("{}: {}\n", path, symbol));
+        }
+        if (map.len() + section.len()) / 4 > REPO_MAP_TOKEN_BUDGET {
+            omitted += 1;
+            continue;
+        }
+        map.push_str(&section);
+    }
+    if omitted > 0 {
+        map.push_str(&format!("({} less relevant files omitted)\n", omitted));
+    }
+    Ok(map)
+}
+
+fn project_files(root: &Path) -> Result<Vec<PathBuf>> {
+    // Inside a git repository the ignore rules come for free
+    if let Ok(files) = git::list_files(root) {
+        return Ok(files.iter().map(|file| git::worktree_path(root, file)).collect());
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_str()) {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn extract_symbols(language: &str, source: &str) -> Vec<String> {
+    definitions(language, source)
+        .map(|(_, line)| {
+            let end = line.find(['{', ';']).unwrap_or(line.len());
+            let signature = line[..end].trim_end();
+            signature.strip_suffix(':').unwrap_or(signature).to_string()
+        })
+        .filter(|signature| !signature.is_empty())
+        .collect()
+}
+
+// The 0-based index and trimmed text of each line that starts a symbol's definition.
+fn definitions<'a>(language: &'a str, source: &'a str) -> impl Iterator<Item = (usize, &'a str)> {
+    let prefixes: &[&str] = match language {
+        "rust" => &["pub fn ", "fn ", "pub async fn ", "async fn ", "pub struct ", "struct ", "pub enum ", "enum ",
+                    "pub trait ", "trait ", "pub type ", "pub const ", "pub mod ", "impl"],
+        "python" => &["def ", "async def ", "class "],
+        "javascript" | "typescript" => &["export ", "function ", "async function ", "class ", "interface ", "type "],
+        "go" => &["func ", "type "],
+        "java" => &["public ", "protected ", "class ", "interface ", "enum ", "abstract ", "final "],
+        _ => &[],
+    };
+    // Top-level items, plus methods one level into a type for languages that nest them
+    let nested = matches!(language, "rust" | "java");
+    source.lines()
+        .enumerate()
+        .filter(move |(_, line)| {
+            let indent = line.len() - line.trim_start().len();
+            indent == 0 || (indent <= 4 && nested && (language == "java" || line.trim_start().starts_with("pub ")))
+        })
+        .map(|(index, line)| (index, line.trim()))
+        .filter(move |(_, line)| prefixes.iter().any(|prefix| line.starts_with(prefix)))
+}
+
+// Words that come before a symbol's name in its definition.
+const DEFINITION_KEYWORDS: &[&str] = &["fn", "def", "class", "struct", "enum", "trait", "type", "mod", "function", "interface", "func", "const"];
+
+/// The named symbols of `source` (functions, types and the like, as the repo map lists them)
+/// with the 0-based lines each spans: from its definition to the line before the next one
+/// indented no deeper, keeping a closing bracket at the definition's own indentation.
+pub fn symbol_ranges(language: &str, source: &str) -> Vec<(String, std::ops::Range<usize>)> {
+    let lines: Vec<&str> = source.lines().collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    definitions(language, source)
+        .filter_map(|(start, line)| {
+            let name = symbol_name(line)?;
+            let own = indent(lines[start]);
+            let mut end = start + 1;
+            while end < lines.len() {
+                let line = lines[end];
+                if !line.trim().is_empty() && indent(line) <= own {
+                    if indent(line) == own && line.trim_start().starts_with(['}', ')', ']']) {
+                        end += 1;
+                    }
+                    break;
+                }
+                end += 1;
+            }
+            // Blank lines after the body belong to the gap, not the symbol
+            while end > start + 1 && lines[end - 1].trim().is_empty() {
+                end -= 1;
+            }
+            Some((name, start..end))
+        })
+        .collect()
+}
+
+// The name after the definition keyword (skipping a Go method's receiver), or else the
+// identifier before the first parenthesis, as in a Java method.
+fn symbol_name(line: &str) -> Option<String> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let first_ident = |text: &str| -> Option<String> {
+        let name: String = text.trim_start().chars().take_while(|c| is_ident(*c)).collect();
+        (!name.is_empty()).then_some(name)
+    };
+    let mut offset = 0;
+    for word in line.split(|c: char| !is_ident(c)) {
+        offset += word.len();
+        if DEFINITION_KEYWORDS.contains(&word) {
+            let after = line[offset..].trim_start();
+            return match after.strip_prefix('(') {
+                Some(receiver) => first_ident(&receiver[receiver.find(')')? + 1..]),
+                None => first_ident(after),
+            };
+        }
+        offset += 1;
+    }
+    let before = line[..line.find('(')?].trim_end();
+    first_ident(&before[before.rfind(|c: char| !is_ident(c)).map_or(0, |at| at + 1)..])
+}
+
+fn keywords(prompt: &str) -> Vec<String> {
+    prompt.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| word.len() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn relevance(keywords: &[String], path: &str, symbols: &[String]) -> usize {
+    let haystack = format!("{} {}", path, symbols.join(" ")).to_lowercase();
+    keywords.iter().filter(|keyword| haystack.contains(keyword.as_str())).count()
+}
+
+fn cache_file() -> Option<PathBuf> {
+    paths::cache_dir().ok().map(|dir| dir.join("repo_map.json"))
+}
+
+fn load_cache() -> Cache {
+    cache_file()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+// The cache is only an optimisation, so failing to write it is not worth reporting.
+fn save_cache(cache: &Cache) {
+    if let Some(path) = cache_file() {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(contents) = serde_json::to_string(cache) {
+            let _ = fs::write(path, contents);
+        }
+    }
+}
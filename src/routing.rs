@@ -0,0 +1,91 @@
+use crate::cli::{model_alias, Cli};
+use crate::config;
+use crate::error::CodersError;
+use crate::slim;
+use crate::Provider;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::fs;
+
+/// Checks that every `[[routing]]` rule names a provider, so a typo fails at startup rather
+/// than when a file of that size comes along.
+pub fn validate(config: &config::Config) -> Result<(), CodersError> {
+    for (i, rule) in config.routing.iter().enumerate() {
+        target(config, &rule.model)
+            .map_err(|e| CodersError::Config(format!("routing rule {}: {}", i + 1, e)))?;
+    }
+    Ok(())
+}
+
+/// With `provider = "auto"`, picks the provider and model of the first rule the largest of
+/// `files` fits under and prints the choice. `None` when routing is off or overridden on the
+/// command line. `explain` prints every rule and why the chosen one matched.
+pub fn route(cli: &Cli, config: &config::Config, files: &[String], explain: bool) -> Result<Option<(Provider, String)>> {
+    let auto = config.provider.as_deref().is_some_and(|provider| provider.eq_ignore_ascii_case("auto"));
+    let overridden = if cli.provider.is_some() || cli.openrouter {
+        Some("--provider was given")
+    } else if cli.model || cli.model_name.is_some() {
+        Some("a model was chosen with --model or --model-name")
+    } else {
+        None
+    };
+    match (auto, overridden) {
+        (false, _) => {
+            if explain {
+                say!("Routing is off: the config doesn't set provider = \"auto\".");
+            }
+            return Ok(None);
+        }
+        (true, Some(reason)) => {
+            if explain {
+                say!("Routing is skipped: {}.", reason);
+            }
+            return Ok(None);
+        }
+        (true, None) => {}
+    }
+
+    // In a batch every file goes to the same provider, so the largest one decides
+    let (file, tokens) = files.iter()
+        .map(|file| (file.as_str(), fs::read_to_string(file).map_or(0, |content| slim::estimate_tokens(&content))))
+        .max_by_key(|(_, tokens)| *tokens)
+        .unwrap_or_default();
+    let matched = config.routing.iter().position(|rule| rule.below_tokens.is_none_or(|below| tokens < below));
+
+    if explain {
+        say!("Routing on the largest file, {} (~{} tokens):", file, tokens);
+        for (i, rule) in config.routing.iter().enumerate() {
+            let condition = match rule.below_tokens {
+                Some(below) => format!("< {} tokens", below),
+                None => "anything else".to_string(),
+            };
+            let verdict = match matched {
+                Some(m) if m == i => "matched",
+                Some(m) if i < m => "too small",
+                _ => "not reached",
+            };
+            say!("  {}. {:<11} {:<18} {}", i + 1, verdict, condition, rule.model);
+        }
+    }
+
+    let Some(index) = matched else {
+        return Err(CodersError::Budget(format!(
+            "{} is ~{} tokens, larger than every [[routing]] rule allows; add a rule without below_tokens to catch it",
+            file, tokens
+        )).into());
+    };
+    let (provider, model) = target(config, &config.routing[index].model)?;
+    say!("auto-selected {} {} (rule {}, ~{} tokens)", provider.name(), model, index + 1, tokens);
+    Ok(Some((provider, model)))
+}
+
+/// `provider:model`, or an alias whose target carries the provider.
+pub fn target(config: &config::Config, name: &str) -> Result<(Provider, String), CodersError> {
+    if let Some((aliased, model)) = model_alias(config, name) {
+        return aliased.map(|provider| (provider, model.to_string()))
+            .ok_or_else(|| CodersError::Config(format!("alias '{}' has no provider prefix", name)));
+    }
+    name.split_once(':')
+        .and_then(|(prefix, model)| Provider::from_str(prefix, true).ok().map(|provider| (provider, model.to_string())))
+        .ok_or_else(|| CodersError::Config(format!("'{}' needs a provider prefix, e.g. groq:{}", name, name)))
+}
@@ -0,0 +1,229 @@
+use crate::repo_map;
+use similar::{DiffOp, TextDiff};
+use std::ops::Range;
+
+/// `--lines A-B`: 1-based and inclusive, as an editor shows them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "line {}", self.start)
+        } else {
+            write!(f, "lines {}-{}", self.start, self.end)
+        }
+    }
+}
+
+pub fn parse_lines(value: &str) -> Result<LineRange, String> {
+    let number = |text: &str| text.trim().parse::<usize>().ok().filter(|line| *line >= 1);
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (number(start), number(end)),
+        None => (number(value), number(value)),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Ok(LineRange { start, end }),
+        (Some(_), Some(_)) => Err(format!("'{}' ends before it starts", value)),
+        _ => Err(format!("'{}' is not a line range such as 100-140", value)),
+    }
+}
+
+/// The part of a file a `--strict-scope` edit may change, as 0-based ranges of its lines.
+pub struct Scope {
+    ranges: Vec<Range<usize>>,
+    /// How messages name it, e.g. "lines 100-140" or "parse_config".
+    pub label: String,
+}
+
+impl Scope {
+    /// `--lines` when given, otherwise the symbols of `content` the prompt names. `None` when
+    /// the prompt names none.
+    pub fn resolve(lines: Option<LineRange>, prompt: &str, content: &str, language: &str) -> Result<Option<Scope>, String> {
+        if let Some(lines) = lines {
+            let count = content.lines().count();
+            if lines.end > count {
+                return Err(format!("--lines {}-{} goes past the end of the file ({} lines)", lines.start, lines.end, count));
+            }
+            return Ok(Some(Scope { ranges: std::iter::once(lines.start - 1..lines.end).collect(), label: lines.to_string() }));
+        }
+        let words: Vec<&str> = prompt.split(|c: char| !c.is_alphanumeric() && c != '_').collect();
+        let named: Vec<(String, Range<usize>)> = repo_map::symbol_ranges(language, content).into_iter()
+            .filter(|(name, _)| words.contains(&name.as_str()))
+            .collect();
+        if named.is_empty() {
+            return Ok(None);
+        }
+        let mut names: Vec<&str> = named.iter().map(|(name, _)| name.as_str()).collect();
+        names.dedup();
+        let label = names.join(", ");
+        Ok(Some(Scope { ranges: named.into_iter().map(|(_, range)| range).collect(), label }))
+    }
+
+    /// Tells the model what it may change.
+    pub fn instruction(&self) -> String {
+        format!("Only change {} of the file; leave every other line exactly as it is.", self.label)
+    }
+
+    // An insertion is in scope anywhere from the first line of a range to just after its last.
+    fn contains(&self, old: &Range<usize>) -> bool {
+        self.ranges.iter().any(|range| range.start <= old.start && old.end <= range.end)
+    }
+}
+
+/// Lines the proposal changed outside the scope: the original's `removed` lines at `old`
+/// and the proposal's `added` lines at `new`, both 0-based.
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+/// Splits the changes from `original` to `proposed` by scope: returns the original with only
+/// the changes inside the scope made, and the changes left out. A change that straddles the
+/// scope's edge counts as outside. `None` when every change is inside.
+pub fn split(original: &str, proposed: &str, scope: &Scope) -> Option<(String, Vec<Hunk>)> {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = proposed.lines().collect();
+    let diff = TextDiff::from_slices(&old, &new);
+
+    let mut kept: Vec<&str> = Vec::new();
+    let mut outside = Vec::new();
+    for op in diff.ops() {
+        let (old_range, new_range) = match *op {
+            DiffOp::Equal { old_index, len, .. } => {
+                kept.extend(&old[old_index..old_index + len]);
+                continue;
+            }
+            DiffOp::Delete { old_index, old_len, new_index } => (old_index..old_index + old_len, new_index..new_index),
+            DiffOp::Insert { old_index, new_index, new_len } => (old_index..old_index, new_index..new_index + new_len),
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => (old_index..old_index + old_len, new_index..new_index + new_len),
+        };
+        if scope.contains(&old_range) {
+            kept.extend(&new[new_range]);
+        } else {
+            kept.extend(&old[old_range.clone()]);
+            outside.push(Hunk {
+                removed: old[old_range.clone()].iter().map(|line| line.to_string()).collect(),
+                added: new[new_range.clone()].iter().map(|line| line.to_string()).collect(),
+                old: old_range,
+                new: new_range,
+            });
+        }
+    }
+    if outside.is_empty() {
+        return None;
+    }
+    let mut content = kept.join("\n");
+    if proposed.ends_with('\n') && !content.is_empty() {
+        content.push('\n');
+    }
+    Some((content, outside))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ten numbered lines; the scope is lines 4-6.
+    const ORIGINAL: &str = "l1\nl2\nl3\nl4\nl5\nl6\nl7\nl8\nl9\nl10\n";
+
+    fn lines(start: usize, end: usize) -> Scope {
+        Scope::resolve(Some(LineRange { start, end }), "", ORIGINAL, "plaintext").unwrap().unwrap()
+    }
+
+    fn proposal(edit: impl Fn(&mut Vec<String>)) -> String {
+        let mut lines: Vec<String> = ORIGINAL.lines().map(String::from).collect();
+        edit(&mut lines);
+        lines.join("\n") + "\n"
+    }
+
+    #[test]
+    fn line_ranges_parse_as_an_editor_shows_them() {
+        assert_eq!(parse_lines("100-140"), Ok(LineRange { start: 100, end: 140 }));
+        assert_eq!(parse_lines(" 7 "), Ok(LineRange { start: 7, end: 7 }));
+        assert_eq!(parse_lines("9-3"), Err("'9-3' ends before it starts".to_string()));
+        assert!(parse_lines("0-3").is_err());
+        assert!(parse_lines("a-b").is_err());
+        assert_eq!(LineRange { start: 4, end: 6 }.to_string(), "lines 4-6");
+        assert_eq!(LineRange { start: 4, end: 4 }.to_string(), "line 4");
+    }
+
+    #[test]
+    fn a_range_past_the_end_of_the_file_is_refused() {
+        let error = Scope::resolve(Some(LineRange { start: 9, end: 11 }), "", ORIGINAL, "plaintext").err();
+
+        assert_eq!(error.as_deref(), Some("--lines 9-11 goes past the end of the file (10 lines)"));
+    }
+
+    #[test]
+    fn the_first_and_last_lines_of_the_scope_are_inside() {
+        let proposed = proposal(|lines| {
+            lines[3] = "L4".to_string();
+            lines[5] = "L6".to_string();
+        });
+
+        assert!(split(ORIGINAL, &proposed, &lines(4, 6)).is_none());
+        assert!(split(ORIGINAL, &proposed, &lines(5, 5)).is_some());
+    }
+
+    #[test]
+    fn a_change_just_past_the_scope_is_left_out() {
+        let proposed = proposal(|lines| {
+            lines[4] = "L5".to_string();
+            lines[6] = "L7".to_string();
+        });
+        let (content, outside) = split(ORIGINAL, &proposed, &lines(4, 6)).unwrap();
+
+        assert_eq!(content, ORIGINAL.replace("l5", "L5"));
+        assert_eq!(outside.len(), 1);
+        assert_eq!((outside[0].old.clone(), outside[0].new.clone()), (6..7, 6..7));
+        assert_eq!((outside[0].removed.clone(), outside[0].added.clone()), (vec!["l7".to_string()], vec!["L7".to_string()]));
+    }
+
+    #[test]
+    fn a_change_straddling_the_edge_is_left_out_whole() {
+        let proposed = proposal(|lines| {
+            lines[2] = "L3".to_string();
+            lines[3] = "L4".to_string();
+        });
+        let (content, outside) = split(ORIGINAL, &proposed, &lines(4, 6)).unwrap();
+
+        assert_eq!(content, ORIGINAL);
+        assert_eq!(outside[0].old, 2..4);
+        assert_eq!(outside[0].added, vec!["L3", "L4"]);
+    }
+
+    #[test]
+    fn lines_inserted_at_either_edge_are_inside() {
+        let before = proposal(|lines| lines.insert(3, "new".to_string()));
+        let after = proposal(|lines| lines.insert(6, "new".to_string()));
+
+        assert!(split(ORIGINAL, &before, &lines(4, 6)).is_none());
+        assert!(split(ORIGINAL, &after, &lines(4, 6)).is_none());
+    }
+
+    #[test]
+    fn lines_inserted_a_line_away_are_outside() {
+        let proposed = proposal(|lines| lines.insert(7, "new".to_string()));
+        let (content, outside) = split(ORIGINAL, &proposed, &lines(4, 6)).unwrap();
+
+        assert_eq!(content, ORIGINAL);
+        assert_eq!((outside[0].old.clone(), outside[0].added.clone()), (7..7, vec!["new".to_string()]));
+    }
+
+    #[test]
+    fn a_named_function_is_the_scope() {
+        let content = "fn parse_config() {\n    read();\n}\n\nfn main() {\n    parse_config();\n}\n";
+        let scope = Scope::resolve(None, "Make parse_config return a Result", content, "rust").unwrap().unwrap();
+
+        assert_eq!(scope.label, "parse_config");
+        assert!(split(content, &content.replace("read()", "read()?"), &scope).is_none());
+        assert!(split(content, &content.replace("    parse_config();", "    parse_config()?;"), &scope).is_some());
+        assert!(Scope::resolve(None, "Tidy up", content, "rust").unwrap().is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use similar::{DiffOp, TextDiff};
+
+// String literals longer than this are shortened in the slimmed copy.
+const MAX_STRING_LITERAL: usize = 80;
+
+struct CommentSyntax {
+    line: &'static [&'static str],
+    block: Option<(&'static str, &'static str)>,
+}
+
+fn comment_syntax(language: &str) -> Option<CommentSyntax> {
+    let syntax = match language {
+        "rust" | "javascript" | "typescript" | "go" | "java" | "c++" | "c" | "c#" | "swift" | "kotlin" | "scala" => CommentSyntax {
+            line: &["//"],
+            block: Some(("/*", "*/")),
+        },
+        "php" => CommentSyntax { line: &["//", "#"], block: Some(("/*", "*/")) },
+        "css" => CommentSyntax { line: &[], block: Some(("/*", "*/")) },
+        "python" | "ruby" | "perl" | "r" | "shell" | "yaml" => CommentSyntax { line: &["#"], block: None },
+        "lua" => CommentSyntax { line: &["--"], block: Some(("--[[", "]]")) },
+        "haskell" => CommentSyntax { line: &["--"], block: Some(("{-", "-}")) },
+        "sql" => CommentSyntax { line: &["--"], block: Some(("/*", "*/")) },
+        "html" | "xml" | "markdown" => CommentSyntax { line: &[], block: Some(("<!--", "-->")) },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Whether comments can be stripped for `language`; for anything else only blank-line runs
+/// and long string literals are shortened.
+pub fn supports(language: &str) -> bool {
+    comment_syntax(language).is_some()
+}
+
+/// A copy of a source file with comment-only lines, repeated blank lines and long string
+/// literals removed, plus the original line number of every line that was kept.
+pub struct SlimSource {
+    pub text: String,
+    line_map: Vec<usize>,
+}
+
+impl SlimSource {
+    pub fn new(source: &str, language: &str) -> SlimSource {
+        let syntax = comment_syntax(language);
+        let mut lines = Vec::new();
+        let mut line_map = Vec::new();
+        let mut in_block: Option<&str> = None;
+        let mut previous_blank = false;
+
+        for (i, line) in source.lines().enumerate() {
+            let trimmed = line.trim();
+
+            if let Some(end) = in_block {
+                if trimmed.contains(end) {
+                    in_block = None;
+                }
+                continue;
+            }
+            if let Some(syntax) = &syntax {
+                if let Some((start, end)) = syntax.block {
+                    // Only whole-line comments are dropped so the line mapping stays simple
+                    if let Some(rest) = trimmed.strip_prefix(start) {
+                        match rest.find(end) {
+                            Some(pos) if rest[pos + end.len()..].trim().is_empty() => continue,
+                            Some(_) => {}
+                            None => {
+                                in_block = Some(end);
+                                continue;
+                            }
+                        }
+                    }
+                }
+                if syntax.line.iter().any(|prefix| trimmed.starts_with(prefix)) && !trimmed.starts_with("#!") {
+                    continue;
+                }
+            }
+
+            let blank = trimmed.is_empty();
+            if blank && previous_blank {
+                continue;
+            }
+            previous_blank = blank;
+
+            lines.push(truncate_string_literals(line));
+            line_map.push(i);
+        }
+
+        SlimSource { text: lines.join("\n"), line_map }
+    }
+
+    /// Maps a full-file answer written against the slimmed copy back onto the original, so
+    /// stripped comments and literals survive. Answers that don't resemble the whole slimmed
+    /// file (e.g. a snippet) are returned unchanged.
+    pub fn restore(&self, original: &str, answer: &str) -> String {
+        let diff = TextDiff::from_lines(self.text.as_str(), answer);
+        if diff.ratio() < 0.5 {
+            return answer.to_string();
+        }
+
+        let original_lines: Vec<&str> = original.lines().collect();
+        let answer_lines: Vec<&str> = answer.lines().collect();
+        let mut restored: Vec<&str> = Vec::new();
+        let mut cursor = 0;
+
+        for op in diff.ops() {
+            match *op {
+                DiffOp::Equal { old_index, len, .. } => {
+                    for &line in &self.line_map[old_index..old_index + len] {
+                        restored.extend(&original_lines[cursor..=line]);
+                        cursor = line + 1;
+                    }
+                }
+                DiffOp::Delete { old_index, old_len, .. } => {
+                    for &line in &self.line_map[old_index..old_index + old_len] {
+                        restored.extend(&original_lines[cursor..line]);
+                        cursor = line + 1;
+                    }
+                }
+                DiffOp::Insert { new_index, new_len, .. } => {
+                    restored.extend(&answer_lines[new_index..new_index + new_len]);
+                }
+                DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                    for &line in &self.line_map[old_index..old_index + old_len] {
+                        restored.extend(&original_lines[cursor..line]);
+                        cursor = line + 1;
+                    }
+                    restored.extend(&answer_lines[new_index..new_index + new_len]);
+                }
+            }
+        }
+        restored.extend(&original_lines[cursor.min(original_lines.len())..]);
+        restored.join("\n")
+    }
+}
+
+fn truncate_string_literals(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '"' {
+            result.push(c);
+            continue;
+        }
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            if c == '\\' {
+                chars.next();
+            } else if c == '"' {
+                end = Some(i);
+                break;
+            }
+        }
+        match end {
+            Some(end) if end - start > MAX_STRING_LITERAL => {
+                let keep: String = line[start + 1..end].chars().take(MAX_STRING_LITERAL / 2).collect();
+                result.push_str(&format!("\"{}...\"", keep));
+            }
+            Some(end) => result.push_str(&line[start..=end]),
+            None => {
+                result.push_str(&line[start..]);
+                break;
+            }
+        }
+    }
+    result
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
@@ -0,0 +1,301 @@
+use crate::error::CodersError;
+use anyhow::Result;
+use crate::http::Transport;
+use serde_json::json;
+use std::io::{self, Write};
+
+// How many times a dropped stream is resumed before giving up.
+const MAX_RESUMES: usize = 3;
+
+/// Incremental parser for `text/event-stream` bodies.
+///
+/// Bytes are buffered until a blank line completes an event, so JSON payloads split across
+/// network chunks are reassembled before parsing. Comment lines (`: OPENROUTER PROCESSING`)
+/// and keep-alives are dropped.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    pending_data: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SseEvent {
+    Json(serde_json::Value),
+    Done,
+}
+
+impl SseParser {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let raw: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let raw = String::from_utf8_lossy(&raw);
+
+            let data: Vec<&str> = raw
+                .lines()
+                .filter(|line| !line.starts_with(':'))
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+            if data.is_empty() {
+                continue;
+            }
+
+            let data = data.join("\n");
+            if data.trim() == "[DONE]" {
+                events.push(SseEvent::Done);
+                continue;
+            }
+
+            // A payload that isn't valid JSON yet is held back and joined with the next one
+            self.pending_data.push_str(&data);
+            if let Ok(value) = serde_json::from_str(&self.pending_data) {
+                events.push(SseEvent::Json(value));
+                self.pending_data.clear();
+            }
+        }
+        events
+    }
+}
+
+enum StreamOutcome {
+    Complete,
+    Dropped(String),
+}
+
+/// Sends a chat completion request with `stream: true`, echoing tokens as they arrive.
+/// If the connection drops mid-stream, the request is re-sent with the text received so
+/// far as an assistant prefix and the continuation is appended.
+pub async fn stream_chat_completion(http: &Transport, url: &str, api_key: &str, request_body: &serde_json::Value) -> Result<Option<String>> {
+    let mut request_body = request_body.clone();
+    request_body["stream"] = json!(true);
+
+    let mut text = String::new();
+    let mut resumes = 0;
+    loop {
+        let body = if text.is_empty() {
+            request_body.clone()
+        } else {
+            with_assistant_prefix(&request_body, &text)
+        };
+
+        match stream_once(http, url, api_key, &body, &mut text).await? {
+            StreamOutcome::Complete => break,
+            StreamOutcome::Dropped(reason) if resumes < MAX_RESUMES => {
+                resumes += 1;
+                eprintln!("\nStream interrupted ({}); resuming ({}/{})...", reason, resumes, MAX_RESUMES);
+            }
+            StreamOutcome::Dropped(reason) => {
+                return Err(anyhow::anyhow!("Stream interrupted after {} resumes: {}", MAX_RESUMES, reason));
+            }
+        }
+    }
+    say!();
+
+    if text.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(text))
+    }
+}
+
+async fn stream_once(http: &Transport, url: &str, api_key: &str, request_body: &serde_json::Value, text: &mut String) -> Result<StreamOutcome> {
+    http.review(url, request_body)?;
+    let mut response = match http.post(url)?
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(request_body)
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => return Ok(StreamOutcome::Dropped(e.to_string())),
+    };
+
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        let body = response.text().await.unwrap_or_default();
+        return Err(CodersError::from_response("OpenRouter", status, &body).into());
+    }
+
+    let mut parser = SseParser::default();
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok(StreamOutcome::Dropped("connection closed before the end of the stream".to_string())),
+            Err(e) => return Ok(StreamOutcome::Dropped(e.to_string())),
+        };
+
+        for event in parser.push(&chunk) {
+            let value = match event {
+                SseEvent::Done => return Ok(StreamOutcome::Complete),
+                SseEvent::Json(value) => value,
+            };
+
+            if let Some(message) = value["error"]["message"].as_str() {
+                return Err(CodersError::Provider {
+                    status: value["error"]["code"].as_u64().unwrap_or(200) as u16,
+                    message: message.to_string(),
+                    provider: "OpenRouter".to_string(),
+                }.into());
+            }
+            if let Some(delta) = value["choices"][0]["delta"]["content"].as_str() {
+                http.mark_answering();
+                if crate::console::enabled() {
+                    print!("{}", delta);
+                    io::stdout().flush()?;
+                }
+                text.push_str(delta);
+            }
+            if !value["choices"][0]["finish_reason"].is_null() {
+                return Ok(StreamOutcome::Complete);
+            }
+        }
+    }
+}
+
+fn with_assistant_prefix(request_body: &serde_json::Value, prefix: &str) -> serde_json::Value {
+    let mut request_body = request_body.clone();
+    if let Some(messages) = request_body["messages"].as_array_mut() {
+        messages.push(json!({"role": "assistant", "content": prefix}));
+    }
+    request_body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // A captured OpenRouter stream: keep-alive comments between the events, then a final
+    // chunk with the finish reason and usage, then `[DONE]`.
+    const TRANSCRIPT: &str = include_str!("../tests/fixtures/sse/openrouter.sse");
+    const ANSWER: &str = "```rust\nfn main() {\n    println!(\"Hello, world!\");\n}\n```";
+
+    fn push_in_chunks(input: &str, size: usize) -> Vec<SseEvent> {
+        let mut parser = SseParser::default();
+        input.as_bytes().chunks(size).flat_map(|chunk| parser.push(chunk)).collect()
+    }
+
+    fn content(events: &[SseEvent]) -> String {
+        events.iter()
+            .filter_map(|event| match event {
+                SseEvent::Json(value) => value["choices"][0]["delta"]["content"].as_str(),
+                SseEvent::Done => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn comment_lines_are_dropped() {
+        let events = push_in_chunks(TRANSCRIPT, TRANSCRIPT.len());
+
+        assert_eq!(events.len(), 6);
+        assert_eq!(content(&events), ANSWER);
+        assert_eq!(events.last(), Some(&SseEvent::Done));
+    }
+
+    #[test]
+    fn a_comment_alone_is_no_event() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.push(b": OPENROUTER PROCESSING\n\n").is_empty());
+        assert!(parser.push(b":\n\n").is_empty());
+    }
+
+    #[test]
+    fn events_split_across_chunks_are_reassembled() {
+        for size in [1, 2, 3, 7, 64, 100] {
+            assert_eq!(push_in_chunks(TRANSCRIPT, size), push_in_chunks(TRANSCRIPT, TRANSCRIPT.len()), "chunks of {} bytes", size);
+        }
+    }
+
+    #[test]
+    fn crlf_line_endings_are_accepted() {
+        let events = push_in_chunks(&TRANSCRIPT.replace('\n', "\r\n"), 5);
+
+        assert_eq!(content(&events), ANSWER);
+        assert_eq!(events.last(), Some(&SseEvent::Done));
+    }
+
+    #[test]
+    fn a_json_payload_split_across_events_waits_for_the_rest() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.push(b"data: {\"choices\": [{\"delta\":\n\n").is_empty());
+        assert_eq!(
+            parser.push(b"data: {\"content\": \"fn\"}}]}\n\n"),
+            vec![SseEvent::Json(json!({"choices": [{"delta": {"content": "fn"}}]}))]
+        );
+    }
+
+    #[test]
+    fn done_is_recognized_with_or_without_the_space() {
+        assert_eq!(push_in_chunks("data: [DONE]\n\n", 4), vec![SseEvent::Done]);
+        assert_eq!(push_in_chunks("data:[DONE]\n\n", 4), vec![SseEvent::Done]);
+    }
+
+    #[test]
+    fn an_incomplete_event_is_held_back() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.push(b"data: {\"choices\": []}\n").is_empty());
+        assert_eq!(parser.push(b"\n"), vec![SseEvent::Json(json!({"choices": []}))]);
+    }
+
+    // Answers one request per response, each as a stream the server then closes, and
+    // returns the request bodies it got.
+    async fn serve(responses: Vec<String>) -> (String, tokio::task::JoinHandle<Vec<serde_json::Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/chat/completions", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                bodies.push(read_body(&mut socket).await);
+                let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+                socket.write_all(format!("{}{}", head, response).as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+        (url, server)
+    }
+
+    async fn read_body(socket: &mut TcpStream) -> serde_json::Value {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head.lines()
+                    .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return serde_json::from_str(body).unwrap();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_dropped_stream_resumes_with_the_text_so_far_as_an_assistant_prefix() {
+        // The connection closes after the first two tokens
+        let (sent, rest) = TRANSCRIPT.split_at(TRANSCRIPT.rfind(": OPENROUTER PROCESSING").unwrap());
+        let (url, server) = serve(vec![sent.to_string(), rest.to_string()]).await;
+        let http = Transport::new(None, None, Vec::new(), Vec::new());
+        let request = json!({"model": "nousresearch/hermes-3-llama-3.1-405b", "messages": [{"role": "user", "content": "Say hello"}]});
+
+        let text = crate::console::silent(stream_chat_completion(&http, &url, "sk-or-test", &request)).await.unwrap();
+
+        assert_eq!(text.as_deref(), Some(ANSWER));
+        let bodies = server.await.unwrap();
+        assert_eq!(bodies[0]["stream"], true);
+        assert_eq!(bodies[0]["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(bodies[1]["messages"][1], json!({"role": "assistant", "content": "```rust\nfn main() {\n"}));
+    }
+}
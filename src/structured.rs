@@ -0,0 +1,239 @@
+use serde_yaml::{Mapping, Value};
+
+/// One key-level difference, addressed by a dotted path such as `dependencies.serde` or
+/// `servers[0].port`.
+pub enum Change {
+    Added { path: String, value: Value },
+    Removed { path: String, value: Value },
+    Changed { path: String, old: Value, new: Value },
+}
+
+pub fn supports(language: &str) -> bool {
+    matches!(language, "json" | "yaml" | "toml")
+}
+
+/// `--structured-merge`: parses both versions, lists the changed keys and rebuilds the
+/// document from the original, so unchanged keys keep their order and new ones are appended.
+/// JSON keeps its indent width and TOML its comments and layout; YAML is re-serialized with
+/// serde_yaml, so files with comments are refused rather than stripped. The error explains
+/// why the line diff has to be used instead.
+pub fn merge(language: &str, original: &str, proposed: &str) -> Result<(String, Vec<Change>), String> {
+    let (old, new) = match language {
+        "json" => (parse_json(original)?, parse_json(proposed)?),
+        "yaml" => (parse_yaml(original)?, parse_yaml(proposed)?),
+        "toml" => (parse_toml(original)?, parse_toml(proposed)?),
+        _ => return Err(format!("{} files have no structured merge", language)),
+    };
+    let mut changes = Vec::new();
+    diff("", &old, &new, &mut changes);
+
+    let merged = match language {
+        "json" => merge_json(original, old, &new)?,
+        "yaml" => merge_yaml(original, old, &new)?,
+        _ => merge_toml(original, proposed)?,
+    };
+    Ok((merged, changes))
+}
+
+/// e.g. "+1 -0 ~2 keys".
+pub fn summary(changes: &[Change]) -> String {
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for change in changes {
+        match change {
+            Change::Added { .. } => added += 1,
+            Change::Removed { .. } => removed += 1,
+            Change::Changed { .. } => changed += 1,
+        }
+    }
+    format!("+{} -{} ~{} key{}", added, removed, changed, if added + removed + changed == 1 { "" } else { "s" })
+}
+
+/// The change as `path = value` or `path: old -> new`, without the marker.
+pub fn describe(change: &Change) -> String {
+    match change {
+        Change::Added { path, value } | Change::Removed { path, value } => format!("{} = {}", path, render(value)),
+        Change::Changed { path, old, new } => format!("{}: {} -> {}", path, render(old), render(new)),
+    }
+}
+
+// serde_yaml's `Value` keeps mapping order, so JSON and TOML are read into it as well.
+fn parse_json(content: &str) -> Result<Value, String> {
+    serde_json::from_str(content).map_err(|e| format!("it doesn't parse as JSON ({})", e))
+}
+
+fn parse_yaml(content: &str) -> Result<Value, String> {
+    serde_yaml::from_str(content).map_err(|e| format!("it doesn't parse as a single YAML document ({})", e))
+}
+
+fn parse_toml(content: &str) -> Result<Value, String> {
+    toml::from_str(content).map_err(|e| format!("it doesn't parse as TOML ({})", e.message()))
+}
+
+fn diff(path: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) => {
+            for (key, value) in old {
+                let path = join(path, key);
+                match new.get(key) {
+                    Some(new) => diff(&path, value, new, changes),
+                    None => changes.push(Change::Removed { path, value: value.clone() }),
+                }
+            }
+            for (key, value) in new {
+                if !old.contains_key(key) {
+                    changes.push(Change::Added { path: join(path, key), value: value.clone() });
+                }
+            }
+        }
+        // Element-wise only while the length is unchanged; otherwise indices don't line up
+        (Value::Sequence(old), Value::Sequence(new)) if old.len() == new.len() => {
+            for (i, (old, new)) in old.iter().zip(new).enumerate() {
+                diff(&format!("{}[{}]", path, i), old, new, changes);
+            }
+        }
+        _ if old != new => changes.push(Change::Changed { path: path.to_string(), old: old.clone(), new: new.clone() }),
+        _ => {}
+    }
+}
+
+fn join(path: &str, key: &Value) -> String {
+    let key = match key {
+        Value::String(key) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') => key.clone(),
+        key => render(key),
+    };
+    if path.is_empty() { key } else { format!("{}.{}", path, key) }
+}
+
+fn render(value: &Value) -> String {
+    // toml smuggles datetimes through serde as a single-key map
+    if let Value::Mapping(map) = value {
+        if let (1, Some(Value::String(datetime))) = (map.len(), map.get("$__toml_private_datetime")) {
+            return datetime.clone();
+        }
+    }
+    let rendered = serde_json::to_string(value)
+        .unwrap_or_else(|_| serde_yaml::to_string(value).unwrap_or_default().trim().to_string());
+    if rendered.chars().count() > 80 {
+        format!("{}...", rendered.chars().take(77).collect::<String>())
+    } else {
+        rendered
+    }
+}
+
+// Applies `new` onto `old`: keys missing from `new` are dropped, matching keys are merged
+// recursively and new keys go at the end, so the original order survives.
+fn merge_value(old: &mut Value, new: &Value) {
+    match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) => merge_mapping(old, new),
+        (old, new) => {
+            if old != new {
+                *old = new.clone();
+            }
+        }
+    }
+}
+
+fn merge_mapping(old: &mut Mapping, new: &Mapping) {
+    old.retain(|key, _| new.contains_key(key));
+    for (key, value) in new {
+        match old.get_mut(key) {
+            Some(existing) => merge_value(existing, value),
+            None => {
+                old.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn merge_json(original: &str, mut old: Value, new: &Value) -> Result<String, String> {
+    merge_value(&mut old, new);
+    let indent = json_indent(original);
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    serde::Serialize::serialize(&old, &mut serializer).map_err(|e| e.to_string())?;
+    let mut merged = String::from_utf8(out).map_err(|e| e.to_string())?;
+    if original.ends_with('\n') {
+        merged.push('\n');
+    }
+    Ok(merged)
+}
+
+// The indentation of the first indented line, or two spaces.
+fn json_indent(content: &str) -> String {
+    content.lines()
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty())
+        .unwrap_or("  ")
+        .to_string()
+}
+
+fn merge_yaml(original: &str, mut old: Value, new: &Value) -> Result<String, String> {
+    if original.lines().any(|line| line.trim_start().starts_with('#')) {
+        return Err("re-serializing the YAML would drop its comments".to_string());
+    }
+    merge_value(&mut old, new);
+    let merged = serde_yaml::to_string(&old).map_err(|e| e.to_string())?;
+    Ok(if original.starts_with("---") { format!("---\n{}", merged) } else { merged })
+}
+
+// TOML goes through toml_edit so comments, whitespace and table layout are kept.
+fn merge_toml(original: &str, proposed: &str) -> Result<String, String> {
+    let mut old: toml_edit::DocumentMut = original.parse().map_err(|e| format!("it doesn't parse as TOML ({})", e))?;
+    let new: toml_edit::DocumentMut = proposed.parse().map_err(|e| format!("it doesn't parse as TOML ({})", e))?;
+    merge_table(old.as_table_mut(), new.as_table());
+    Ok(old.to_string())
+}
+
+fn merge_table(old: &mut dyn toml_edit::TableLike, new: &dyn toml_edit::TableLike) {
+    let removed: Vec<String> = old.iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new.contains_key(key))
+        .collect();
+    for key in removed {
+        old.remove(&key);
+    }
+    for (key, item) in new.iter() {
+        match old.get_mut(key) {
+            Some(existing) => merge_item(existing, item),
+            None => {
+                old.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut toml_edit::Item, new: &toml_edit::Item) {
+    let same_kind = (old.is_table() && new.is_table()) || (old.is_inline_table() && new.is_inline_table());
+    if same_kind {
+        if let (Some(old), Some(new)) = (old.as_table_like_mut(), new.as_table_like()) {
+            merge_table(old, new);
+            return;
+        }
+    }
+    if let (Some(old), Some(new)) = (old.as_array_of_tables_mut(), new.as_array_of_tables()) {
+        if old.len() == new.len() {
+            for (old, new) in old.iter_mut().zip(new.iter()) {
+                merge_table(old, new);
+            }
+            return;
+        }
+    }
+    match (old.as_value_mut(), new.as_value()) {
+        // Keep the original's spacing and trailing comment around a changed value
+        (Some(old), Some(new)) => {
+            if bare(old) != bare(new) {
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+        }
+        _ => *old = new.clone(),
+    }
+}
+
+fn bare(value: &toml_edit::Value) -> String {
+    let mut value = value.clone();
+    value.decor_mut().clear();
+    value.to_string()
+}
@@ -0,0 +1,232 @@
+use serde::Deserialize;
+
+/// A problem found while parsing, with a 1-based line number when the parser reports one.
+pub struct SyntaxError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Parse-only validation of a whole file. JSON, YAML, TOML and Rust go through a real
+/// parser; the other brace languages and Python only get a delimiter balance check that
+/// skips strings and comments. Returns `None` for languages that aren't checked.
+pub fn check(language: &str, content: &str) -> Option<Vec<SyntaxError>> {
+    let errors = match language {
+        "json" => check_json(content),
+        "yaml" => check_yaml(content),
+        "toml" => check_toml(content),
+        "rust" => check_rust(content),
+        "python" => check_delimiters(content, Comments::Hash),
+        "javascript" | "typescript" | "go" | "java" | "c" | "c++" | "c#" | "kotlin" | "swift" | "scala" => {
+            check_delimiters(content, Comments::Slash)
+        }
+        _ => return None,
+    };
+    Some(errors)
+}
+
+fn check_json(content: &str) -> Vec<SyntaxError> {
+    match serde_json::from_str::<serde_json::Value>(content) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![SyntaxError { line: Some(e.line()), message: e.to_string() }],
+    }
+}
+
+fn check_yaml(content: &str) -> Vec<SyntaxError> {
+    // After an error the document iterator doesn't advance, so stop at the first one
+    serde_yaml::Deserializer::from_str(content)
+        .find_map(|document| serde_yaml::Value::deserialize(document).err())
+        .map(|e| SyntaxError { line: e.location().map(|location| location.line()), message: e.to_string() })
+        .into_iter()
+        .collect()
+}
+
+fn check_toml(content: &str) -> Vec<SyntaxError> {
+    match content.parse::<toml::Table>() {
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            let line = e.span().map(|span| line_of(content, span.start));
+            vec![SyntaxError { line, message: e.message().to_string() }]
+        }
+    }
+}
+
+fn check_rust(content: &str) -> Vec<SyntaxError> {
+    match syn::parse_file(content) {
+        Ok(_) => Vec::new(),
+        Err(e) => e.into_iter()
+            .map(|e| SyntaxError { line: Some(e.span().start().line), message: e.to_string() })
+            .collect(),
+    }
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comments {
+    /// `//` and `/* */`
+    Slash,
+    /// `#`, with Python's triple-quoted strings
+    Hash,
+}
+
+fn check_delimiters(content: &str, comments: Comments) -> Vec<SyntaxError> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut open: Vec<(char, usize)> = Vec::new();
+    let mut errors = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\n' => line += 1,
+            // Outside strings a backslash only appears in regex literals and line continuations
+            '\\' => i += 1,
+            '/' if comments == Comments::Slash && next == Some('/') => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if comments == Comments::Slash && next == Some('*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    if chars[i] == '\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '#' if comments == Comments::Hash => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            '"' | '\'' | '`' => {
+                let triple = comments == Comments::Hash && next == Some(c) && chars.get(i + 2) == Some(&c);
+                let start_line = line;
+                i += if triple { 3 } else { 1 };
+                let mut closed = false;
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => i += 1,
+                        '\n' if !triple && c != '`' => break,
+                        '\n' => line += 1,
+                        ch if ch == c && (!triple || (chars.get(i + 1) == Some(&c) && chars.get(i + 2) == Some(&c))) => {
+                            if triple {
+                                i += 2;
+                            }
+                            closed = true;
+                            break;
+                        }
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                if !closed {
+                    errors.push(SyntaxError { line: Some(start_line), message: format!("unterminated string starting with {}", c) });
+                    // Let the newline that ended the string still be counted
+                    continue;
+                }
+            }
+            '(' | '[' | '{' => open.push((c, line)),
+            ')' | ']' | '}' => match open.pop() {
+                Some((opening, _)) if closing_for(opening) == c => {}
+                Some((opening, opened)) => {
+                    errors.push(SyntaxError { line: Some(line), message: format!("`{}` does not close `{}` from line {}", c, opening, opened) });
+                }
+                None => errors.push(SyntaxError { line: Some(line), message: format!("unmatched `{}`", c) }),
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+
+    for (opening, opened) in open {
+        errors.push(SyntaxError { line: Some(opened), message: format!("`{}` is never closed", opening) });
+    }
+    errors
+}
+
+fn closing_for(opening: char) -> char {
+    match opening {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::smart_merge;
+
+    // Merges `answer` into `original` and checks the result, as `report_syntax_errors` does.
+    fn merged_errors(language: &str, original: &str, answer: &str) -> Vec<Option<usize>> {
+        assert!(check(language, original).unwrap().is_empty(), "the {} original parses", language);
+        let (merged, _) = smart_merge(original, answer);
+        check(language, &merged).unwrap().iter().map(|error| error.line).collect()
+    }
+
+    #[test]
+    fn a_merge_that_drops_a_brace_breaks_rust() {
+        let original = "fn add(a: i32, b: i32) -> i32 {\n    a - b\n}\n\nfn main() {}";
+        let answer = "fn add(a: i32, b: i32) -> i32 {\n    a + b\n\nfn main() {}";
+
+        assert!(!merged_errors("rust", original, answer).is_empty());
+    }
+
+    #[test]
+    fn a_merge_with_a_stray_comma_breaks_json_at_its_line() {
+        let original = "{\n  \"name\": \"coders\",\n  \"version\": 1\n}";
+        let answer = "{\n  \"name\": \"coders\",\n  \"version\": 2,\n}";
+
+        assert_eq!(merged_errors("json", original, answer), vec![Some(4)]);
+    }
+
+    #[test]
+    fn a_merge_that_breaks_yaml_indentation_is_caught() {
+        let original = "server:\n  host: localhost\n  port: 8080";
+        let answer = "server:\n  host: localhost\n port: 8081";
+
+        assert_eq!(merged_errors("yaml", original, answer).len(), 1);
+    }
+
+    #[test]
+    fn a_merge_with_an_unclosed_toml_table_is_caught_at_its_line() {
+        let original = "[package]\nname = \"coders\"\nversion = \"0.1.0\"";
+        let answer = "[package\nname = \"coders\"\nversion = \"0.2.0\"";
+
+        assert_eq!(merged_errors("toml", original, answer), vec![Some(1)]);
+    }
+
+    #[test]
+    fn a_mismatched_delimiter_is_reported_where_it_closes() {
+        let original = "function add(a, b) {\n  return a + b;\n}";
+        let answer = "function add(a, b) {\n  return (a + b;\n}";
+
+        assert_eq!(merged_errors("javascript", original, answer), vec![Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn an_unterminated_python_string_is_reported() {
+        let original = "def greet():\n    return 'hello'";
+        let answer = "def greet():\n    return 'hello";
+
+        assert_eq!(merged_errors("python", original, answer), vec![Some(2)]);
+    }
+
+    #[test]
+    fn delimiters_in_strings_and_comments_are_ignored() {
+        assert!(check("go", "// a ( in a comment\nvar s = \"{\" /* ] */").unwrap().is_empty());
+        assert!(check("python", "# (\ns = \"\"\"\n{\n\"\"\"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_languages_are_not_checked() {
+        assert!(check("markdown", "(((").is_none());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::paths;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    task: Vec<RawTask>,
+}
+
+// One `[[task]]` as written in the manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTask {
+    file: String,
+    #[serde(default)]
+    prompt: Option<String>,
+    #[serde(default)]
+    preset: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    auto_apply: bool,
+}
+
+/// One `[[task]]` of a `coders run` manifest, with its glob expanded and its preset resolved.
+pub struct Entry {
+    /// 1-based position in the manifest.
+    pub number: usize,
+    /// `file` as written: a path or a glob.
+    pub pattern: String,
+    pub files: Vec<String>,
+    pub prompt: String,
+    pub preset: Option<String>,
+    /// Model name or alias; the run's model when missing.
+    pub model: Option<String>,
+    /// Write accepted changes without asking.
+    pub auto_apply: bool,
+    /// Identifies the entry across runs for `--resume`; editing the entry changes it.
+    pub key: String,
+}
+
+/// Reads a `coders run` manifest. Every task needs a `file` (a path, or a glob such as
+/// `src/**/*.rs`) and either a `prompt` or a `preset` from the config's `[presets]`. Paths are
+/// relative to the current directory. A glob that matches nothing, or any other mistake, is
+/// an error before anything is sent.
+pub fn read(path: &Path, presets: &HashMap<String, String>) -> Result<Vec<Entry>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let manifest: Manifest = toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+    if manifest.task.is_empty() {
+        return Err(anyhow::anyhow!("{} has no [[task]] entries", path.display()));
+    }
+
+    let mut keys: HashMap<String, usize> = HashMap::new();
+    let mut entries = Vec::new();
+    for (i, task) in manifest.task.into_iter().enumerate() {
+        let number = i + 1;
+        let fail = |message: String| anyhow::anyhow!("{}: task {}: {}", path.display(), number, message);
+        let prompt = match (&task.prompt, &task.preset) {
+            (Some(_), Some(_)) => return Err(fail("give either a prompt or a preset, not both".to_string())),
+            (None, None) => return Err(fail("needs a prompt or a preset".to_string())),
+            (Some(prompt), None) => prompt.clone(),
+            (None, Some(name)) => presets.get(name).cloned()
+                .ok_or_else(|| fail(format!("there is no preset named '{}' in the config file", name)))?,
+        };
+        if prompt.trim().is_empty() {
+            return Err(fail("the prompt is empty".to_string()));
+        }
+        let files = expand(&task.file).map_err(fail)?;
+
+        let key = sha256(&json!([task.file, prompt, task.model, task.auto_apply]).to_string())[..16].to_string();
+        if let Some(previous) = keys.insert(key.clone(), number) {
+            return Err(fail(format!("repeats task {}", previous)));
+        }
+        entries.push(Entry {
+            number,
+            pattern: task.file,
+            files,
+            prompt,
+            preset: task.preset,
+            model: task.model,
+            auto_apply: task.auto_apply,
+            key,
+        });
+    }
+    Ok(entries)
+}
+
+// A plain path must name a file; one with `*`, `?` or `[` is a glob and must match at least
+// one file. Matches come back in path order.
+fn expand(pattern: &str) -> Result<Vec<String>, String> {
+    if !pattern.contains(['*', '?', '[']) {
+        if !Path::new(pattern).is_file() {
+            return Err(format!("{} is not a file", pattern));
+        }
+        return Ok(vec![pattern.to_string()]);
+    }
+    let files: Vec<String> = glob::glob(pattern)
+        .map_err(|e| format!("invalid glob '{}': {}", pattern, e))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    if files.is_empty() {
+        return Err(format!("{} matches no files", pattern));
+    }
+    Ok(files)
+}
+
+/// `--resume` bookkeeping: the keys of the entries completed by runs of one manifest, kept
+/// in `runs/<hash of the manifest's path>.json` in the state directory.
+pub struct State {
+    path: PathBuf,
+    done: HashSet<String>,
+}
+
+impl State {
+    /// With `resume`, what earlier runs of `manifest` completed; otherwise a fresh start that
+    /// forgets them.
+    pub fn open(manifest: &Path, resume: bool) -> Result<State> {
+        let manifest = fs::canonicalize(manifest)
+            .with_context(|| format!("Failed to read {}", manifest.display()))?;
+        let name = &sha256(&manifest.to_string_lossy())[..16];
+        let path = paths::state_dir()?.join("runs").join(format!("{}.json", name));
+        let done = match fs::read_to_string(&path) {
+            Ok(contents) if resume => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid run state {}", path.display()))?,
+            Ok(_) => {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+                HashSet::new()
+            }
+            Err(_) => HashSet::new(),
+        };
+        Ok(State { path, done })
+    }
+
+    pub fn is_done(&self, key: &str) -> bool {
+        self.done.contains(key)
+    }
+
+    /// Records `key` as completed, right away so an interrupted run keeps it.
+    pub fn complete(&mut self, key: &str) -> Result<()> {
+        self.done.insert(key.to_string());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&self.done)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Applied,
+    /// The model proposed nothing new, or nothing could be applied (e.g. a dry run).
+    NoChanges,
+    /// A proposal was shown and turned down.
+    Declined,
+    Failed,
+    /// Completed by an earlier run and passed over by `--resume`.
+    Skipped,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Applied => "applied",
+            Status::NoChanges => "no-changes",
+            Status::Declined => "declined",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+/// How one entry of the run ended.
+pub struct Outcome {
+    pub status: Status,
+    /// The change summary of every file written.
+    pub changes: Vec<(String, String)>,
+    pub error: Option<String>,
+}
+
+/// `coders run --report`: one JSON object per entry, written as soon as the entry ends so an
+/// interrupted run still leaves a record. A resumed run appends to the existing report.
+pub struct Report {
+    path: PathBuf,
+    file: File,
+}
+
+impl Report {
+    pub fn create(path: &Path, append: bool) -> Result<Report> {
+        let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Report { path: path.to_path_buf(), file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&mut self, entry: &Entry, model: &str, outcome: &Outcome) -> Result<()> {
+        let line = json!({
+            "task": entry.number,
+            "key": entry.key,
+            "file": entry.pattern,
+            "preset": entry.preset,
+            "prompt": entry.preset.is_none().then_some(&entry.prompt),
+            "model": model,
+            "auto_apply": entry.auto_apply,
+            "status": outcome.status.as_str(),
+            "changes": outcome.changes.iter().map(|(file, summary)| json!({"file": file, "summary": summary})).collect::<Vec<_>>(),
+            "error": outcome.error,
+        });
+        writeln!(self.file, "{}", line)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+fn sha256(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
@@ -0,0 +1,1200 @@
+use crate::apply;
+use crate::audit;
+use crate::azure;
+use crate::batch;
+use crate::catalog;
+use crate::check;
+use crate::cli::{get_or_prompt_for_api_key, Cli};
+use crate::config;
+use crate::edit::{self, edit_context, fenced_blocks, get_file_language, with_retries, Endpoint, GenerationOptions, Retries};
+use crate::error::CodersError;
+use crate::export;
+use crate::git;
+use crate::http;
+use crate::memory;
+use crate::merge::{combine, match_indentation, replace, smart_merge, ChangeType, EditMode};
+use crate::moves;
+use crate::patch;
+use crate::profile;
+use crate::prompt;
+use crate::prompts::VERIFY_INSTRUCTION;
+use crate::render::{append_diff, change_summary, line_number_width, moves_summary, render_change, render_hunks, render_moves, render_structured_change, DiffFormat};
+use crate::repo_map;
+use crate::routing;
+use crate::scope;
+use crate::secrets;
+use crate::slim;
+use crate::structured;
+use crate::syntax;
+use crate::taskfile;
+use crate::undo;
+use crate::Provider;
+use anyhow::{Context, Result};
+use colored::*;
+use futures_util::future::join_all;
+use tokio::sync::Semaphore;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// The share of --deadline the model gets before --fallback-model takes over.
+const FALLBACK_AFTER: f64 = 0.5;
+
+pub async fn process_files(session: &Session<'_>, tasks: &[Task]) -> Result<()> {
+    let cli = session.cli;
+    if tasks.len() == 1 && cli.batch.is_none() {
+        let task = &tasks[0];
+        // The summary of the change that was written
+        let result = async {
+            let Some(write) = process_file(session, task).await? else {
+                return Ok(None);
+            };
+            let writes = [write];
+            if !confirm_write_locations(&writes, cli.yes, cli.on_no_tty)? {
+                return Ok(None);
+            }
+            undo::record(&writes)?;
+            apply::apply_all(&writes)?;
+            audit_applied(session, &[&task.file], &writes)?;
+            say!("Changes applied successfully.");
+            let [write] = writes;
+            Ok(Some(write.summary))
+        }.await;
+        if cli.quiet {
+            let outcome = batch::Outcome {
+                file: task.file.clone(),
+                model: task.model.clone(),
+                prompt: task.prompt.clone(),
+                result: result.as_ref().map(Option::clone).map_err(|e| format!("{:#}", e)),
+                usage: batch::Usage::default(),
+                stats: session.stats.borrow_mut().remove(&task.file).unwrap_or_default(),
+            };
+            batch::print_quiet(&[outcome], true);
+        }
+        return result.map(|_| ());
+    }
+
+    let reviewed = review_tasks(session, tasks).await?;
+    let written = write_reviewed(session, &reviewed, cli.yes);
+    // Entries that share a file share its usage too; the first of them gets all of it
+    let outcomes: Vec<batch::Outcome> = reviewed.outcomes.iter()
+        .map(|(i, result)| batch::Outcome {
+            file: tasks[*i].file.clone(),
+            model: tasks[*i].model.clone(),
+            prompt: tasks[*i].prompt.clone(),
+            result: result.clone(),
+            usage: session.usage.borrow_mut().remove(&tasks[*i].file).unwrap_or_default(),
+            stats: session.stats.borrow_mut().remove(&tasks[*i].file).unwrap_or_default(),
+        })
+        .collect();
+    if cli.quiet {
+        batch::print_quiet(&outcomes, matches!(written, Ok(true)));
+    } else {
+        batch::print_summary(&outcomes, matches!(written, Ok(true)), &model_prices(session).await, session.format == DiffFormat::Json);
+    }
+    written.map(|_| ())
+}
+
+// Only OpenRouter's live model list has prices. It is left alone when no other request
+// should be made, and a failure to fetch it just leaves the cost out.
+pub async fn model_prices(session: &Session<'_>) -> batch::Prices {
+    if session.provider != Provider::OpenRouter || !session.http.is_live() || session.cli.offline_validate {
+        return batch::Prices::new();
+    }
+    let models = catalog::load(&session.http, session.provider, &session.base_url, &session.api_key, session.cli.refresh).await.unwrap_or_default();
+    models.into_iter()
+        .filter_map(|model| Some(((session.provider.id().to_string(), model.id), model.pricing?)))
+        .collect()
+}
+
+// Every task's proposal, reviewed. Outcomes are in task order: the accepted change's summary,
+// `None` when nothing was accepted, or the error.
+pub struct Reviewed<'t> {
+    pub outcomes: Vec<(usize, Result<Option<String>, String>)>,
+    pub writes: Vec<apply::PendingWrite>,
+    pub written_files: Vec<&'t str>,
+    pub failed: usize,
+}
+
+// Files are prepared one at a time, since that can ask questions, and then requested up to
+// --jobs at a time. The answers are reviewed in order once they have all arrived. Only an
+// abort is an error; every other failure is an outcome.
+pub async fn review_tasks<'t>(session: &Session<'_>, tasks: &'t [Task]) -> Result<Reviewed<'t>> {
+    let cli = session.cli;
+    let mut failed = 0;
+    let mut outcomes = Vec::new();
+    let mut prepared = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        match prepare_file(session, task) {
+            Ok(file) => prepared.push((i, file)),
+            Err(e) if matches!(e.downcast_ref(), Some(CodersError::UserAbort)) => {
+                say!("Batch aborted; no changes were written.");
+                return Err(e);
+            }
+            Err(e) => {
+                eprintln!("Error processing {}: {:#}", task.file, e);
+                outcomes.push((i, Err(format!("{:#}", e))));
+                failed += 1;
+            }
+        }
+    }
+
+    // Each --show-context preview asks before its request, so they go out one at a time
+    let jobs = if cli.show_context { 1 } else { cli.jobs };
+    say!("\nRequesting changes to {} file(s), up to {} at a time...", prepared.len(), jobs);
+    let slots = Semaphore::new(jobs as usize);
+    let responses = join_all(prepared.iter().map(|(_, prepared)| async {
+        // A request waiting out a rate-limit retry keeps its slot, so the cap still holds
+        let _slot = slots.acquire().await?;
+        request_completion(session, prepared).await
+    })).await;
+
+    // Collect every confirmed edit first so the batch can be written all-or-nothing
+    let mut writes = Vec::new();
+    let mut written_files = Vec::new();
+    for ((i, prepared), response) in prepared.into_iter().zip(responses) {
+        let file = prepared.file.clone();
+        say!("\n==> {}", file);
+        let result = match response {
+            Ok(response) => review_file(session, prepared, response).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(Some(write)) => {
+                outcomes.push((i, Ok(Some(write.summary.clone()))));
+                writes.push(write);
+                written_files.push(tasks[i].file.as_str());
+            }
+            Ok(None) => outcomes.push((i, Ok(None))),
+            Err(e) if matches!(e.downcast_ref(), Some(CodersError::UserAbort)) => {
+                say!("Batch aborted; no changes were written.");
+                return Err(e);
+            }
+            Err(e) => {
+                eprintln!("Error processing {}: {:#}", file, e);
+                outcomes.push((i, Err(format!("{:#}", e))));
+                failed += 1;
+            }
+        }
+    }
+    outcomes.sort_by_key(|(i, _)| *i);
+    Ok(Reviewed { outcomes, writes, written_files, failed })
+}
+
+// Writes the accepted changes all-or-nothing: none when a file failed or two changes target
+// the same file. Returns whether they were written.
+pub fn write_reviewed(session: &Session<'_>, reviewed: &Reviewed, yes: bool) -> Result<bool> {
+    let writes = &reviewed.writes;
+    // Entries that share a file were each answered against its original content
+    let mut targets = HashSet::new();
+    if let Some(write) = writes.iter().find(|write| !targets.insert(apply::resolved_path(&write.path))) {
+        say!("\nMore than one accepted change targets {}; no changes were written.", write.path);
+        return Ok(false);
+    }
+
+    if reviewed.failed > 0 {
+        say!("\n{} file(s) failed; no changes were written.", reviewed.failed);
+        return Ok(false);
+    }
+    if writes.is_empty() {
+        say!("\nNo changes to apply.");
+        return Ok(false);
+    }
+
+    if !confirm_write_locations(writes, yes, session.cli.on_no_tty)? {
+        return Ok(false);
+    }
+    undo::record(writes)?;
+    apply::apply_all(writes)?;
+    audit_applied(session, &reviewed.written_files, writes)?;
+    say!("Changes applied successfully.");
+    Ok(true)
+}
+
+// `coders run`: the manifest's tasks in order, each reviewed and written on its own like a
+// small batch, and added to the report as soon as it ends. A failed task stops the run only
+// with --fail-fast, but the run fails either way.
+pub async fn run_manifest(session: &Session<'_>, manifest: &Path, runs: &[(taskfile::Entry, Vec<Task>)], report_path: &Path, resume: bool, fail_fast: bool) -> Result<()> {
+    let mut state = taskfile::State::open(manifest, resume)?;
+    let mut report = taskfile::Report::create(report_path, resume)?;
+    let mut statuses = Vec::new();
+    for (entry, tasks) in runs {
+        let model = &tasks[0].model;
+        if state.is_done(&entry.key) {
+            say!("\nTask {} ({}) was completed by an earlier run; skipping.", entry.number, entry.pattern);
+            let outcome = taskfile::Outcome { status: taskfile::Status::Skipped, changes: Vec::new(), error: None };
+            report.add(entry, model, &outcome)?;
+            statuses.push(outcome.status);
+            continue;
+        }
+
+        say!("\n=== Task {} of {}: {} ({} file(s))", entry.number, runs.len(), entry.pattern, tasks.len());
+        session.proposals.borrow_mut().clear();
+        let reviewed = review_tasks(session, tasks).await?;
+        let errors: Vec<String> = reviewed.outcomes.iter()
+            .filter_map(|(i, result)| Some(format!("{}: {}", tasks[*i].file, result.as_ref().err()?)))
+            .collect();
+        let written = if errors.is_empty() {
+            write_reviewed(session, &reviewed, session.cli.yes || entry.auto_apply)
+        } else {
+            Ok(false)
+        };
+        let outcome = match written {
+            Ok(true) => taskfile::Outcome {
+                status: taskfile::Status::Applied,
+                changes: reviewed.written_files.iter().zip(&reviewed.writes).map(|(file, write)| (file.to_string(), write.summary.clone())).collect(),
+                error: None,
+            },
+            Ok(false) if !errors.is_empty() => taskfile::Outcome { status: taskfile::Status::Failed, changes: Vec::new(), error: Some(errors.join("; ")) },
+            Ok(false) => {
+                let proposed = !reviewed.writes.is_empty() || session.proposals.borrow().iter().any(|(_, changed)| *changed);
+                let status = if proposed { taskfile::Status::Declined } else { taskfile::Status::NoChanges };
+                taskfile::Outcome { status, changes: Vec::new(), error: None }
+            }
+            Err(e) => {
+                eprintln!("Error writing the changes of task {}: {:#}", entry.number, e);
+                taskfile::Outcome { status: taskfile::Status::Failed, changes: Vec::new(), error: Some(format!("{:#}", e)) }
+            }
+        };
+        if session.cli.quiet {
+            let outcomes: Vec<batch::Outcome> = reviewed.outcomes.iter()
+                .map(|(i, result)| batch::Outcome {
+                    file: tasks[*i].file.clone(),
+                    model: tasks[*i].model.clone(),
+                    prompt: tasks[*i].prompt.clone(),
+                    result: result.clone(),
+                    usage: batch::Usage::default(),
+                    stats: session.stats.borrow_mut().remove(&tasks[*i].file).unwrap_or_default(),
+                })
+                .collect();
+            batch::print_quiet(&outcomes, outcome.status == taskfile::Status::Applied);
+        }
+        report.add(entry, model, &outcome)?;
+        statuses.push(outcome.status);
+        if outcome.status == taskfile::Status::Failed {
+            if fail_fast {
+                say!("Stopping after the failed task (--fail-fast).");
+                break;
+            }
+        } else {
+            state.complete(&entry.key)?;
+        }
+    }
+
+    let count = |wanted: taskfile::Status| statuses.iter().filter(|status| **status == wanted).count();
+    let failed = count(taskfile::Status::Failed);
+    say!(
+        "\nRun summary: {} applied, {} without changes, {} declined, {} failed, {} skipped, {} not run. Results are in {}.",
+        count(taskfile::Status::Applied), count(taskfile::Status::NoChanges), count(taskfile::Status::Declined),
+        failed, count(taskfile::Status::Skipped), runs.len() - statuses.len(), report.path().display()
+    );
+    let mut usage = batch::Usage::default();
+    for file_usage in session.usage.borrow().values() {
+        usage.merge(file_usage);
+    }
+    if !session.cli.quiet {
+        say!("Total: {}", batch::describe_total(&usage, &model_prices(session).await));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} task(s) failed; rerun with --resume to retry only those", failed, runs.len()));
+    }
+    Ok(())
+}
+
+// --fail-on-change and --fail-on-no-change, once every file has been reviewed. A file that got
+// no proposal to look at fails either gate, since nothing can be said about it.
+pub fn check_gate(session: &Session<'_>, files: usize) -> Result<()> {
+    let cli = session.cli;
+    if !cli.fail_on_change && !cli.fail_on_no_change {
+        return Ok(());
+    }
+    let proposals = session.proposals.borrow();
+    if proposals.len() < files {
+        return Err(CodersError::Gate(format!("{} of {} file(s) got no proposal to check", files - proposals.len(), files)).into());
+    }
+    let changed: Vec<&str> = proposals.iter().filter(|(_, changed)| *changed).map(|(file, _)| file.as_str()).collect();
+    if cli.fail_on_change && !changed.is_empty() {
+        return Err(CodersError::Gate(format!("changes were proposed for {}", changed.join(", "))).into());
+    }
+    if cli.fail_on_no_change && changed.is_empty() {
+        return Err(CodersError::Gate("no changes were proposed".to_string()).into());
+    }
+    Ok(())
+}
+
+// --verify-model as `provider:model` or an alias with a provider. The run's own provider
+// shares its endpoint and key; another one gets its key the usual way.
+pub async fn resolve_verifier(cli: &Cli, config: &config::Config, http: &http::Transport, name: &str, provider: Provider, base_url: &str, api_key: &str) -> Result<Verifier> {
+    let (verifier, model) = routing::target(config, name)
+        .map_err(|e| CodersError::Config(format!("--verify-model: {}", e)))?;
+    if verifier == Provider::Azure {
+        return Err(CodersError::Config("--verify-model can't use Azure, which routes by deployment rather than by model".to_string()).into());
+    }
+    let (base_url, api_key) = if verifier == provider {
+        (base_url.to_string(), api_key.to_string())
+    } else {
+        // --base-url is meant for the run's provider only
+        let base_url = config.endpoints.get(verifier.id()).map_or(verifier.default_base_url(), String::as_str).trim_end_matches('/').to_string();
+        let api_key = if http.is_replay() {
+            String::new()
+        } else {
+            get_or_prompt_for_api_key(http, verifier.name(), &base_url, None, !cli.offline_validate, !cli.no_save_key).await?
+        };
+        (base_url, api_key)
+    };
+
+    // The verdict is read whole, as plain text
+    let mut options = GenerationOptions::from_cli(cli, config, verifier);
+    options.stream = false;
+    options.tools = false;
+    options.request_confidence = false;
+    options.request_rationale = false;
+    options.concise = true;
+    options.image = None;
+    options.messages = None;
+    Ok(Verifier { provider: verifier, model, base_url, api_key, options })
+}
+
+// Sends the unified diff of the draft to the --verify-model. Returns the corrected file when
+// the verifier answered with a fix that applies, and `None` to keep the draft: on APPROVE,
+// and also when the verifier fails, so that the run goes on as if there were none.
+pub async fn verify_proposal(session: &Session<'_>, verifier: &Verifier, file: &str, original: &str, proposed: &str) -> Option<String> {
+    let diff = similar::TextDiff::from_lines(original, proposed)
+        .unified_diff()
+        .header(file, file)
+        .to_string();
+    let context = format!("{}\n\n```diff\n{}```", VERIFY_INSTRUCTION, diff);
+    say!("Asking {} {} to verify the proposal...", verifier.provider.name(), verifier.model);
+    let answer = match send_recorded(session, &verifier.endpoint(), &verifier.model, &context, file).await {
+        Ok(Some(answer)) if !answer.trim().is_empty() => answer,
+        Ok(_) => {
+            say!("The verifier sent no answer; keeping the draft.");
+            return None;
+        }
+        Err(e) => {
+            say!("Verification failed ({:#}); keeping the draft.", e);
+            return None;
+        }
+    };
+    say!("Verification: ~{} tokens sent, ~{} received", slim::estimate_tokens(&context), slim::estimate_tokens(&answer));
+
+    if answer.trim_start().to_uppercase().starts_with("APPROVE") {
+        say!("Verifier: APPROVE");
+        return None;
+    }
+    let blocks = fenced_blocks(&answer);
+    let patch = blocks.iter()
+        .find(|(info, _)| matches!(*info, "diff" | "patch"))
+        .or(blocks.first())
+        .map_or(answer.as_str(), |(_, code)| code.as_str());
+    match patch::apply(original, patch) {
+        Ok(corrected) => {
+            say!("Verifier: corrected the proposal; showing its version instead.");
+            Some(corrected)
+        }
+        Err(reason) => {
+            say!("Verifier: sent a correction that can't be applied ({}); keeping the draft.", reason);
+            None
+        }
+    }
+}
+
+// `files[i]` is the file whose change became `writes[i]`.
+pub fn audit_applied(session: &Session<'_>, files: &[&str], writes: &[apply::PendingWrite]) -> Result<()> {
+    let Some(audit) = &session.audit else {
+        return Ok(());
+    };
+    for (file, write) in files.iter().zip(writes) {
+        audit.applied(file, &write.path, &write.content)?;
+    }
+    Ok(())
+}
+
+// Shows the absolute path of every file about to be written and, unless --yes was given,
+// asks once more before anything touches the disk.
+pub fn confirm_write_locations(writes: &[apply::PendingWrite], yes: bool, on_no_tty: prompt::NoTty) -> Result<bool> {
+    say!("\nWriting changes to {} file(s):", writes.len());
+    for write in writes {
+        say!("  {}  ({})", apply::resolved_path(&write.path).display(), write.summary);
+    }
+    if yes {
+        return Ok(true);
+    }
+
+    if prompt::confirm_change("Write to these locations?", on_no_tty)? {
+        Ok(true)
+    } else {
+        say!("No changes were written.");
+        Ok(false)
+    }
+}
+
+// --verify-model: a second model that reviews the diff of every proposal.
+pub struct Verifier {
+    pub provider: Provider,
+    pub model: String,
+    pub base_url: String,
+    pub api_key: String,
+    pub options: GenerationOptions,
+}
+
+impl Verifier {
+    pub fn endpoint(&self) -> Endpoint<'_> {
+        Endpoint { provider: self.provider, base_url: &self.base_url, api_key: &self.api_key, azure: None, options: &self.options }
+    }
+}
+
+// Everything that stays the same across the files processed in one run.
+pub struct Session<'a> {
+    pub cli: &'a Cli,
+    pub provider: Provider,
+    pub http: http::Transport,
+    pub base_url: String,
+    pub api_key: String,
+    pub options: GenerationOptions,
+    pub retry_on_empty: u32,
+    pub retry_on_malformed: u32,
+    pub azure: Option<azure::Deployment>,
+    pub format: DiffFormat,
+    pub strip_phrases: Vec<String>,
+    pub report: Option<RefCell<export::HtmlReport>>,
+    pub feedback_loop: bool,
+    pub profile: profile::Profile,
+    pub audit: Option<audit::Audit>,
+    // Every file a proposal was reviewed for, and whether it changed anything, for
+    // --fail-on-change, --fail-on-no-change and `coders run`
+    pub proposals: RefCell<Vec<(String, bool)>>,
+    // The estimated tokens each file's requests used, for the batch summary
+    pub usage: RefCell<HashMap<String, batch::Usage>>,
+    // Each file's last proposal and request time, for --quiet
+    pub stats: RefCell<HashMap<String, batch::Stats>>,
+    pub secrets: secrets::Scanner,
+    // The placeholders --scrub-secrets sends for each file's secrets
+    pub redactions: RefCell<HashMap<String, secrets::Redactions>>,
+    pub fallback_model: Option<String>,
+    // --example files: where they are, as given and resolved, and their content
+    pub examples: Vec<(String, PathBuf, String)>,
+    pub verifier: Option<Verifier>,
+}
+
+impl Session<'_> {
+    pub fn endpoint(&self) -> Endpoint<'_> {
+        Endpoint { provider: self.provider, base_url: &self.base_url, api_key: &self.api_key, azure: self.azure.as_ref(), options: &self.options }
+    }
+
+    fn extraction<'a>(&'a self, file: &str) -> edit::Extraction<'a> {
+        edit::Extraction {
+            language: get_file_language(file),
+            strip_phrases: &self.strip_phrases,
+            rationale: self.cli.explain,
+            verbatim: self.options.tools || self.cli.keep_fences,
+            confidence: self.options.request_confidence,
+        }
+    }
+}
+
+// One file to edit, with the instruction and model used for it. `--batch` entries and
+// `coders run` tasks give the files of a run different ones.
+#[derive(Clone)]
+pub struct Task {
+    pub file: String,
+    pub prompt: String,
+    pub model: String,
+    // `auto_apply` of a `coders run` task: accept the proposal without asking
+    pub auto_apply: bool,
+}
+
+// A file read and turned into a request, waiting for the provider's response.
+pub struct PreparedFile {
+    pub file: String,
+    pub model: String,
+    pub content: String,
+    pub target: String,
+    // The input file's, for `--preserve-permissions`
+    pub permissions: Option<fs::Permissions>,
+    pub dry_run: bool,
+    pub auto_apply: bool,
+    pub slim: Option<slim::SlimSource>,
+    // The task's prompt, for `--with-memory`
+    pub prompt: String,
+    // What `--strict-scope` lets the proposal change
+    pub scope: Option<scope::Scope>,
+    pub context: String,
+}
+
+pub async fn process_file(session: &Session<'_>, task: &Task) -> Result<Option<apply::PendingWrite>> {
+    let prepared = prepare_file(session, task)?;
+    let response = request_completion(session, &prepared).await?;
+    review_file(session, prepared, response).await
+}
+
+// Reads the file and assembles the request; asks for another target if it can't be written.
+pub fn prepare_file(session: &Session<'_>, task: &Task) -> Result<PreparedFile> {
+    let cli = session.cli;
+    let file = task.file.as_str();
+    let started = Instant::now();
+    // A file that doesn't exist yet, or has nothing in it, is written from scratch
+    let (file_content, creating) = match fs::read_to_string(file) {
+        Ok(content) if cli.create && !content.trim().is_empty() => {
+            return Err(CodersError::Config(format!("{} already has content; drop --create to edit it", file)).into());
+        }
+        Ok(content) => {
+            let creating = content.trim().is_empty();
+            (content, creating)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            say!("{} doesn't exist yet; it will be created.", file);
+            (String::new(), true)
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to read file: {}", file))),
+    };
+    let permissions = if cli.preserve_permissions && !creating {
+        Some(fs::metadata(file).with_context(|| format!("Failed to read file: {}", file))?.permissions())
+    } else {
+        None
+    };
+    let language = get_file_language(file);
+    let scope = if cli.lines.is_some() || cli.strict_scope {
+        scope::Scope::resolve(cli.lines, &task.prompt, &file_content, language).map_err(|e| CodersError::Config(format!("{}: {}", file, e)))?
+    } else {
+        None
+    };
+    if cli.strict_scope && scope.is_none() {
+        return Err(CodersError::Config(format!("--strict-scope needs --lines, or a prompt that names a function or type defined in {}", file)).into());
+    }
+    let over_limit = file_content.len() as u64 > cli.max_file_size;
+    let trimmed = if over_limit && (cli.trim_context || cli.slim_context) && slim::supports(language) {
+        Some(slim::SlimSource::new(&file_content, language))
+            .filter(|trimmed| trimmed.text.len() as u64 <= cli.max_file_size)
+    } else {
+        None
+    };
+    if over_limit && trimmed.is_none() {
+        let hint = if cli.trim_context || cli.slim_context {
+            "Even the trimmed copy doesn't fit. Split the file into smaller pieces"
+        } else {
+            "Split the file into smaller pieces, try --trim-context"
+        };
+        return Err(CodersError::Budget(format!(
+            "{} is {} bytes, over the --max-file-size limit of {} bytes. {}, or raise the limit with --max-file-size if you really mean to send it.",
+            file, file_content.len(), cli.max_file_size, hint
+        )).into());
+    }
+
+    // The question about an unwritable target is left out of the profile
+    let assembly = started.elapsed();
+
+    // Find out now rather than after paying for the request
+    let mut target = cli.output.clone().unwrap_or_else(|| file.to_string());
+    let mut dry_run = false;
+    if let Some(reason) = apply::check_writable(Path::new(&target)) {
+        say!("Warning: {}.", reason);
+        match prompt_for_unwritable_target()? {
+            Some(output) => target = output,
+            None => dry_run = true,
+        }
+    }
+
+    let started = Instant::now();
+    let slim = if trimmed.is_some() || cli.slim_context {
+        let slim = trimmed.unwrap_or_else(|| slim::SlimSource::new(&file_content, language));
+        let before = slim::estimate_tokens(&file_content);
+        let after = slim::estimate_tokens(&slim.text);
+        if over_limit {
+            say!("{} is over --max-file-size; sending a trimmed copy with comments and repeated blank lines removed (edit quality may suffer).", file);
+        }
+        say!("Slim context: ~{} tokens saved ({} -> {})", before.saturating_sub(after), before, after);
+        Some(slim)
+    } else {
+        None
+    };
+    let sent_content = slim.as_ref().map_or(file_content.as_str(), |slim| slim.text.as_str());
+
+    let mut context = if creating {
+        create_context(&task.prompt, file, language)
+    } else {
+        edit_context(&task.prompt, sent_content)
+    };
+    if let Some(commits) = cli.git_context {
+        let dir = Path::new(file).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        match git::recent_diff(dir, commits) {
+            Ok(Some(diff)) => {
+                context = format!("{}\n\nRecent changes in this repository:\n```diff\n{}```", context, diff);
+            }
+            Ok(None) => say!("No recent changes in the repository; skipping --git-context."),
+            Err(_) => say!("{} is not in a git repository; skipping --git-context.", file),
+        }
+    }
+    if let Some(examples) = style_examples(&session.examples, file) {
+        context = format!("{}\n\n{}", context, examples);
+    }
+    if cli.repo_map {
+        let map = repo_map::build_repo_map(file, &task.prompt)?;
+        if !map.is_empty() {
+            context = format!("Repository map (top-level symbols defined in other project files):\n{}\n{}", map, context);
+        }
+    }
+    if let Some(scope) = &scope {
+        context = format!("{}\n\n{}", context, scope.instruction());
+    }
+    if cli.with_memory {
+        if let Some(memory) = memory::context(file)? {
+            context = format!("{}\n\n{}", context, memory);
+        }
+    }
+    if cli.mode == EditMode::Append {
+        context.push_str("\n\nReturn only the new code to add at the end of the file, without repeating any of the existing code.");
+    }
+    // A partial answer would read as every other key being removed
+    if cli.structured_merge && structured::supports(language) {
+        context.push_str("\n\nReturn the complete file, not only the changed part.");
+    }
+    session.profile.add("prompt assembly", assembly + started.elapsed());
+
+    let found = session.secrets.scan(&context);
+    if !found.is_empty() {
+        say!("\nWarning: what would be sent for {} looks like it contains secrets:", file);
+        for finding in &found {
+            say!("  {}: {}", finding.kind, finding.masked.trim());
+        }
+        if cli.scrub_secrets {
+            let redactions = secrets::Redactions::new(&found);
+            context = redactions.scrub(&context);
+            say!("Sending them as <REDACTED-n> placeholders (--scrub-secrets).");
+            session.redactions.borrow_mut().insert(file.to_string(), redactions);
+        } else {
+            confirm_sending_secrets(session.provider.name(), file)?;
+        }
+    }
+
+    Ok(PreparedFile { file: file.to_string(), model: task.model.clone(), content: file_content, target, permissions, dry_run, auto_apply: task.auto_apply, slim, prompt: task.prompt.clone(), scope: scope.filter(|_| cli.strict_scope), context })
+}
+
+// Read once up front, so a missing example fails the run before anything is asked or sent.
+pub fn read_examples(paths: &[PathBuf]) -> Result<Vec<(String, PathBuf, String)>> {
+    paths.iter()
+        .map(|path| {
+            let content = fs::read_to_string(path).with_context(|| format!("Failed to read --example {}", path.display()))?;
+            Ok((path.display().to_string(), apply::resolved_path(&path.to_string_lossy()), content))
+        })
+        .collect()
+}
+
+// The --example files, fenced and labelled so they read as references rather than more
+// files to edit. An example that is the file being edited is left out.
+pub fn style_examples(examples: &[(String, PathBuf, String)], file: &str) -> Option<String> {
+    let target = apply::resolved_path(file);
+    let blocks: Vec<String> = examples.iter()
+        .filter(|(_, resolved, _)| *resolved != target)
+        .map(|(path, _, content)| {
+            let fence = if content.contains("```") { "````" } else { "```" };
+            format!("Example: {}\n{}{}\n{}\n{}", path, fence, get_file_language(path), content.trim_end(), fence)
+        })
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Reference files from the same project, showing how it does things. Follow their conventions, but don't edit them or include them in your answer; only the file above is being changed.\n\n{}",
+        blocks.join("\n\n")
+    ))
+}
+
+// The user message for a file being created: there is no content to send, so the model is
+// told what kind of file to write instead.
+pub fn create_context(prompt: &str, file: &str, language: &str) -> String {
+    let kind = if language == "plaintext" { "file".to_string() } else { format!("{} file", language) };
+    format!("{}\n\nThis is a new {} at {} with no content yet. Write the complete file.", prompt, kind, file)
+}
+
+pub async fn request_completion(session: &Session<'_>, prepared: &PreparedFile) -> Result<Option<String>> {
+    let endpoint = session.endpoint();
+    let retries = Retries { empty: session.retry_on_empty, malformed: session.retry_on_malformed, verbose: session.cli.verbose, deadline: session.cli.deadline.map(Duration::from_secs) };
+    if let (Some(fallback), Some(deadline)) = (&session.fallback_model, retries.deadline) {
+        return request_with_fallback(session, prepared, retries, fallback, deadline).await;
+    }
+    with_retries(session.provider, retries, || send_recorded(session, &endpoint, &prepared.model, &prepared.context, &prepared.file)).await
+}
+
+// --fallback-model: a request that hasn't started answering by FALLBACK_AFTER of the
+// --deadline is cancelled, and the fallback model gets what is left of it. The first request
+// is dropped before the second is sent, so only one of them can answer.
+pub async fn request_with_fallback(session: &Session<'_>, prepared: &PreparedFile, retries: Retries, fallback: &str, deadline: Duration) -> Result<Option<String>> {
+    let endpoint = session.endpoint();
+    let started = Instant::now();
+    let cutoff = deadline.mul_f64(FALLBACK_AFTER);
+    session.http.take_answering();
+    let mut first = Box::pin(with_retries(session.provider, retries, || send_recorded(session, &endpoint, &prepared.model, &prepared.context, &prepared.file)));
+    let answered = tokio::select! {
+        result = &mut first => Some(result),
+        _ = tokio::time::sleep(cutoff) => None,
+    };
+    let (model, result) = match answered {
+        Some(result) => (prepared.model.as_str(), result),
+        // A streamed answer that has started is left to finish within the deadline
+        None if session.http.take_answering() => (prepared.model.as_str(), first.await),
+        None => {
+            drop(first);
+            say!("{} hasn't answered after {:.1}s; cancelling it and asking {} instead.", prepared.model, cutoff.as_secs_f64(), fallback);
+            let retries = Retries { deadline: Some(deadline.saturating_sub(started.elapsed())), ..retries };
+            session.stats.borrow_mut().entry(prepared.file.clone()).or_default().fallback = Some(fallback.to_string());
+            (fallback, with_retries(session.provider, retries, || send_recorded(session, &endpoint, fallback, &prepared.context, &prepared.file)).await)
+        }
+    };
+    if result.is_ok() {
+        say!("Answered by {} after {:.1}s of the {}s --deadline.", model, started.elapsed().as_secs_f64(), deadline.as_secs());
+    }
+    result
+}
+
+// Shows the proposal, with the --check repairs and feedback rounds, and returns the write
+// the user accepted.
+pub async fn review_file(session: &Session<'_>, prepared: PreparedFile, response: Option<String>) -> Result<Option<apply::PendingWrite>> {
+    let cli = session.cli;
+    let options = &session.options;
+    let PreparedFile { file, model, content: file_content, target, permissions, dry_run, auto_apply, slim, prompt, scope, context } = prepared;
+    let file = file.as_str();
+    match response {
+        Some(content) => {
+            if !options.stream {
+                say!("API Response:\n{}", content);
+            }
+            let syntax_language = (!cli.no_syntax_check).then(|| get_file_language(file));
+            let mut content = content;
+            // What the proposal being reviewed answers, for --with-memory
+            let mut asked = prompt;
+            // Each rejected proposal can be answered with feedback, which asks for a revision
+            let accepted = loop {
+                let edit::Extracted { code: proposal, confidence, rationale } = session.profile.time("parsing", || edit::extract(&content, &session.extraction(file)));
+                let allow_apply = confidence_allows_apply(cli.min_confidence, confidence) && !dry_run && !cli.no_apply_prompt;
+                let mut extracted_code = match_indentation(file, &file_content, &proposal, true);
+                if let Some(check) = &cli.check {
+                    extracted_code = repair_until_checked(session, &model, file, &file_content, slim.as_ref(), &context, check, extracted_code).await?;
+                }
+                // Appended code is all new, so there is nothing of the slimmed file to restore
+                if let Some(slim) = slim.as_ref().filter(|_| cli.edit_mode() != EditMode::Append) {
+                    extracted_code = session.profile.time("parsing", || slim.restore(&file_content, &extracted_code));
+                }
+                let (mut merged, mut changes) = session.profile.time("diff", || combine(cli.edit_mode(), &file_content, &extracted_code));
+                // The verifier's fix is a whole file, which replaces the merged draft as it is
+                let mut corrected = None;
+                if let Some(verifier) = session.verifier.as_ref().filter(|_| !changes.is_empty()) {
+                    if let Some(fixed) = verify_proposal(session, verifier, file, &file_content, &merged).await {
+                        (merged, changes) = session.profile.time("diff", || replace(&file_content, &fixed));
+                        corrected = Some(fixed);
+                    }
+                }
+                // Changes outside --strict-scope are held back for a second confirmation
+                let mut outside = None;
+                if let Some(scope) = &scope {
+                    if let Some((inside, hunks)) = scope::split(&file_content, &merged, scope) {
+                        changes = replace(&file_content, &inside).1;
+                        outside = Some((std::mem::replace(&mut merged, inside), hunks));
+                    }
+                }
+                let width = line_number_width(&[&file_content, &merged]);
+                let mut diff: Vec<String> = changes.iter().map(|change| render_change(change, session.format, width)).collect();
+                let mut summary = change_summary(&changes);
+                let mut unchanged = changes.is_empty();
+                // Reordered code reads as every line of it changing; show the moves instead
+                if let Some(entries) = session.profile.time("diff", || moves::classify(&file_content, &merged)).filter(|_| !unchanged) {
+                    diff = render_moves(&entries, &file_content, &merged, session.format, width, cli.expand_moves);
+                    summary = moves_summary(&entries);
+                }
+                if cli.structured_merge && structured::supports(get_file_language(file)) && !unchanged {
+                    match session.profile.time("diff", || structured::merge(get_file_language(file), &file_content, corrected.as_deref().unwrap_or(&extracted_code))) {
+                        Ok((document, keys)) => {
+                            merged = document;
+                            diff = keys.iter().map(|change| render_structured_change(change, session.format)).collect();
+                            summary = structured::summary(&keys);
+                            unchanged = keys.is_empty();
+                        }
+                        Err(reason) => say!("Structured merge of {} not possible: {}. Showing the line diff instead.", file, reason),
+                    }
+                }
+                say!("\nproposed: {} ({})", summary, file);
+                if let (Some(scope), Some((_, hunks))) = (&scope, &outside) {
+                    say!("\nLeft out, outside {} (--strict-scope):", scope.label);
+                    for line in render_hunks(hunks, session.format, width) {
+                        say!("{}", line);
+                    }
+                }
+                if let Some(report) = &session.report {
+                    let section = export::Section { file: file.to_string(), summary: summary.clone(), original: file_content.clone(), proposed: merged.clone() };
+                    let mut report = report.borrow_mut();
+                    report.add(section)?;
+                    say!("Diff exported to {}", report.path().display());
+                }
+                if let Some(path) = &cli.diff_out {
+                    append_diff(path, file, &summary, &diff)?;
+                }
+                session.proposals.borrow_mut().push((file.to_string(), !unchanged));
+                {
+                    let mut stats = session.stats.borrow_mut();
+                    let stats = stats.entry(file.to_string()).or_default();
+                    stats.inserted = changes.iter().filter(|change| matches!(change.change_type, ChangeType::Insert)).count();
+                    stats.deleted = changes.iter().filter(|change| matches!(change.change_type, ChangeType::Delete)).count();
+                    stats.modified = changes.iter().filter(|change| matches!(change.change_type, ChangeType::Modify)).count();
+                }
+                if cli.fail_on_change || cli.fail_on_no_change {
+                    return Ok(None);
+                }
+                if unchanged {
+                    return Ok(None);
+                }
+                let unparseable = if cli.force { None } else { unparseable_result(get_file_language(file), &file_content, &merged) };
+                let allow_apply = allow_apply && unparseable.is_none();
+                let excerpt: Vec<String> = if cli.with_memory && allow_apply {
+                    changes.iter().map(|change| render_change(change, DiffFormat::Plain, width)).collect()
+                } else {
+                    Vec::new()
+                };
+                let mut accepted = show_diff_and_prompt_for_changes(&file_content, merged, &diff, allow_apply, session.format, rationale.as_deref(), syntax_language, cli.confirm_each_file, auto_apply || (cli.quiet && cli.yes), cli.on_no_tty)?;
+                if let (Some(scope), Some((unscoped, hunks)), Some(_)) = (&scope, outside, &accepted) {
+                    if confirm_outside_scope(hunks.len(), &scope.label)? {
+                        summary = change_summary(&replace(&file_content, &unscoped).1);
+                        accepted = Some(unscoped);
+                    }
+                }
+                // Only what the developer decided on: a dry run or a skipped prompt says nothing
+                if cli.with_memory && allow_apply {
+                    if let Err(e) = memory::record(file, memory::Entry::new(&asked, &summary, &excerpt, accepted.is_some())) {
+                        eprintln!("Warning: could not update the memory of {}: {:#}", file, e);
+                    }
+                }
+                if let Some(error) = unparseable {
+                    return Err(CodersError::Unparseable(format!("{} would no longer parse ({}); rerun with --force to write it anyway", file, error)).into());
+                }
+                if accepted.is_some() || !allow_apply || cli.confirm_each_file || !session.feedback_loop {
+                    // With --check the exit code reports how the run ended, so declining counts as an abort
+                    if cli.check.is_some() && !cli.confirm_each_file && allow_apply && accepted.is_none() {
+                        return Err(CodersError::UserAbort.into());
+                    }
+                    break accepted.map(|content| (content, summary));
+                }
+
+                // With nobody at a terminal to answer, there is no feedback either
+                let feedback = prompt::answer("Send feedback to the model? (enter text or press Enter to quit): ")?.unwrap_or_default();
+                if feedback.is_empty() || feedback.eq_ignore_ascii_case("q") {
+                    if cli.check.is_some() {
+                        return Err(CodersError::UserAbort.into());
+                    }
+                    break None;
+                }
+                let feedback_context = format!(
+                    "{}\n\nYour previous answer was:\n```\n{}\n```\n\nThe developer rejected it with this feedback:\n{}\n\nReturn the complete revised file.",
+                    context, proposal, feedback
+                );
+                asked = feedback;
+                match send_request(session, &model, &feedback_context, file).await?.filter(|content| !content.trim().is_empty()) {
+                    Some(revised) => {
+                        if !options.stream {
+                            say!("API Response:\n{}", revised);
+                        }
+                        content = revised;
+                    }
+                    None => {
+                        say!("No valid response received from the API.");
+                        break None;
+                    }
+                }
+            };
+            Ok(accepted.map(|(content, summary)| apply::PendingWrite { path: target, content, summary, permissions }))
+        }
+        None => {
+            say!("No valid response received from the API.");
+            Ok(None)
+        }
+    }
+}
+
+// The second question --strict-scope asks after the changes in scope were accepted. Nobody
+// at a terminal to answer it means no.
+pub fn confirm_outside_scope(count: usize, label: &str) -> Result<bool> {
+    let Some(answer) = prompt::answer(&format!("\nAlso apply the {} change(s) outside {} listed above? [y/N] ", count, label))? else {
+        say!("No terminal to confirm on; leaving out the changes outside {}.", label);
+        return Ok(false);
+    };
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Possible secrets go out only after a yes; with nobody to answer, they don't go out at all.
+pub fn confirm_sending_secrets(provider: &str, file: &str) -> Result<()> {
+    let Some(answer) = prompt::answer(&format!("Send them to {} anyway? [y/N] ", provider))? else {
+        return Err(CodersError::NoTerminal(format!(
+            "{} looks like it contains secrets, and there is no terminal to confirm sending them on; pass --scrub-secrets to send placeholders instead",
+            file
+        )).into());
+    };
+    if matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+        return Ok(());
+    }
+    say!("Nothing was sent. --scrub-secrets sends placeholders in their place.");
+    Err(CodersError::UserAbort.into())
+}
+
+// Returns another path to write to, or `None` to continue as a dry run.
+pub fn prompt_for_unwritable_target() -> Result<Option<String>> {
+    loop {
+        match prompt::ask("Continue as a (d)ry run, write to an (o)ther path, or (q)uit? ")?.to_lowercase().as_str() {
+            "d" => return Ok(None),
+            "o" => {
+                let path = prompt::ask("Output path: ")?;
+                match apply::check_writable(Path::new(&path)) {
+                    _ if path.is_empty() => {}
+                    Some(reason) => println!("Warning: {}.", reason),
+                    None => return Ok(Some(path)),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Runs --check on the proposal and, while it fails, sends the failure back to the model
+// together with its previous answer, up to --max-repair-attempts times. Stops early when the
+// model repeats a proposal it already made.
+#[allow(clippy::too_many_arguments)]
+pub async fn repair_until_checked(session: &Session<'_>, model: &str, file: &str, original: &str, slim: Option<&slim::SlimSource>, context: &str, check: &str, proposal: String) -> Result<String> {
+    let cli = session.cli;
+    let restore = |code: &str| match slim.filter(|_| cli.edit_mode() != EditMode::Append) {
+        Some(slim) => slim.restore(original, code),
+        None => code.to_string(),
+    };
+
+    let mut proposal = proposal;
+    let mut merged = combine(cli.edit_mode(), original, &restore(&proposal)).0;
+    let mut seen = HashSet::from([content_hash(&merged)]);
+    let mut result = check::run_check(check, file, &merged)?;
+
+    for attempt in 1..=cli.max_repair_attempts {
+        if result.passed {
+            break;
+        }
+        say!("\nCheck failed:\n{}", result.output);
+        say!("Asking {} for a fix (attempt {}/{})", session.provider.name(), attempt, cli.max_repair_attempts);
+
+        let repair_context = format!(
+            "{}\n\nYour previous answer was:\n```\n{}\n```\n\nRunning `{}` on it failed with:\n```\n{}\n```\nReturn the complete corrected file.",
+            context, proposal, check, result.output
+        );
+        let Some(response) = send_request(session, model, &repair_context, file).await?.filter(|r| !r.trim().is_empty()) else {
+            say!("No response received; stopping the repair loop.");
+            break;
+        };
+        let revised = edit::extract(&response, &session.extraction(file)).code;
+        let revised_merged = combine(cli.edit_mode(), original, &restore(&match_indentation(file, original, &revised, false))).0;
+        if !seen.insert(content_hash(&revised_merged)) {
+            say!("The model proposed the same code again; stopping the repair loop.");
+            break;
+        }
+
+        say!("\nChanges since the previous attempt:");
+        let width = line_number_width(&[&merged, &revised_merged]);
+        for change in &smart_merge(&merged, &revised_merged).1 {
+            say!("{}", render_change(change, session.format, width));
+        }
+        proposal = revised;
+        merged = revised_merged;
+        result = check::run_check(check, file, &merged)?;
+    }
+
+    if result.passed {
+        say!("Check passed.");
+        Ok(proposal)
+    } else {
+        Err(CodersError::CheckFailed(format!("`{}` still fails on the proposed changes for {}:\n{}", check, file, result.output)).into())
+    }
+}
+
+pub fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+// A follow-up request (a feedback round or a --check repair), which only gets the
+// malformed-response retries.
+pub async fn send_request(session: &Session<'_>, model: &str, context: &str, file: &str) -> Result<Option<String>> {
+    let endpoint = session.endpoint();
+    let retries = Retries { empty: 0, malformed: session.retry_on_malformed, verbose: session.cli.verbose, deadline: session.cli.deadline.map(Duration::from_secs) };
+    with_retries(session.provider, retries, || send_recorded(session, &endpoint, model, context, file)).await
+}
+
+// The session's bookkeeping around `edit::send`: the --profile network time, and each
+// file's latency and estimated tokens for the batch summary and --quiet.
+pub async fn send_recorded(session: &Session<'_>, endpoint: &Endpoint<'_>, model: &str, context: &str, file: &str) -> Result<Option<String>> {
+    let redactions = session.redactions.borrow().get(file).cloned();
+    let recording = edit::Recording { file, audit: session.audit.as_ref(), redactions: redactions.as_ref(), verbose: session.cli.verbose, history: true };
+    let started = Instant::now();
+    let result = edit::send(&session.http, endpoint, model, context, get_file_language(file), &recording).await;
+    session.profile.add("network", started.elapsed());
+    session.stats.borrow_mut().entry(file.to_string()).or_default().latency += started.elapsed();
+    let completion_tokens = result.as_ref().ok().and_then(Option::as_deref).map_or(0, slim::estimate_tokens);
+    session.usage.borrow_mut().entry(file.to_string()).or_default()
+        .add(endpoint.provider.id(), model, slim::estimate_tokens(context), completion_tokens);
+    result
+}
+
+// Returns the merged content when the user accepts the changes. With `per_file` the prompt
+// is apply/skip/abort, and aborting surfaces as `CodersError::UserAbort` to stop the batch.
+// With `auto_apply` (a task's, or `--quiet --yes`) nothing is asked and the changes are accepted.
+#[allow(clippy::too_many_arguments)]
+pub fn show_diff_and_prompt_for_changes(original: &str, updated_content: String, diff: &[String], allow_apply: bool, format: DiffFormat, rationale: Option<&str>, syntax_language: Option<&str>, per_file: bool, auto_apply: bool, on_no_tty: prompt::NoTty) -> Result<Option<String>> {
+    say!("\nProposed changes:");
+    say!("------------------");
+
+    for line in diff {
+        say!("{}", line);
+    }
+
+    if let Some(rationale) = rationale {
+        say!("\nRationale:");
+        say!("----------");
+        say!("{}", rationale);
+    }
+
+    if let Some(language) = syntax_language {
+        report_syntax_errors(language, original, &updated_content, format);
+    }
+
+    if !allow_apply {
+        say!("\nChanges discarded.");
+        return Ok(None);
+    }
+
+    if auto_apply {
+        say!("\nAccepted automatically (auto_apply).");
+        return Ok(Some(updated_content));
+    }
+
+    if per_file {
+        loop {
+            let Some(answer) = prompt::answer("\nApply, skip this file, or abort the batch? (a/s/q)\n")? else {
+                return Ok(prompt::no_tty(on_no_tty)?.then_some(updated_content));
+            };
+            match answer.to_lowercase().as_str() {
+                "a" | "apply" => return Ok(Some(updated_content)),
+                "s" | "skip" => {
+                    say!("Skipped.");
+                    return Ok(None);
+                }
+                "q" | "abort" => return Err(CodersError::UserAbort.into()),
+                _ => say!("Please answer a, s or q."),
+            }
+        }
+    }
+
+    if prompt::confirm_change("\nDo you want to apply these changes?", on_no_tty)? {
+        Ok(Some(updated_content))
+    } else {
+        say!("Changes discarded.");
+        Ok(None)
+    }
+}
+
+// Advisory only: the changes can still be applied. Files that already fail the check before
+// the edit are skipped, since the errors can't be blamed on the merge.
+pub fn report_syntax_errors(language: &str, original: &str, merged: &str, format: DiffFormat) {
+    let Some(errors) = syntax::check(language, merged) else {
+        return;
+    };
+    if errors.is_empty() || !syntax::check(language, original).unwrap_or_default().is_empty() {
+        return;
+    }
+
+    let lines: Vec<String> = errors.iter()
+        .filter_map(|error| error.line)
+        .map(|line| line.to_string())
+        .collect();
+    let mut summary = format!("\nMerged result has {} syntax error(s)", errors.len());
+    if !lines.is_empty() {
+        summary.push_str(&format!(" at line(s) {}", lines.join(", ")));
+    }
+    match format {
+        DiffFormat::Color => say!("{}", summary.red()),
+        DiffFormat::Plain | DiffFormat::Json | DiffFormat::Ascii => say!("{}", summary),
+    }
+    for error in errors.iter().take(5) {
+        match error.line {
+            Some(line) => say!("  line {}: {}", line, error.message),
+            None => say!("  {}", error.message),
+        }
+    }
+}
+
+// Unlike `report_syntax_errors`, this blocks the write: JSON, TOML and YAML parse exactly in
+// process, so an edit that breaks them is refused. Files that didn't parse before are let through.
+pub fn unparseable_result(language: &str, original: &str, merged: &str) -> Option<String> {
+    if !matches!(language, "json" | "toml" | "yaml") {
+        return None;
+    }
+    let errors = syntax::check(language, merged)?;
+    let error = errors.first()?;
+    if !syntax::check(language, original).unwrap_or_default().is_empty() {
+        return None;
+    }
+    Some(match error.line {
+        Some(line) => format!("line {}: {}", line, error.message),
+        None => error.message.clone(),
+    })
+}
+
+// Reports the score against --min-confidence; without the flag every proposal may be applied.
+pub fn confidence_allows_apply(min_confidence: Option<f32>, confidence: Option<f32>) -> bool {
+    match (min_confidence, confidence) {
+        (Some(min), Some(confidence)) => {
+            say!("Model confidence: {:.2}", confidence);
+            if confidence < min {
+                say!("Confidence {:.2} is below the --min-confidence threshold of {:.2}; changes will not be applied.", confidence, min);
+            }
+            confidence >= min
+        }
+        (Some(_), None) => {
+            say!("The model did not report a confidence score; changes will not be applied.");
+            false
+        }
+        (None, _) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_merge_that_breaks_json_is_refused() {
+        let original = "{\n  \"name\": \"coders\",\n  \"version\": 1\n}";
+        let (merged, _) = smart_merge(original, "{\n  \"name\": \"coders\",\n  \"version\": 2,\n}");
+
+        assert!(unparseable_result("json", original, &merged).is_some_and(|error| error.starts_with("line 4: ")));
+    }
+
+    #[test]
+    fn a_merge_that_keeps_json_valid_is_let_through() {
+        let original = "{\n  \"version\": 1\n}";
+        let (merged, _) = smart_merge(original, "{\n  \"version\": 2\n}");
+
+        assert_eq!(unparseable_result("json", original, &merged), None);
+    }
+
+    #[test]
+    fn a_config_that_was_already_broken_is_let_through() {
+        let original = "server:\n  host: localhost\n port: 8080";
+        let (merged, _) = smart_merge(original, "server:\n  host: example.com\n port: 8080");
+
+        assert_eq!(unparseable_result("yaml", original, &merged), None);
+    }
+
+    #[test]
+    fn broken_code_is_only_reported() {
+        let original = "fn main() {\n    run();\n}";
+        let (merged, _) = smart_merge(original, "fn main() {\n    run(;\n}");
+
+        assert!(!syntax::check("rust", &merged).unwrap().is_empty());
+        assert_eq!(unparseable_result("rust", original, &merged), None);
+    }
+}
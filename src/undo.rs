@@ -0,0 +1,135 @@
+use crate::apply::{self, PendingWrite};
+use crate::history;
+use crate::paths;
+use crate::prompt;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Older entries and their backups are dropped as new ones are recorded.
+const MAX_ENTRIES: usize = 50;
+
+/// One applied write, newest last in `undo/log.json` in the state directory.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    timestamp: u64,
+    /// Absolute path of the file that was written.
+    path: PathBuf,
+    /// Copy of the previous content in the undo directory; `None` when the write created
+    /// the file, so undoing it deletes the file.
+    backup: Option<String>,
+    /// Hash of what was written, to notice edits made after the change was applied.
+    applied_hash: u64,
+}
+
+fn undo_dir() -> Result<PathBuf> {
+    Ok(paths::state_dir()?.join("undo"))
+}
+
+fn load_log(dir: &Path) -> Result<Vec<Entry>> {
+    let path = dir.join("log.json");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid undo log {}", path.display()))
+}
+
+fn save_log(dir: &Path, entries: &[Entry]) -> Result<()> {
+    let path = dir.join("log.json");
+    fs::write(&path, serde_json::to_string_pretty(entries)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Backs up the current content of every target before `apply::apply_all` replaces it.
+pub fn record(writes: &[PendingWrite]) -> Result<()> {
+    let dir = undo_dir()?;
+    fs::create_dir_all(&dir)?;
+    let mut entries = load_log(&dir)?;
+    let timestamp = history::now();
+
+    for (i, write) in writes.iter().enumerate() {
+        let path = apply::resolved_path(&write.path);
+        let backup = match fs::read(&path) {
+            Ok(previous) => {
+                let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                let backup = format!("{}-{}-{}-{}", timestamp, std::process::id(), i, name);
+                fs::write(dir.join(&backup), previous)
+                    .with_context(|| format!("Failed to back up {}", path.display()))?;
+                Some(backup)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to back up {}", path.display()))),
+        };
+        entries.push(Entry { timestamp, path, backup, applied_hash: fnv1a(write.content.as_bytes()) });
+    }
+
+    if entries.len() > MAX_ENTRIES {
+        for entry in entries.drain(..entries.len() - MAX_ENTRIES) {
+            if let Some(backup) = entry.backup {
+                let _ = fs::remove_file(dir.join(backup));
+            }
+        }
+    }
+    save_log(&dir, &entries)
+}
+
+/// `coders undo [FILE]`: restores the content `file` (or the most recently changed file)
+/// had before the last applied change. Repeating it steps further back.
+pub fn run_undo(file: Option<&str>) -> Result<()> {
+    let dir = undo_dir()?;
+    let mut entries = load_log(&dir)?;
+    let target = file.map(apply::resolved_path);
+    let Some(index) = entries.iter().rposition(|entry| target.as_ref().is_none_or(|target| &entry.path == target)) else {
+        match file {
+            Some(file) => println!("No applied change to {} to undo.", file),
+            None => println!("No applied change to undo."),
+        }
+        return Ok(());
+    };
+    let entry = &entries[index];
+
+    let current = fs::read(&entry.path).ok();
+    if current.as_deref().map(fnv1a) != Some(entry.applied_hash) {
+        let question = format!("{} has changed since coders wrote it; restoring will discard those edits. Continue? (y/n) ", entry.path.display());
+        if !prompt::confirm(&question)? {
+            println!("Nothing was restored.");
+            return Ok(());
+        }
+    }
+
+    let path = entry.path.to_string_lossy().into_owned();
+    match &entry.backup {
+        Some(backup) => {
+            let previous = fs::read_to_string(dir.join(backup))
+                .with_context(|| format!("The backup of {} is missing", path))?;
+            apply::apply_all(&[PendingWrite { path: path.clone(), content: previous, summary: String::new(), permissions: None }])?;
+            let _ = fs::remove_file(dir.join(backup));
+            println!("Restored {}", path);
+        }
+        None => {
+            if current.is_some() {
+                fs::remove_file(&entry.path)
+                    .with_context(|| format!("Failed to remove {}", path))?;
+            }
+            println!("Removed {}, which the undone change had created", path);
+        }
+    }
+
+    entries.remove(index);
+    save_log(&dir, &entries)
+}
+
+// Stable across Rust releases, unlike `DefaultHasher`, since the log outlives the binary.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,49 @@
+//! Where `coders` looks for its config file: `--config-dir`, then `CODERS_CONFIG_DIR`.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// `coders config` in an empty working directory, so no project config is found.
+fn show_config(dir: &TempDir) -> Command {
+    let mut command = Command::cargo_bin("coders").unwrap();
+    command.current_dir(dir.path()).env_remove("CODERS_CONFIG_DIR").arg("config");
+    command
+}
+
+fn config_dir(dir: &TempDir, name: &str, temperature: f32) -> PathBuf {
+    let path = dir.path().join(name);
+    fs::create_dir_all(&path).unwrap();
+    fs::write(path.join("config.toml"), format!("temperature = {}\n", temperature)).unwrap();
+    path
+}
+
+fn config_file_line(dir: &Path) -> String {
+    format!("config file:       {}", dir.join("config.toml").display())
+}
+
+#[test]
+fn the_flag_wins_over_the_variable() {
+    let dir = TempDir::new().unwrap();
+    let (flag, env) = (config_dir(&dir, "flag", 0.3), config_dir(&dir, "env", 0.1));
+
+    show_config(&dir).env("CODERS_CONFIG_DIR", &env).arg("--config-dir").arg(&flag)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(config_file_line(&flag)))
+        .stdout(predicate::str::contains("temperature:       0.3"));
+}
+
+#[test]
+fn the_variable_is_used_without_the_flag() {
+    let dir = TempDir::new().unwrap();
+    let env = config_dir(&dir, "env", 0.1);
+
+    show_config(&dir).env("CODERS_CONFIG_DIR", &env)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(config_file_line(&env)))
+        .stdout(predicate::str::contains("temperature:       0.1"));
+}
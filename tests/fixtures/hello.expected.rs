@@ -0,0 +1,3 @@
+fn main() {
+    println!("Hello, world!");
+}
\ No newline at end of file
@@ -0,0 +1,115 @@
+//! The whole CLI against the recorded provider sessions in `tests/fixtures/replay`, so no
+//! key or network is needed. Each recording answers one provider and prompt.
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// `hello.rs` edited with `prompt` by `provider`, written to a file in `dir`.
+fn edit_hello(dir: &TempDir, provider: &str, prompt: &str) -> (assert_cmd::assert::Assert, PathBuf) {
+    edit_hello_with(dir, provider, prompt, &["-y", "--on-no-tty", "apply"])
+}
+
+fn edit_hello_with(dir: &TempDir, provider: &str, prompt: &str, args: &[&str]) -> (assert_cmd::assert::Assert, PathBuf) {
+    let output = dir.path().join("hello.rs");
+    let assert = Command::cargo_bin("coders").unwrap()
+        .arg("--config-dir").arg(dir.path().join("config"))
+        .args(["--provider", provider, "--replay", "tests/fixtures/replay", "-f", "tests/fixtures/hello.rs"])
+        .arg("--output").arg(&output)
+        .args(args)
+        .write_stdin(format!("{}\n", prompt))
+        .assert();
+    (assert, output)
+}
+
+fn expected() -> String {
+    fs::read_to_string(Path::new("tests/fixtures/hello.expected.rs")).unwrap()
+}
+
+#[test]
+fn groq_answer_is_applied() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "groq", "Fix the syntax error");
+
+    assert.success().stdout(predicate::str::contains("Changes applied successfully."));
+    assert_eq!(fs::read_to_string(output).unwrap(), expected());
+}
+
+#[test]
+fn openrouter_answer_is_applied() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "openrouter", "Fix the syntax error");
+
+    assert.success().stdout(predicate::str::contains("Changes applied successfully."));
+    assert_eq!(fs::read_to_string(output).unwrap(), expected());
+}
+
+#[test]
+fn hyperbolic_chat_answer_is_applied() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "hyperbolic", "Fix the syntax error");
+
+    assert.success().stdout(predicate::str::contains("Changes applied successfully."));
+    assert_eq!(fs::read_to_string(output).unwrap(), expected());
+}
+
+// A base model answers with a text completion, and keeps going past the code block.
+#[test]
+fn hyperbolic_base_model_completion_is_applied() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello_with(&dir, "hyperbolic", "Fix the syntax error", &["--model-name", "meta-llama/Meta-Llama-3.1-405B", "-y", "--on-no-tty", "apply"]);
+
+    assert.success().stdout(predicate::str::contains("Changes applied successfully."));
+    assert_eq!(fs::read_to_string(output).unwrap(), expected());
+}
+
+// The verifier answers with a patch that also adds a comment; its file is written as it is,
+// final newline included.
+#[test]
+fn verifier_correction_is_written_whole() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello_with(&dir, "groq", "Fix the syntax error", &["--verify-model", "groq:llama-3.1-8b-instant", "-y", "--on-no-tty", "apply"]);
+
+    assert.success().stdout(predicate::str::contains("Verifier: corrected the proposal"));
+    assert_eq!(fs::read_to_string(output).unwrap(), format!("// Prints a greeting\n{}\n", expected()));
+}
+
+#[test]
+fn provider_error_exits_69_and_writes_nothing() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "groq", "Fix the syntax error, quickly");
+
+    assert.code(69).stderr(predicate::str::contains("Groq returned status 503"));
+    assert!(!output.exists());
+}
+
+#[test]
+fn truncated_response_is_retried_then_exits_76() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "openrouter", "Fix the syntax error, quickly");
+
+    assert.code(76)
+        .stdout(predicate::str::contains("retrying (2/2)"))
+        .stderr(predicate::str::contains("isn't valid JSON"));
+    assert!(!output.exists());
+}
+
+#[test]
+fn missing_recording_names_the_file_it_expected() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello(&dir, "groq", "Something nobody recorded");
+
+    assert.failure().stderr(predicate::str::contains("No recorded response for POST https://api.groq.com/openai/v1/chat/completions"));
+    assert!(!output.exists());
+}
+
+#[test]
+fn quiet_prints_one_tab_separated_line() {
+    let dir = TempDir::new().unwrap();
+    let (assert, output) = edit_hello_with(&dir, "groq", "Fix the syntax error", &["--quiet", "-y"]);
+
+    assert.success().stdout(predicate::str::is_match(r"^tests/fixtures/hello\.rs\tapplied\t\+0/-0/~1\tllama-3\.1-70b-versatile\t[0-9]+\n$").unwrap());
+    assert_eq!(fs::read_to_string(output).unwrap(), expected());
+}